@@ -1,58 +1,205 @@
+use crate::message::error_message;
 use core::game::{board::Board, piece::Piece};
+use core::stream::{Stream, UNIX_PREFIX};
+use core::tls::{TlsStream, Trust};
 use core::{io_err, read_str, write_str};
 use core::{request::Request, response::Response};
-use std::{io, net::TcpStream};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use std::{io, thread};
 
 pub struct Client {
-    stream: TcpStream,
+    stream: Stream,
+    // Shared by every clone so requests from different threads don't interleave.
+    writer: Arc<Mutex<Stream>>,
 }
 
-impl Client {
-    fn validate_address(address: &str) -> bool {
-        let &[address, port] = address.split(':').collect::<Vec<_>>().as_slice() else {
-            return false;
-        };
+/// What the server tells a client when it takes a seat.
+pub struct Seat {
+    pub board: Board,
+    pub piece: Piece,
+    pub turn: Piece,
+    pub hints: bool,
+    pub opponent: Option<String>,
+}
 
-        if port.parse::<u16>().unwrap_or_default() == 0 {
-            return false;
-        }
+/// What the server tells a spectator when it starts watching.
+pub struct View {
+    pub board: Board,
+    pub turn: Piece,
+    pub players: Vec<(Piece, String)>,
+}
 
-        if address.to_lowercase() == "localhost" {
-            return true;
+impl Client {
+    /// Resolves `host:port`, where the host is an IPv4 address, a bracketed IPv6 one or a
+    /// name looked up through DNS.
+    fn resolve(address: &str) -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<_> = address.to_socket_addrs()?.collect();
+        if addrs.is_empty() || addrs.iter().any(|addr| addr.port() == 0) {
+            return Err(io_err!("Invalid address"));
         }
 
-        address.split('.').flat_map(str::parse::<u8>).count() == 4
+        Ok(addrs)
     }
 
-    fn connect(stream: &mut TcpStream) -> io::Result<(Piece, Board)> {
-        let data = read_str(stream)?;
-        let res: Response = serde_json::from_str(&data)?;
-
-        match res {
-            Response::Init { piece, board } => Ok((piece, board)),
-            _ => Err(io_err!("Failed to connect")),
-        }
+    /// Host part of `host:port`, without the brackets of IPv6 addresses.
+    fn host(address: &str) -> &str {
+        let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+        host.strip_prefix('[')
+            .and_then(|host| host.strip_suffix(']'))
+            .unwrap_or(host)
     }
 
-    pub fn new(address: &str) -> Result<(Self, Board, Piece), &'static str> {
-        if !Self::validate_address(address) {
-            return Err("Invalid IP address");
+    /// Connects to `address`, over TLS if the client trusts some servers. Addresses like
+    /// `unix:/tmp/tictactoe.sock` are Unix sockets, which never use TLS.
+    fn open(address: &str, trust: Option<&Trust>) -> Result<Self, &'static str> {
+        if let Some(path) = address.strip_prefix(UNIX_PREFIX) {
+            if trust.is_some() {
+                return Err("Unix sockets don't use TLS");
+            }
+
+            let stream = Stream::unix(Path::new(path))
+                .map_err(|_| "Could not establish connection to server")?;
+            return Self::wrap(stream);
         }
 
-        let Ok(mut stream) = TcpStream::connect(address) else {
+        let Ok(addrs) = Self::resolve(address) else {
+            return Err("Invalid address");
+        };
+
+        let Ok(tcp) = TcpStream::connect(&addrs[..]) else {
             return Err("Could not establish connection to server");
         };
 
-        let Ok((piece, board)) = Self::connect(&mut stream) else {
+        let stream: Stream = match trust {
+            Some(trust) => {
+                let config = trust
+                    .client_config()
+                    .map_err(|_| "Invalid certificate authority")?;
+                TlsStream::connect(tcp, config, Self::host(address))
+                    .map_err(|_| "Could not establish a secure connection to server")?
+                    .into()
+            }
+
+            None => tcp.into(),
+        };
+
+        Self::wrap(stream)
+    }
+
+    fn wrap(stream: Stream) -> Result<Self, &'static str> {
+        let Ok(writer) = stream.try_clone() else {
             return Err("Failed to connect to server");
         };
 
-        Ok((Self { stream }, board, piece))
+        let writer = Arc::new(Mutex::new(writer));
+        Ok(Self { stream, writer })
+    }
+
+    /// Sends the first request of the connection and returns the server's answer.
+    fn handshake(&mut self, req: Request) -> Result<Response, &'static str> {
+        let res = self
+            .send_request(req)
+            .and_then(|_| self.recv_response())
+            .map_err(|_| "Failed to connect to server")?;
+
+        match res {
+            Response::Invalid(code) => Err(error_message(code)),
+            res => Ok(res),
+        }
+    }
+
+    /// Connects to `address` and takes a seat in `room` as `name`.
+    pub fn join(
+        address: &str,
+        trust: Option<&Trust>,
+        room: &str,
+        name: &str,
+    ) -> Result<(Self, Seat), &'static str> {
+        let mut client = Self::open(address, trust)?;
+        let req = Request::Join {
+            room: room.to_string(),
+            name: name.to_string(),
+        };
+
+        match client.handshake(req)? {
+            Response::Init {
+                board,
+                piece,
+                turn,
+                hints,
+                opponent,
+            } => Ok((
+                client,
+                Seat {
+                    board,
+                    piece,
+                    turn,
+                    hints,
+                    opponent,
+                },
+            )),
+            _ => Err("Failed to connect to server"),
+        }
+    }
+
+    /// Connects to `address` and watches the game in `room`.
+    pub fn spectate(
+        address: &str,
+        trust: Option<&Trust>,
+        room: &str,
+    ) -> Result<(Self, View), &'static str> {
+        let mut client = Self::open(address, trust)?;
+        let req = Request::Spectate {
+            room: room.to_string(),
+        };
+
+        match client.handshake(req)? {
+            Response::Watch {
+                board,
+                turn,
+                players,
+            } => Ok((
+                client,
+                View {
+                    board,
+                    turn,
+                    players,
+                },
+            )),
+            _ => Err("Failed to connect to server"),
+        }
     }
 
     pub fn send_request(&mut self, req: Request) -> io::Result<()> {
+        Self::write_request(&self.writer, req)
+    }
+
+    fn write_request(writer: &Mutex<Stream>, req: Request) -> io::Result<()> {
         let json = serde_json::to_string(&req)?;
-        write_str(&mut self.stream, &json)
+        let mut stream = writer.lock().unwrap_or_else(PoisonError::into_inner);
+        write_str(&mut *stream, &json)
+    }
+
+    /// Gives up on `recv_response` after `timeout` without hearing from the server.
+    pub fn set_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    /// Pings the server every `interval` until the connection breaks. Each nonce is the
+    /// number of microseconds between `epoch` and the ping, so the matching `Pong` tells
+    /// the round-trip time.
+    pub fn heartbeat(&self, interval: Duration, epoch: Instant) {
+        let writer = Arc::clone(&self.writer);
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let nonce = epoch.elapsed().as_micros() as u64;
+            if Self::write_request(&writer, Request::Ping(nonce)).is_err() {
+                break;
+            }
+        });
     }
 
     pub fn recv_response(&mut self) -> io::Result<Response> {
@@ -65,7 +212,8 @@ impl Client {
 impl Clone for Client {
     fn clone(&self) -> Self {
         let stream = self.stream.try_clone().unwrap();
-        Self { stream }
+        let writer = Arc::clone(&self.writer);
+        Self { stream, writer }
     }
 }
 
@@ -78,19 +226,176 @@ impl Drop for Client {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::error::ErrorCode;
+    use core::tls::Identity;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
 
     #[test]
-    fn valid_address() {
-        assert!(Client::validate_address("127.0.0.1:8080"));
-        assert!(Client::validate_address("255.255.255.255:1111"));
-        assert!(Client::validate_address("0.0.0.0:65535"));
-        assert!(Client::validate_address("localhost:1"));
-        assert!(Client::validate_address("LOCALHOST:8080"));
-
-        assert!(!Client::validate_address("255.255.255.256:2222"));
-        assert!(!Client::validate_address("0.0.0.0:65536"));
-        assert!(!Client::validate_address("0.0:11"));
-        assert!(!Client::validate_address("Melman"));
-        assert!(!Client::validate_address("localhost:0"));
+    fn resolve_address() {
+        assert!(Client::resolve("127.0.0.1:8080").is_ok());
+        assert!(Client::resolve("255.255.255.255:1111").is_ok());
+        assert!(Client::resolve("0.0.0.0:65535").is_ok());
+        assert!(Client::resolve("[::1]:8080").is_ok());
+        assert!(Client::resolve("localhost:1").is_ok());
+        assert!(Client::resolve("LOCALHOST:8080").is_ok());
+
+        assert!(Client::resolve("255.255.255.256:2222").is_err());
+        assert!(Client::resolve("0.0.0.0:65536").is_err());
+        assert!(Client::resolve("::1").is_err());
+        assert!(Client::resolve("Melman").is_err());
+        assert!(Client::resolve("localhost:0").is_err());
+        assert!(Client::resolve("gamebox.invalid:9000").is_err());
+    }
+
+    #[test]
+    fn hosts() {
+        assert_eq!("gamebox.lan", Client::host("gamebox.lan:9000"));
+        assert_eq!("::1", Client::host("[::1]:8080"));
+        assert_eq!("127.0.0.1", Client::host("127.0.0.1:8080"));
+    }
+
+    fn respond(stream: &mut impl Write, res: &Response) {
+        write_str(stream, &serde_json::to_string(res).unwrap()).unwrap();
+    }
+
+    /// Accepts one client, checks it joins `room` and seats it as X.
+    fn seat(listener: &TcpListener, room: &str) -> TcpStream {
+        let (mut stream, _) = listener.accept().unwrap();
+        seat_on(&mut stream, room);
+        stream
+    }
+
+    fn seat_on(stream: &mut (impl Read + Write), room: &str) {
+        let req: Request = serde_json::from_str(&read_str(stream).unwrap()).unwrap();
+        assert!(matches!(req, Request::Join { room: r, .. } if r == room));
+
+        let init = Response::Init {
+            board: Board::new(),
+            piece: Piece::X,
+            turn: Piece::X,
+            hints: true,
+            opponent: Some("Melman".to_string()),
+        };
+
+        respond(stream, &init);
+    }
+
+    #[test]
+    fn connect_by_name() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || seat(&listener, "zoo"));
+
+        let address = format!("localhost:{port}");
+        let (_client, seat) = Client::join(&address, None, "zoo", "Gloria").unwrap();
+        assert_eq!(Piece::X, seat.piece);
+        assert_eq!(Some("Melman"), seat.opponent.as_deref());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn tls() {
+        let identity = Identity::self_signed().unwrap();
+        let config = identity.server_config().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        thread::spawn(move || {
+            for tcp in listener.incoming().flatten() {
+                if let Ok(mut stream) = TlsStream::accept(tcp, Arc::clone(&config)) {
+                    seat_on(&mut stream, "zoo");
+                }
+            }
+        });
+
+        let address = format!("localhost:{port}");
+        let pinned = Trust::Fingerprint(identity.fingerprint().unwrap());
+        let (_client, seat) = Client::join(&address, Some(&pinned), "zoo", "Gloria").unwrap();
+        assert_eq!(Piece::X, seat.piece);
+
+        let ca = Trust::Ca(identity.cert.clone());
+        assert!(Client::join(&address, Some(&ca), "zoo", "Gloria").is_ok());
+
+        let other = Identity::self_signed().unwrap();
+        let wrong = Trust::Fingerprint(other.fingerprint().unwrap());
+        let Err(e) = Client::join(&address, Some(&wrong), "zoo", "Gloria") else {
+            panic!("Trusted the wrong certificate");
+        };
+        assert_eq!("Could not establish a secure connection to server", e);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix() {
+        let path = std::env::temp_dir().join(format!("client-{}.sock", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            seat_on(&mut stream, "zoo");
+            stream
+        });
+
+        let address = format!("unix:{}", path.display());
+        let (_client, seat) = Client::join(&address, None, "zoo", "Gloria").unwrap();
+        assert_eq!(Piece::X, seat.piece);
+        server.join().unwrap();
+
+        let pinned = Trust::Fingerprint("00".repeat(32).parse().unwrap());
+        let Err(e) = Client::join(&address, Some(&pinned), "zoo", "Gloria") else {
+            panic!("Used TLS over a Unix socket");
+        };
+        assert_eq!("Unix sockets don't use TLS", e);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn room_full() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            read_str(&mut stream).unwrap();
+            respond(&mut stream, &Response::Invalid(ErrorCode::RoomFull));
+        });
+
+        let Err(e) = Client::join(&address, None, "zoo", "Gloria") else {
+            panic!("Joined a full room");
+        };
+        assert_eq!("Both seats of this room are taken", e);
+    }
+
+    #[test]
+    fn heartbeat() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let server = thread::spawn(move || {
+            let mut stream = seat(&listener, "lobby");
+            let req: Request = serde_json::from_str(&read_str(&mut stream).unwrap()).unwrap();
+            let Request::Ping(nonce) = req else {
+                panic!("Expected a ping, got {req:?}");
+            };
+
+            respond(&mut stream, &Response::Pong(nonce));
+            stream
+        });
+
+        let (mut client, _) = Client::join(&address, None, "lobby", "Gloria").unwrap();
+        let epoch = Instant::now();
+        client.heartbeat(Duration::from_millis(10), epoch);
+
+        let Ok(Response::Pong(nonce)) = client.recv_response() else {
+            panic!("Expected a pong");
+        };
+
+        assert!(Duration::from_micros(nonce) <= epoch.elapsed());
+
+        // The server went quiet, so the client gives up instead of blocking forever.
+        let _stream = server.join().unwrap();
+        client.set_timeout(Some(Duration::from_millis(50))).unwrap();
+        assert!(client.recv_response().is_err());
     }
 }
@@ -0,0 +1,179 @@
+use crate::message::error_message;
+use crate::print::{paint, print_board, print_stalemate, print_victory};
+use core::error::ErrorCode;
+use core::game::{board::Board, piece::Piece, state::GameState};
+use core::notation::{parse_cell, parse_moves};
+use std::io::{self, Write};
+use std::thread;
+use std::time::Duration;
+
+/// Two players taking turns on the same keyboard, no server involved. The keys are the
+/// same as online, `u` takes back the last move straight away.
+pub fn local() -> Result<(), &'static str> {
+    let stdin = io::stdin();
+    let mut board = Board::new();
+    let mut turn = Piece::X;
+    let mut started = Piece::X;
+    let mut moves: Vec<(usize, usize)> = Vec::new();
+    let (mut x, mut y) = (1, 1);
+    let mut msg = String::new();
+    let mut input = String::new();
+
+    loop {
+        print_board(&board, Some((x, y)), &msg, &[], &moves, None);
+        msg.clear();
+        print!("{}", paint(turn, "> "));
+        io::stdout().flush().map_err(|_| "Failed to flush stdout")?;
+
+        input.clear();
+        if stdin.read_line(&mut input).unwrap_or(0) == 0 {
+            return Ok(());
+        }
+
+        let idx = match input.to_lowercase().trim() {
+            "w" => {
+                x = (x + 2) % 3;
+                continue;
+            }
+
+            "a" => {
+                y = (y + 2) % 3;
+                continue;
+            }
+
+            "s" => {
+                x = (x + 1) % 3;
+                continue;
+            }
+
+            "d" => {
+                y = (y + 1) % 3;
+                continue;
+            }
+
+            "q" => return Ok(()),
+            "u" => {
+                match moves.pop() {
+                    Some(idx) => {
+                        board.unmake_move(idx).ok();
+                        turn = turn.other();
+                    }
+
+                    None => msg = error_message(ErrorCode::NothingToTakeBack).to_string(),
+                }
+
+                continue;
+            }
+
+            "e" => (x, y),
+            cell => match parse_cell(cell) {
+                Ok(idx) => idx,
+                Err(e) => {
+                    msg = e.to_string();
+                    continue;
+                }
+            },
+        };
+
+        if let Err(e) = board.make_move(idx, turn) {
+            msg = format!("Invalid move: {}", error_message(e.into()));
+            continue;
+        }
+
+        (x, y) = idx;
+        moves.push(idx);
+
+        match board.check_end(turn) {
+            GameState::Playing => {
+                turn = turn.other();
+                continue;
+            }
+
+            GameState::Win(piece) => print_victory(&board, piece),
+            GameState::Stalemate => print_stalemate(&board),
+        }
+
+        print!("Press enter for a new game ");
+        io::stdout().flush().map_err(|_| "Failed to flush stdout")?;
+        input.clear();
+        if stdin.read_line(&mut input).unwrap_or(0) == 0 {
+            return Ok(());
+        }
+
+        board.clear();
+        moves.clear();
+        started = started.other();
+        turn = started;
+    }
+}
+
+/// Every board reached by playing `moves` from an empty one, X moving first.
+fn positions(moves: &[(usize, usize)]) -> Result<Vec<(Board, GameState)>, &'static str> {
+    let mut board = Board::new();
+    let mut turn = Piece::X;
+    let mut positions: Vec<(Board, GameState)> = Vec::with_capacity(moves.len());
+
+    for &idx in moves {
+        if positions.last().is_some_and(|(_, state)| state.is_end()) {
+            return Err("Moves go on after the end of the game");
+        }
+
+        board
+            .make_move(idx, turn)
+            .map_err(|_| "The move list has an illegal move")?;
+
+        positions.push((board, board.check_end(turn)));
+        turn = turn.other();
+    }
+
+    Ok(positions)
+}
+
+/// Plays back a game written like "1. b2 a1 2. c3", one move every `delay`.
+pub fn replay(moves: &str, delay: Duration) -> Result<(), &'static str> {
+    let moves = parse_moves(moves).map_err(|_| "Invalid move list")?;
+    let positions = positions(&moves)?;
+    let n = moves.len();
+
+    print_board(&Board::new(), None, &format!("Move 0/{n}"), &[], &[], None);
+
+    for (k, (board, state)) in positions.iter().enumerate() {
+        thread::sleep(delay);
+        match *state {
+            GameState::Playing => {
+                let msg = format!("Move {}/{n}", k + 1);
+                print_board(board, None, &msg, &[], &moves[..=k], None);
+            }
+
+            GameState::Win(piece) => print_victory(board, piece),
+            GameState::Stalemate => print_stalemate(board),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replayed_positions() {
+        let moves = parse_moves("1. b2 a1 2. a3 c1 3. b1 c3 4. b3").unwrap();
+        let positions = positions(&moves).unwrap();
+
+        assert_eq!(7, positions.len());
+        assert_eq!(GameState::Playing, positions[5].1);
+        assert_eq!(GameState::Win(Piece::X), positions[6].1);
+        assert_eq!(Some(Piece::O), positions[6].0[(2, 0)]);
+    }
+
+    #[test]
+    fn invalid_replays() {
+        let occupied = parse_moves("b2 b2").unwrap();
+        assert!(positions(&occupied).is_err());
+
+        let too_long = parse_moves("b2 a1 a3 c1 b1 c3 b3 a2").unwrap();
+        assert!(positions(&too_long).is_err());
+    }
+}
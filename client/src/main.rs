@@ -1,114 +1,189 @@
 mod client;
+mod local;
+mod message;
+mod play;
 mod print;
+mod watch;
+
+use clap::{Args, Parser, Subcommand};
+use core::tls::{Fingerprint, Trust};
+use print::Scheme;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{env, fs};
+
+/// Room joined unless another one is given, the server's default too.
+const DEFAULT_ROOM: &str = "lobby";
+
+/// Terminal client for online tic-tac-toe.
+#[derive(Parser, Debug)]
+#[command(name = "client", version = env!("CARGO_PKG_VERSION"))]
+struct Cli {
+    /// Colours the pieces are drawn in
+    #[arg(long, value_enum, default_value_t, global = true)]
+    colors: Scheme,
+
+    #[command(subcommand)]
+    command: Command,
+}
 
-use client::Client;
-use colored::Colorize;
-use core::game::{piece::Piece, state::GameState};
-use core::{request::Request, response::Response};
-use print::{clear, print_board, print_stalemate, print_victory};
-use std::io::{self, Write};
-use std::sync::atomic::{AtomicUsize, Ordering::Relaxed as Or};
-use std::sync::{Arc, Mutex};
-use std::{env, thread};
-
-fn main() -> Result<(), &'static str> {
-    let mut args = env::args();
-    let _ = args.next().unwrap();
-
-    let err_msg = "Args: <address:port>";
-    let address = args.next().ok_or(err_msg)?;
-
-    let (mut client, board, piece) = Client::new(&address)?;
-    let board = Arc::new(Mutex::new(board));
-
-    let x = Arc::new(AtomicUsize::new(1));
-    let y = Arc::new(AtomicUsize::new(1));
-    let prompt = match piece {
-        Piece::X => "> ".red(),
-        Piece::O => "> ".blue(),
-    };
-
-    let board_send = Arc::clone(&board);
-    let mut client_send = client.clone();
-    let x_send = Arc::clone(&x);
-    let y_send = Arc::clone(&y);
-    let stdout_send = io::stdout();
-    let prompt_send = prompt.clone();
-
-    let handle = thread::spawn(move || loop {
-        match client_send.recv_response() {
-            Ok(Response::Valid { piece, idx, state }) => {
-                let mut board = board_send.lock().unwrap();
-                board[idx] = Some(piece);
-
-                match state {
-                    GameState::Playing => {
-                        print_board(&board, (x_send.load(Or), y_send.load(Or)), "");
-                    }
-
-                    GameState::Win(played_piece) => {
-                        print_victory(&board, played_piece);
-                        board.clear();
-                    }
-
-                    GameState::Stalemate => {
-                        print_stalemate(&board);
-                        board.clear();
-                    }
-                }
-            }
-
-            Ok(Response::Disconnect(down_piece)) if down_piece == piece => break,
-
-            Ok(res) => {
-                let board = board_send.lock().unwrap();
-                let msg = res.to_string();
-                print_board(&board, (x_send.load(Or), y_send.load(Or)), &msg);
-            }
-
-            Err(_) => {
-                clear();
-                println!("Server disconnected, press `q` to exit");
-                print!("{prompt_send}");
-                stdout_send.lock().flush().unwrap();
-                break;
-            }
-        }
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Take a seat in a room of a server
+    Play {
+        #[command(flatten)]
+        server: ServerArgs,
+
+        /// Name shown to your opponent [default: your user name]
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+
+    /// Watch the game in a room of a server without playing
+    Spectate {
+        #[command(flatten)]
+        server: ServerArgs,
+    },
+
+    /// Step through a game written like "1. b2 a1 2. c3"
+    Replay {
+        moves: String,
+
+        /// Seconds between moves
+        #[arg(long, value_parser = parse_secs, default_value = "1")]
+        delay: Duration,
+    },
+
+    /// Play against someone on the same keyboard, without a server
+    Local,
+}
 
-        print!("{prompt_send}");
-        stdout_send.lock().flush().unwrap();
-    });
+#[derive(Args, Debug)]
+struct ServerArgs {
+    /// Server to connect to, like `gamebox.lan:9000`, `[::1]:8080` or
+    /// `unix:/tmp/tictactoe.sock`
+    address: String,
+
+    /// Room to join
+    #[arg(short, long, default_value = DEFAULT_ROOM)]
+    room: String,
+
+    /// Seconds between the pings that keep the connection alive
+    #[arg(long, value_parser = parse_secs, default_value = "5")]
+    ping_interval: Duration,
+
+    /// Connect over TLS, trusting servers signed by a certificate authority of this PEM
+    /// bundle
+    #[arg(long, value_name = "FILE")]
+    ca: Option<PathBuf>,
+
+    /// Connect over TLS, trusting only the server with this SHA-256 certificate
+    /// fingerprint, like `AB:CD:...`
+    #[arg(long, conflicts_with = "ca")]
+    fingerprint: Option<Fingerprint>,
+}
 
-    let stdin = io::stdin();
-    let stdout = io::stdout();
-    let mut input = String::new();
+impl ServerArgs {
+    /// Which servers to trust, none if the connection isn't encrypted.
+    fn trust(&self) -> Result<Option<Trust>, &'static str> {
+        if let Some(fingerprint) = self.fingerprint {
+            return Ok(Some(Trust::Fingerprint(fingerprint)));
+        }
 
-    loop {
-        let (xx, yy) = (x.load(Or), y.load(Or));
-        print_board(&board.lock().unwrap(), (xx, yy), "");
+        let Some(path) = &self.ca else {
+            return Ok(None);
+        };
 
-        print!("{prompt}");
-        stdout.lock().flush().expect("Failed to flush stdout");
-        stdin.read_line(&mut input).unwrap();
+        let pem = fs::read_to_string(path).map_err(|_| "Failed to read the CA bundle")?;
+        Ok(Some(Trust::Ca(pem)))
+    }
+}
 
-        match input.to_lowercase().trim() {
-            "w" => x.store(xx.checked_sub(1).unwrap_or(2), Or),
-            "a" => y.store(yy.checked_sub(1).unwrap_or(2), Or),
-            "s" => x.store((xx + 1) % 3, Or),
-            "d" => y.store((yy + 1) % 3, Or),
-            "q" => break,
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+    Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+}
 
-            "e" => {
-                let req = Request::Play { idx: (xx, yy) };
-                client.send_request(req).ok();
-            }
+fn main() -> Result<(), &'static str> {
+    let cli = Cli::parse();
+    print::set_scheme(cli.colors);
+
+    match cli.command {
+        Command::Play { server, name } => {
+            let name = name
+                .or_else(|| env::var("USER").ok())
+                .or_else(|| env::var("USERNAME").ok())
+                .unwrap_or_default();
+
+            let trust = server.trust()?;
+            let address = &server.address;
+            play::play(
+                address,
+                trust.as_ref(),
+                &server.room,
+                &name,
+                server.ping_interval,
+            )
+        }
 
-            _ => {}
+        Command::Spectate { server } => {
+            let trust = server.trust()?;
+            let address = &server.address;
+            watch::spectate(address, trust.as_ref(), &server.room, server.ping_interval)
         }
 
-        input.clear();
+        Command::Replay { moves, delay } => local::replay(&moves, delay),
+        Command::Local => local::local(),
     }
+}
 
-    drop(client);
-    handle.join().map_err(|_| "Failed to join thread")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli() {
+        Cli::command().debug_assert();
+
+        let cli = Cli::try_parse_from(["client", "play", "[::1]:8080", "-r", "zoo"]).unwrap();
+        let Command::Play { server, name } = cli.command else {
+            panic!("Expected play");
+        };
+        assert_eq!("zoo", server.room);
+        assert_eq!(Duration::from_secs(5), server.ping_interval);
+        assert_eq!(None, name);
+
+        let fingerprint = "ab".repeat(32);
+        let args = [
+            "client",
+            "spectate",
+            "[::1]:8080",
+            "--fingerprint",
+            &fingerprint,
+        ];
+        let Command::Spectate { server } = Cli::try_parse_from(args).unwrap().command else {
+            panic!("Expected spectate");
+        };
+        assert!(matches!(server.trust(), Ok(Some(Trust::Fingerprint(_)))));
+
+        let args = ["client", "spectate", "[::1]:8080", "--fingerprint", "abcd"];
+        assert!(Cli::try_parse_from(args).is_err());
+        let args = [
+            "client",
+            "play",
+            "h:1",
+            "--ca",
+            "ca.pem",
+            "--fingerprint",
+            &fingerprint,
+        ];
+        assert!(Cli::try_parse_from(args).is_err());
+
+        let cli = Cli::try_parse_from(["client", "--colors", "mono", "local"]).unwrap();
+        assert_eq!(Scheme::Mono, cli.colors);
+
+        assert!(Cli::try_parse_from(["client", "127.0.0.1:8080"]).is_err());
+        assert!(Cli::try_parse_from(["client", "replay", "b2", "--delay", "-1"]).is_err());
+    }
 }
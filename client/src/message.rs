@@ -0,0 +1,77 @@
+use core::error::ErrorCode;
+use core::response::Response;
+
+pub fn error_message(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::NotYourTurn => "Not your turn",
+        ErrorCode::Occupied => "Cell already occupied",
+        ErrorCode::OutOfBounds => "Cell out of bounds",
+        ErrorCode::GameOver => "The game is over",
+        ErrorCode::NotSeated => "You're not playing in this game",
+        ErrorCode::RateLimited => "Slow down, too many requests",
+        ErrorCode::NothingToTakeBack => "No move to take back",
+        ErrorCode::NoOpponent => "No opponent to ask",
+        ErrorCode::NoPendingTakeback => "No takeback to answer",
+        ErrorCode::Malformed => "The server didn't understand the request",
+        ErrorCode::RoomFull => "Both seats of this room are taken",
+        ErrorCode::AlreadyJoined => "Already in a room",
+        ErrorCode::TooManyRooms => "The server can't open any more rooms",
+        ErrorCode::ServerBusy => "The server is busy, try again later",
+        ErrorCode::TooManyConnections => "Too many connections from your address",
+        ErrorCode::Banned => "Banned for a while after too many bad requests",
+        ErrorCode::Kicked => "An administrator removed you from the room",
+        ErrorCode::RoomClosed => "An administrator closed the room",
+        _ => "The server refused the request",
+    }
+}
+
+/// Human-readable description of a response, shown above the board.
+pub fn message(res: &Response) -> String {
+    use Response as R;
+    match res {
+        R::Valid { .. } => "Valid move".to_string(),
+        R::Invalid(code) => format!("Invalid move: {}", error_message(*code)),
+        R::Init { .. } => "Init".to_string(),
+        R::Watch { .. } => "Watching".to_string(),
+        R::Connect { piece, name } => format!("{name} joined as `{piece}`"),
+        R::Disconnect(piece) => format!("Player `{piece}` disconnected"),
+        R::TakebackOffer(piece) => {
+            format!("Player `{piece}` asks to take back their last move (y/n)")
+        }
+        R::TakebackDeclined => "Takeback declined".to_string(),
+        R::Takeback { .. } => "Move taken back".to_string(),
+        R::Pong(_) => "Pong".to_string(),
+        R::Timeout { piece, .. } => format!("Player `{piece}` ran out of time"),
+        R::Resigned { piece, .. } => format!("Player `{piece}` resigned"),
+        R::Chat { name, text, .. } => format!("{name}: {text}"),
+    }
+}
+
+/// Time left to both players, like "X 4:58 | O 5:00".
+pub fn clock_message([x, o]: [u64; 2]) -> String {
+    let time = |millis: u64| format!("{}:{:02}", millis / 60_000, millis / 1000 % 60);
+    format!("X {} | O {}", time(x), time(o))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors() {
+        let res = Response::Invalid(ErrorCode::Occupied);
+        assert_eq!("Invalid move: Cell already occupied", message(&res));
+
+        let res: Response = serde_json::from_str(r#"{"Invalid":"FromTheFuture"}"#).unwrap();
+        assert_eq!(
+            "Invalid move: The server refused the request",
+            message(&res)
+        );
+    }
+
+    #[test]
+    fn clocks() {
+        assert_eq!("X 4:58 | O 5:00", clock_message([298_400, 300_000]));
+        assert_eq!("X 0:00 | O 61:05", clock_message([999, 3_665_000]));
+    }
+}
@@ -0,0 +1,309 @@
+use crate::client::{Client, Seat};
+use crate::message::{clock_message, message};
+use crate::print::{clear, paint, print_board, print_stalemate, print_victory};
+use core::game::board::Board;
+use core::game::eval::{Evaluator, Outcome};
+use core::game::{piece::Piece, state::GameState};
+use core::notation::parse_cell;
+use core::tls::Trust;
+use core::{request::Request, response::Response};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed as Or};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Silent pings after which the server is considered gone.
+pub const MISSED_PINGS: u32 = 3;
+
+/// Perfect-play outcome of every empty cell, only while it's `piece`'s turn. The same
+/// `evaluator` serves the whole session, keeping what it worked out for later boards.
+fn hints(
+    evaluator: &Mutex<Evaluator>,
+    board: &Board,
+    turn: Piece,
+    piece: Piece,
+    show: bool,
+) -> Vec<((usize, usize), Outcome)> {
+    if !show || turn != piece {
+        return Vec::new();
+    }
+
+    evaluator.lock().unwrap().evaluate_moves(board, turn)
+}
+
+/// Takes a seat in `room` of the server at `address` and plays until the user quits.
+pub fn play(
+    address: &str,
+    trust: Option<&Trust>,
+    room: &str,
+    name: &str,
+    interval: Duration,
+) -> Result<(), &'static str> {
+    let timeout = interval
+        .checked_mul(MISSED_PINGS)
+        .ok_or("Invalid ping interval")?;
+    let (mut client, seat) = Client::join(address, trust, room, name)?;
+    let epoch = Instant::now();
+    client.heartbeat(interval, epoch);
+    client
+        .set_timeout(Some(timeout))
+        .map_err(|_| "Invalid ping interval")?;
+
+    let Seat {
+        board,
+        piece,
+        turn,
+        hints: hints_allowed,
+        opponent,
+    } = seat;
+
+    let board = Arc::new(Mutex::new(board));
+    let turn = Arc::new(Mutex::new(turn));
+    let moves = Arc::new(Mutex::new(Vec::new()));
+    let show_hints = Arc::new(AtomicBool::new(false));
+    let evaluator = Arc::new(Mutex::new(Evaluator::new()));
+    let latency = Arc::new(Mutex::new(None));
+
+    let x = Arc::new(AtomicUsize::new(1));
+    let y = Arc::new(AtomicUsize::new(1));
+    let prompt = paint(piece, "> ");
+
+    let board_send = Arc::clone(&board);
+    let turn_send = Arc::clone(&turn);
+    let moves_send = Arc::clone(&moves);
+    let show_hints_send = Arc::clone(&show_hints);
+    let evaluator_send = Arc::clone(&evaluator);
+    let latency_send = Arc::clone(&latency);
+    let mut client_send = client.clone();
+    let x_send = Arc::clone(&x);
+    let y_send = Arc::clone(&y);
+    let stdout_send = io::stdout();
+    let prompt_send = prompt.clone();
+
+    let handle = thread::spawn(move || loop {
+        match client_send.recv_response() {
+            Ok(Response::Valid {
+                piece: played,
+                idx,
+                state,
+                turn: next,
+                clock,
+            }) => {
+                let mut board = board_send.lock().unwrap();
+                let mut turn = turn_send.lock().unwrap();
+                let mut moves = moves_send.lock().unwrap();
+                board.make_move(idx, played).ok();
+                *turn = next;
+                moves.push(idx);
+
+                match state {
+                    GameState::Playing => {
+                        let hints = hints(
+                            &evaluator_send,
+                            &board,
+                            *turn,
+                            piece,
+                            show_hints_send.load(Or),
+                        );
+                        let idx = (x_send.load(Or), y_send.load(Or));
+                        let latency = *latency_send.lock().unwrap();
+                        let msg = clock.map(clock_message).unwrap_or_default();
+                        print_board(&board, Some(idx), &msg, &hints, &moves, latency);
+                    }
+
+                    GameState::Win(played_piece) => {
+                        print_victory(&board, played_piece);
+                        board.clear();
+                        moves.clear();
+                    }
+
+                    GameState::Stalemate => {
+                        print_stalemate(&board);
+                        board.clear();
+                        moves.clear();
+                    }
+                }
+            }
+
+            Ok(Response::Takeback {
+                board: restored,
+                turn: next,
+            }) => {
+                let mut board = board_send.lock().unwrap();
+                let mut turn = turn_send.lock().unwrap();
+                let mut moves = moves_send.lock().unwrap();
+                *board = restored;
+                *turn = next;
+                moves.retain(|&idx| board[idx].is_some());
+
+                let hints = hints(
+                    &evaluator_send,
+                    &board,
+                    *turn,
+                    piece,
+                    show_hints_send.load(Or),
+                );
+                let idx = (x_send.load(Or), y_send.load(Or));
+                let latency = *latency_send.lock().unwrap();
+                print_board(
+                    &board,
+                    Some(idx),
+                    "Move taken back",
+                    &hints,
+                    &moves,
+                    latency,
+                );
+            }
+
+            Ok(
+                res
+                @ (Response::Timeout { turn: next, .. } | Response::Resigned { turn: next, .. }),
+            ) => {
+                let mut board = board_send.lock().unwrap();
+                let mut moves = moves_send.lock().unwrap();
+                board.clear();
+                moves.clear();
+                *turn_send.lock().unwrap() = next;
+
+                let hints = hints(
+                    &evaluator_send,
+                    &board,
+                    next,
+                    piece,
+                    show_hints_send.load(Or),
+                );
+                let idx = (x_send.load(Or), y_send.load(Or));
+                let latency = *latency_send.lock().unwrap();
+                print_board(&board, Some(idx), &message(&res), &hints, &moves, latency);
+            }
+
+            Ok(Response::Pong(nonce)) => {
+                let rtt = epoch.elapsed().saturating_sub(Duration::from_micros(nonce));
+                *latency_send.lock().unwrap() = Some(rtt);
+                continue;
+            }
+
+            Ok(Response::Disconnect(down_piece)) if down_piece == piece => break,
+
+            Ok(res) => {
+                let board = board_send.lock().unwrap();
+                let turn = turn_send.lock().unwrap();
+                let moves = moves_send.lock().unwrap();
+                let hints = hints(
+                    &evaluator_send,
+                    &board,
+                    *turn,
+                    piece,
+                    show_hints_send.load(Or),
+                );
+                let idx = (x_send.load(Or), y_send.load(Or));
+                let latency = *latency_send.lock().unwrap();
+                print_board(&board, Some(idx), &message(&res), &hints, &moves, latency);
+            }
+
+            Err(e) => {
+                clear();
+                match e.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                        println!("Server stopped responding, press `q` to exit")
+                    }
+
+                    _ => println!("Server disconnected, press `q` to exit"),
+                }
+
+                print!("{prompt_send}");
+                stdout_send.lock().flush().unwrap();
+                break;
+            }
+        }
+
+        print!("{prompt_send}");
+        stdout_send.lock().flush().unwrap();
+    });
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    let mut input = String::new();
+    let mut msg = match opponent {
+        Some(name) => format!("Playing against {name}"),
+        None => "Waiting for an opponent".to_string(),
+    };
+
+    loop {
+        let (xx, yy) = (x.load(Or), y.load(Or));
+        {
+            let board = board.lock().unwrap();
+            let hints = hints(
+                &evaluator,
+                &board,
+                *turn.lock().unwrap(),
+                piece,
+                show_hints.load(Or),
+            );
+            let latency = *latency.lock().unwrap();
+            print_board(
+                &board,
+                Some((xx, yy)),
+                &msg,
+                &hints,
+                &moves.lock().unwrap(),
+                latency,
+            );
+        }
+
+        msg.clear();
+        print!("{prompt}");
+        stdout.lock().flush().expect("Failed to flush stdout");
+        stdin.read_line(&mut input).unwrap();
+
+        match input.to_lowercase().trim() {
+            "w" => x.store(xx.checked_sub(1).unwrap_or(2), Or),
+            "a" => y.store(yy.checked_sub(1).unwrap_or(2), Or),
+            "s" => x.store((xx + 1) % 3, Or),
+            "d" => y.store((yy + 1) % 3, Or),
+            "q" => break,
+
+            "h" if hints_allowed => {
+                show_hints.fetch_xor(true, Or);
+            }
+
+            "h" => msg = "Hints are disabled on this server".to_string(),
+
+            "e" => {
+                let req = Request::Play { idx: (xx, yy) };
+                client.send_request(req).ok();
+            }
+
+            "u" => {
+                client.send_request(Request::Takeback).ok();
+                msg = "Takeback requested".to_string();
+            }
+
+            "y" => {
+                client.send_request(Request::AcceptTakeback).ok();
+            }
+
+            "n" => {
+                client.send_request(Request::DeclineTakeback).ok();
+            }
+
+            "r" => {
+                client.send_request(Request::Resign).ok();
+            }
+
+            cell => {
+                if let Ok(idx) = parse_cell(cell) {
+                    x.store(idx.0, Or);
+                    y.store(idx.1, Or);
+                    client.send_request(Request::Play { idx }).ok();
+                }
+            }
+        }
+
+        input.clear();
+    }
+
+    drop(client);
+    handle.join().map_err(|_| "Failed to join thread")
+}
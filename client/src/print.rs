@@ -1,32 +1,107 @@
-use colored::Colorize;
-use core::game::{board::Board, piece::Piece};
+use clap::ValueEnum;
+use colored::{ColoredString, Colorize};
+use core::game::{board::Board, eval::Outcome, piece::Piece};
+use core::notation::format_pair;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Colours pieces are drawn in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Scheme {
+    /// Red crosses and blue noughts
+    #[default]
+    Classic,
+    /// Orange crosses and sky blue noughts, told apart with any colour vision
+    Colorblind,
+    /// No colours at all
+    Mono,
+}
+
+static SCHEME: OnceLock<Scheme> = OnceLock::new();
+
+/// Picks the colours of everything printed afterwards, only the first call counts.
+pub fn set_scheme(scheme: Scheme) {
+    if scheme == Scheme::Mono {
+        colored::control::set_override(false);
+    }
+
+    SCHEME.set(scheme).ok();
+}
+
+/// `s` in the colour of `piece`.
+pub fn paint(piece: Piece, s: &str) -> ColoredString {
+    match (SCHEME.get().copied().unwrap_or_default(), piece) {
+        (Scheme::Classic, Piece::X) => s.red(),
+        (Scheme::Classic, Piece::O) => s.blue(),
+        (Scheme::Colorblind, Piece::X) => s.truecolor(230, 159, 0),
+        (Scheme::Colorblind, Piece::O) => s.truecolor(86, 180, 233),
+        (Scheme::Mono, _) => s.normal(),
+    }
+}
 
 pub fn clear() {
     print!("\x1B[2J\x1B[1;1H");
 }
 
-pub fn print_board(board: &Board, idx: (usize, usize), msg: &str) {
+fn hint_str(outcome: Outcome) -> ColoredString {
+    match outcome {
+        Outcome::Win => "W".green(),
+        Outcome::Draw => "D".white(),
+        Outcome::Loss => "L".purple(),
+    }
+}
+
+/// Last `n` numbered pairs of moves, oldest first.
+fn move_list(moves: &[(usize, usize)], n: usize) -> Vec<String> {
+    let pairs: Vec<String> = moves
+        .chunks(2)
+        .enumerate()
+        .map(|(i, pair)| format_pair(i + 1, pair))
+        .collect();
+
+    pairs[pairs.len().saturating_sub(n)..].to_vec()
+}
+
+pub fn print_board(
+    board: &Board,
+    cursor: Option<(usize, usize)>,
+    msg: &str,
+    hints: &[((usize, usize), Outcome)],
+    moves: &[(usize, usize)],
+    latency: Option<Duration>,
+) {
     clear();
     println!("{msg}");
 
+    let mut list = move_list(moves, 4);
+    if let Some(latency) = latency {
+        list.resize(4, String::new());
+        list.push(format!("ping {} ms", latency.as_millis()));
+    }
+
+    let side = |line: usize| list.get(line).map(String::as_str).unwrap_or("");
+
+    let hint = |idx| {
+        hints
+            .iter()
+            .find(|&&(cell, _)| cell == idx)
+            .map(|&(_, outcome)| outcome)
+    };
+
     for i in 0..3 {
         for j in 0..3 {
             print!(" ");
-            let piece = if (i, j) == idx {
-                board[(i, j)]
-                    .map(|piece| piece.to_string())
-                    .unwrap_or("_".to_string())
-                    .white()
+            let piece = if cursor == Some((i, j)) {
+                match (board[(i, j)], hint((i, j))) {
+                    (Some(piece), _) => piece.to_string().white(),
+                    (None, Some(outcome)) => hint_str(outcome).underline(),
+                    (None, None) => "_".white(),
+                }
+            } else if let Some(outcome) = hint((i, j)) {
+                hint_str(outcome).dimmed()
             } else {
                 board[(i, j)]
-                    .map(|piece| {
-                        let color = match piece {
-                            Piece::X => |s: &str| s.red(),
-                            Piece::O => |s: &str| s.blue(),
-                        };
-
-                        color(&piece.to_string())
-                    })
+                    .map(|piece| paint(piece, &piece.to_string()))
                     .unwrap_or(" ".to_string().yellow())
             };
 
@@ -37,14 +112,18 @@ pub fn print_board(board: &Board, idx: (usize, usize), msg: &str) {
             }
         }
 
-        println!();
+        println!("   {}", side(i * 2));
 
         if i < 2 {
-            println!("{}", " - + - + - ".yellow());
+            println!("{}   {}", " - + - + - ".yellow(), side(i * 2 + 1));
         }
     }
 
     println!();
+
+    if !hints.is_empty() {
+        println!("{}", "Hints: W win, D draw, L loss".dimmed());
+    }
 }
 
 fn print_str(board: &Board, msg: &str) -> String {
@@ -88,14 +167,8 @@ pub fn print_stalemate(board: &Board) {
 
 pub fn print_victory(board: &Board, player: Piece) {
     let s = print_str(board, &format!("{player} Wins!"));
-
-    let color = match player {
-        Piece::X => |s: &str| s.red(),
-        Piece::O => |s: &str| s.blue(),
-    };
-
     clear();
-    println!("\n{}", color(&s));
+    println!("\n{}", paint(player, &s));
 }
 
 #[cfg(test)]
@@ -103,11 +176,35 @@ mod tests {
     use super::*;
     use std::str::FromStr;
 
+    #[test]
+    fn scrolls_move_list() {
+        let moves = [(0, 0), (1, 1), (2, 2), (0, 2), (2, 0)];
+        assert_eq!(vec!["1. a3 b2", "2. c1 c3", "3. a1"], move_list(&moves, 5));
+        assert_eq!(vec!["2. c1 c3", "3. a1"], move_list(&moves, 2));
+        assert!(move_list(&[], 5).is_empty());
+    }
+
     #[test]
     #[ignore]
     fn print() {
         let board = Board::from_str("x x x o o o - - -").unwrap();
-        print_board(&board, (1, 1), "msg");
+        let moves = [(0, 0), (1, 0), (0, 1)];
+        print_board(
+            &board,
+            Some((1, 1)),
+            "msg",
+            &[],
+            &moves,
+            Some(Duration::from_millis(12)),
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn hints() {
+        let board = Board::from_str("x x - o o - - - -").unwrap();
+        let hints = [((0, 2), Outcome::Win), ((1, 2), Outcome::Draw)];
+        print_board(&board, Some((1, 2)), "msg", &hints, &[], None);
     }
 
     #[test]
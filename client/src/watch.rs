@@ -0,0 +1,165 @@
+use crate::client::{Client, View};
+use crate::message::{clock_message, message};
+use crate::play::MISSED_PINGS;
+use crate::print::{clear, print_board, print_stalemate, print_victory};
+use core::game::{piece::Piece, state::GameState};
+use core::response::Response;
+use core::tls::Trust;
+use std::collections::BTreeMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Who plays in `room`, shown above the board.
+fn title(room: &str, players: &BTreeMap<Piece, String>, turn: Piece) -> String {
+    let name = |piece| players.get(&piece).map(String::as_str).unwrap_or("(empty)");
+    format!(
+        "Room `{room}`: X {} vs O {}, {turn} to move",
+        name(Piece::X),
+        name(Piece::O)
+    )
+}
+
+/// Watches the game in `room` of the server at `address` until the connection drops.
+pub fn spectate(
+    address: &str,
+    trust: Option<&Trust>,
+    room: &str,
+    interval: Duration,
+) -> Result<(), &'static str> {
+    let timeout = interval
+        .checked_mul(MISSED_PINGS)
+        .ok_or("Invalid ping interval")?;
+    let (mut client, view) = Client::spectate(address, trust, room)?;
+    let epoch = Instant::now();
+    client.heartbeat(interval, epoch);
+    client
+        .set_timeout(Some(timeout))
+        .map_err(|_| "Invalid ping interval")?;
+
+    let View {
+        mut board,
+        mut turn,
+        players,
+    } = view;
+
+    let mut players: BTreeMap<_, _> = players.into_iter().collect();
+    let mut moves = Vec::new();
+    let mut latency = None;
+    let mut msg = "Press Ctrl-C to stop watching".to_string();
+    let mut redraw = true;
+
+    loop {
+        if redraw {
+            let title = title(room, &players, turn);
+            print_board(
+                &board,
+                None,
+                &format!("{title}\n{msg}"),
+                &[],
+                &moves,
+                latency,
+            );
+            msg.clear();
+        }
+
+        redraw = true;
+
+        let res = match client.recv_response() {
+            Ok(res) => res,
+            Err(e) => {
+                clear();
+                return match e.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                        Err("Server stopped responding")
+                    }
+
+                    _ => Err("Server disconnected"),
+                };
+            }
+        };
+
+        match res {
+            Response::Valid {
+                piece,
+                idx,
+                state,
+                turn: next,
+                clock,
+            } => {
+                board.make_move(idx, piece).ok();
+                moves.push(idx);
+                turn = next;
+
+                match state {
+                    GameState::Playing => {
+                        msg = clock.map(clock_message).unwrap_or_default();
+                        continue;
+                    }
+
+                    GameState::Win(piece) => print_victory(&board, piece),
+                    GameState::Stalemate => print_stalemate(&board),
+                }
+
+                // The finished board stays up until something happens in the next game.
+                board.clear();
+                moves.clear();
+                redraw = false;
+            }
+
+            Response::Takeback {
+                board: restored,
+                turn: next,
+            } => {
+                board = restored;
+                turn = next;
+                moves.retain(|&idx| board[idx].is_some());
+                msg = message(&res);
+            }
+
+            Response::Timeout { turn: next, .. } | Response::Resigned { turn: next, .. } => {
+                board.clear();
+                moves.clear();
+                turn = next;
+                msg = message(&res);
+            }
+
+            Response::Connect { piece, ref name } => {
+                players.insert(piece, name.clone());
+                msg = message(&res);
+            }
+
+            Response::Disconnect(piece) => {
+                players.remove(&piece);
+                msg = message(&res);
+            }
+
+            Response::Pong(nonce) => {
+                let rtt = epoch.elapsed().saturating_sub(Duration::from_micros(nonce));
+                latency = Some(rtt);
+                redraw = false;
+            }
+
+            res => msg = message(&res),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles() {
+        let mut players = BTreeMap::from([(Piece::X, "Gloria".to_string())]);
+        assert_eq!(
+            "Room `zoo`: X Gloria vs O (empty), X to move",
+            title("zoo", &players, Piece::X)
+        );
+
+        players.insert(Piece::O, "Melman".to_string());
+        assert_eq!(
+            "Room `zoo`: X Gloria vs O Melman, O to move",
+            title("zoo", &players, Piece::O)
+        );
+    }
+}
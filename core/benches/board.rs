@@ -0,0 +1,76 @@
+use core::game::{bitboard::BitBoard, board::Board, piece::Piece};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::str::FromStr;
+
+fn positions() -> Vec<Board> {
+    [
+        "- - -  - - -  - - -",
+        "x - -  - o -  - - -",
+        "x o x  - o -  - - -",
+        "x o x  o x o  o x -",
+        "o x o  o x o  x o x",
+        "x x x  o o -  - - -",
+    ]
+    .iter()
+    .map(|s| Board::from_str(s).unwrap())
+    .collect()
+}
+
+fn check_end(c: &mut Criterion) {
+    let boards = positions();
+    let bitboards: Vec<BitBoard> = boards.iter().copied().map(BitBoard::from).collect();
+
+    let mut group = c.benchmark_group("check_end");
+    group.bench_function("board", |b| {
+        b.iter(|| {
+            for board in &boards {
+                black_box(black_box(board).check_end(Piece::X));
+            }
+        })
+    });
+
+    group.bench_function("bitboard", |b| {
+        b.iter(|| {
+            for bitboard in &bitboards {
+                black_box(black_box(bitboard).check_end(Piece::X));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn moves(c: &mut Criterion) {
+    let boards = positions();
+    let bitboards: Vec<BitBoard> = boards.iter().copied().map(BitBoard::from).collect();
+
+    let mut group = c.benchmark_group("moves");
+    group.bench_function("board", |b| {
+        b.iter(|| {
+            for board in &boards {
+                black_box(black_box(board).legal_moves());
+            }
+        })
+    });
+
+    group.bench_function("bitboard", |b| {
+        b.iter(|| {
+            for bitboard in &bitboards {
+                let mut moves = [(0, 0); 9];
+                let mut len = 0;
+
+                for idx in black_box(bitboard).moves() {
+                    moves[len] = idx;
+                    len += 1;
+                }
+
+                black_box((moves, len));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, check_end, moves);
+criterion_main!(benches);
@@ -0,0 +1,62 @@
+use super::game::board::MoveError;
+use serde::{Deserialize, Serialize};
+
+/// Why the server refused a request, sent in [`Response::Invalid`](super::response::Response).
+///
+/// New codes may be added at any time, clients built before them receive `Unknown`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    NotYourTurn,
+    Occupied,
+    OutOfBounds,
+    GameOver,
+    NotSeated,
+    RateLimited,
+    NothingToTakeBack,
+    NoOpponent,
+    NoPendingTakeback,
+    Malformed,
+    RoomFull,
+    AlreadyJoined,
+    TooManyRooms,
+    ServerBusy,
+    TooManyConnections,
+    Banned,
+    Kicked,
+    RoomClosed,
+    #[serde(other)]
+    Unknown,
+}
+
+impl From<MoveError> for ErrorCode {
+    fn from(e: MoveError) -> Self {
+        match e {
+            MoveError::OutOfBounds => ErrorCode::OutOfBounds,
+            MoveError::Occupied => ErrorCode::Occupied,
+            MoveError::Empty => ErrorCode::NothingToTakeBack,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::response::Response;
+
+    #[test]
+    fn serialization() {
+        let json = serde_json::to_string(&ErrorCode::NotYourTurn).unwrap();
+        assert_eq!("\"NotYourTurn\"", json);
+        assert_eq!(ErrorCode::NotYourTurn, serde_json::from_str(&json).unwrap());
+    }
+
+    #[test]
+    fn unknown_codes() {
+        let code: ErrorCode = serde_json::from_str("\"FromTheFuture\"").unwrap();
+        assert_eq!(ErrorCode::Unknown, code);
+
+        let res: Response = serde_json::from_str(r#"{"Invalid":"FromTheFuture"}"#).unwrap();
+        assert!(matches!(res, Response::Invalid(ErrorCode::Unknown)));
+    }
+}
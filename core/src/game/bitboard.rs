@@ -0,0 +1,259 @@
+use super::{board::Board, piece::Piece, state::GameState};
+
+const FULL: u16 = 0b111_111_111;
+
+/// Every line of three cells, one bit per cell in row-major order.
+pub const WIN_MASKS: [u16; 8] = [
+    0b000_000_111,
+    0b000_111_000,
+    0b111_000_000,
+    0b001_001_001,
+    0b010_010_010,
+    0b100_100_100,
+    0b100_010_001,
+    0b001_010_100,
+];
+
+/// A [`Board`] stored as one bitmask per piece, cell `(i, j)` being bit `i * 3 + j`.
+///
+/// Meant for hot loops like solvers, convert from and into a [`Board`] at the edges.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct BitBoard {
+    x: u16,
+    o: u16,
+}
+
+/// Whether each of the 512 possible masks contains a line.
+const WON: [bool; 512] = {
+    let mut won = [false; 512];
+    let mut mask = 0;
+
+    while mask < 512 {
+        let mut k = 0;
+        while k < WIN_MASKS.len() {
+            if mask as u16 & WIN_MASKS[k] == WIN_MASKS[k] {
+                won[mask] = true;
+            }
+
+            k += 1;
+        }
+
+        mask += 1;
+    }
+
+    won
+};
+
+/// Coordinates of each bit.
+const CELLS: [(usize, usize); 9] = [
+    (0, 0),
+    (0, 1),
+    (0, 2),
+    (1, 0),
+    (1, 1),
+    (1, 2),
+    (2, 0),
+    (2, 1),
+    (2, 2),
+];
+
+/// # Panics
+///
+/// If the cell is outside of the board, like indexing a [`Board`].
+fn bit((i, j): (usize, usize)) -> u16 {
+    assert!(i < 3 && j < 3, "Cell {:?} is outside of the board", (i, j));
+    1 << (i * 3 + j)
+}
+
+impl BitBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mask of the cells taken by `piece`.
+    pub fn pieces(&self, piece: Piece) -> u16 {
+        match piece {
+            Piece::X => self.x,
+            Piece::O => self.o,
+        }
+    }
+
+    /// Mask of the empty cells.
+    pub fn empty(&self) -> u16 {
+        !(self.x | self.o) & FULL
+    }
+
+    /// # Panics
+    ///
+    /// If the cell is outside of the board.
+    pub fn get(&self, idx: (usize, usize)) -> Option<Piece> {
+        let bit = bit(idx);
+        if self.x & bit != 0 {
+            Some(Piece::X)
+        } else if self.o & bit != 0 {
+            Some(Piece::O)
+        } else {
+            None
+        }
+    }
+
+    /// # Panics
+    ///
+    /// If the cell is outside of the board.
+    pub fn set(&mut self, idx: (usize, usize), piece: Option<Piece>) {
+        let bit = bit(idx);
+        self.x &= !bit;
+        self.o &= !bit;
+
+        match piece {
+            Some(Piece::X) => self.x |= bit,
+            Some(Piece::O) => self.o |= bit,
+            None => {}
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        (self.x | self.o).count_ones() == 9
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Same as [`Board::check_end`].
+    pub fn check_end(&self, piece: Piece) -> GameState {
+        if WON[self.x as usize] || WON[self.o as usize] {
+            return GameState::Win(piece);
+        }
+
+        if self.is_full() {
+            return GameState::Stalemate;
+        }
+
+        GameState::Playing
+    }
+
+    /// Empty cells in row-major order, none once a side won, same as
+    /// [`Board::legal_moves`].
+    pub fn moves(&self) -> impl Iterator<Item = (usize, usize)> {
+        let won = WON[self.x as usize] || WON[self.o as usize];
+        let mut empty = if won { 0 } else { self.empty() };
+        std::iter::from_fn(move || {
+            if empty == 0 {
+                return None;
+            }
+
+            let n = empty.trailing_zeros() as usize;
+            empty &= empty - 1;
+            Some(CELLS[n])
+        })
+    }
+}
+
+impl From<Board> for BitBoard {
+    fn from(board: Board) -> Self {
+        let mut bitboard = Self::new();
+        for i in 0..3 {
+            for j in 0..3 {
+                bitboard.set((i, j), board[(i, j)]);
+            }
+        }
+
+        bitboard
+    }
+}
+
+impl From<BitBoard> for Board {
+    fn from(bitboard: BitBoard) -> Self {
+        let mut board = Self::new();
+        for i in 0..3 {
+            for j in 0..3 {
+                board[(i, j)] = bitboard.get((i, j));
+            }
+        }
+
+        board
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    /// Every assignment of `X`, `O` or nothing to the nine cells.
+    fn all_boards() -> impl Iterator<Item = Board> {
+        (0..3usize.pow(9)).map(|mut n| {
+            let mut board = Board::new();
+            for k in 0..9 {
+                board[(k / 3, k % 3)] = match n % 3 {
+                    0 => None,
+                    1 => Some(Piece::X),
+                    _ => Some(Piece::O),
+                };
+
+                n /= 3;
+            }
+
+            board
+        })
+    }
+
+    #[test]
+    fn conversion() {
+        let board = Board::from_str("x o -  - x -  o - -").unwrap();
+        let bitboard = BitBoard::from(board);
+
+        assert_eq!(0b000_010_001, bitboard.pieces(Piece::X));
+        assert_eq!(0b001_000_010, bitboard.pieces(Piece::O));
+        assert_eq!(Some(Piece::O), bitboard.get((0, 1)));
+        assert_eq!(board, Board::from(bitboard));
+    }
+
+    #[test]
+    fn matches_board() {
+        for board in all_boards() {
+            let bitboard = BitBoard::from(board);
+            assert_eq!(board, Board::from(bitboard));
+            assert_eq!(board.is_full(), bitboard.is_full());
+            assert_eq!(board.check_end(Piece::O), bitboard.check_end(Piece::O));
+
+            assert_eq!(board.legal_moves(), bitboard.moves().collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn no_moves_once_won() {
+        let board = Board::from_str("x x x  o o -  - - -").unwrap();
+        let bitboard = BitBoard::from(board);
+        assert_eq!(None, bitboard.moves().next());
+        assert!(board.legal_moves().is_empty());
+
+        let board = Board::from_str("x x -  o o -  - - -").unwrap();
+        let bitboard = BitBoard::from(board);
+        assert_eq!(board.legal_moves(), bitboard.moves().collect::<Vec<_>>());
+        assert_eq!(5, board.legal_moves().len());
+    }
+
+    #[test]
+    #[should_panic(expected = "outside of the board")]
+    fn out_of_bounds() {
+        BitBoard::new().set((0, 3), Some(Piece::X));
+    }
+
+    #[test]
+    fn set_and_clear() {
+        let mut bitboard = BitBoard::new();
+        bitboard.set((1, 1), Some(Piece::X));
+        bitboard.set((1, 1), Some(Piece::O));
+        assert_eq!(0, bitboard.pieces(Piece::X));
+        assert_eq!(Some(Piece::O), bitboard.get((1, 1)));
+
+        bitboard.set((1, 1), None);
+        assert_eq!(FULL, bitboard.empty());
+
+        bitboard.set((0, 0), Some(Piece::X));
+        bitboard.clear();
+        assert_eq!(BitBoard::new(), bitboard);
+    }
+}
@@ -1,12 +1,34 @@
 use super::{piece::Piece, state::GameState};
 use serde::{Deserialize, Serialize};
-use std::fmt::{self, Debug};
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
 use std::ops::{Index, IndexMut};
 use std::str::FromStr;
 
 const LEN: usize = 9;
 
-#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveError {
+    OutOfBounds,
+    Occupied,
+    Empty,
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            MoveError::OutOfBounds => "Cell out of bounds",
+            MoveError::Occupied => "Cell already occupied",
+            MoveError::Empty => "Cell is empty",
+        };
+
+        write!(f, "{s}")
+    }
+}
+
+impl Error for MoveError {}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash)]
 pub struct Board([Option<Piece>; LEN]);
 
 impl Board {
@@ -22,6 +44,46 @@ impl Board {
         *self = Self::default();
     }
 
+    fn offset((i, j): (usize, usize)) -> Result<usize, MoveError> {
+        if i < 3 && j < 3 {
+            Ok(i * 3 + j)
+        } else {
+            Err(MoveError::OutOfBounds)
+        }
+    }
+
+    /// The cell at `idx`, or `None` if it's outside of the board.
+    pub fn get(&self, idx: (usize, usize)) -> Option<Option<Piece>> {
+        Self::offset(idx).ok().map(|k| self.0[k])
+    }
+
+    /// Empty cells in row-major order, none once someone has won.
+    pub fn legal_moves(&self) -> Vec<(usize, usize)> {
+        if self.check_end(Piece::default()).is_end() {
+            return Vec::new();
+        }
+
+        (0..LEN)
+            .filter(|&k| self.0[k].is_none())
+            .map(|k| (k / 3, k % 3))
+            .collect()
+    }
+
+    pub fn make_move(&mut self, idx: (usize, usize), piece: Piece) -> Result<(), MoveError> {
+        let cell = &mut self.0[Self::offset(idx)?];
+        if cell.is_some() {
+            return Err(MoveError::Occupied);
+        }
+
+        *cell = Some(piece);
+        Ok(())
+    }
+
+    /// Empties `idx`, returning the piece that was there.
+    pub fn unmake_move(&mut self, idx: (usize, usize)) -> Result<Piece, MoveError> {
+        self.0[Self::offset(idx)?].take().ok_or(MoveError::Empty)
+    }
+
     pub fn check_end(&self, piece: Piece) -> GameState {
         for i in 0..3 {
             if self[(i, 0)].is_some()
@@ -55,16 +117,25 @@ impl Board {
     }
 }
 
+/// # Panics
+///
+/// If the cell is outside of the board, use [`Board::get`] for untrusted input.
 impl Index<(usize, usize)> for Board {
     type Output = Option<Piece>;
-    fn index(&self, (i, j): (usize, usize)) -> &Self::Output {
-        &self.0[i * 3 + j]
+    fn index(&self, idx: (usize, usize)) -> &Self::Output {
+        match Self::offset(idx) {
+            Ok(k) => &self.0[k],
+            Err(_) => panic!("Cell {idx:?} is outside of the board"),
+        }
     }
 }
 
 impl IndexMut<(usize, usize)> for Board {
-    fn index_mut(&mut self, (i, j): (usize, usize)) -> &mut Self::Output {
-        &mut self.0[i * 3 + j]
+    fn index_mut(&mut self, idx: (usize, usize)) -> &mut Self::Output {
+        match Self::offset(idx) {
+            Ok(k) => &mut self.0[k],
+            Err(_) => panic!("Cell {idx:?} is outside of the board"),
+        }
     }
 }
 
@@ -83,6 +154,43 @@ impl FromStr for Board {
     }
 }
 
+/// Writes the rows of a [`Position`](super::position::Position), as in `X1O/1X1/3`.
+impl Display for Board {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows: Vec<String> = self
+            .0
+            .chunks(3)
+            .map(|row| {
+                let mut s = String::new();
+                let mut empty = 0;
+
+                for cell in row {
+                    match cell {
+                        Some(piece) => {
+                            if empty > 0 {
+                                s.push_str(&empty.to_string());
+                                empty = 0;
+                            }
+
+                            s.push_str(&piece.to_string());
+                        }
+
+                        None => empty += 1,
+                    }
+                }
+
+                if empty > 0 {
+                    s.push_str(&empty.to_string());
+                }
+
+                s
+            })
+            .collect();
+
+        write!(f, "{}", rows.join("/"))
+    }
+}
+
 impl Debug for Board {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let pieces: Vec<String> = self
@@ -126,6 +234,54 @@ mod tests {
         assert!(board.0.iter().all(|p| p.is_some()));
     }
 
+    #[test]
+    fn legal_moves() {
+        assert_eq!(9, Board::new().legal_moves().len());
+
+        let board = Board::from_str("x o -  - x -  o - -").unwrap();
+        let moves = vec![(0, 2), (1, 0), (1, 2), (2, 1), (2, 2)];
+        assert_eq!(moves, board.legal_moves());
+
+        let board = Board::from_str("x x x  o o -  - - -").unwrap();
+        assert!(board.legal_moves().is_empty());
+    }
+
+    #[test]
+    fn bounds() {
+        let board = Board::from_str("- - -  - x -  - - -").unwrap();
+        assert_eq!(Some(Some(Piece::X)), board.get((1, 1)));
+        assert_eq!(Some(None), board.get((2, 2)));
+        assert_eq!(None, board.get((0, 3)));
+        assert_eq!(None, board.get((5, 0)));
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_out_of_bounds() {
+        // Would alias cell (1, 2) without the bounds check.
+        let _ = Board::new()[(0, 5)];
+    }
+
+    #[test]
+    fn make_and_unmake() {
+        let mut board = Board::new();
+        assert_eq!(Ok(()), board.make_move((1, 1), Piece::X));
+        assert_eq!(Err(MoveError::Occupied), board.make_move((1, 1), Piece::O));
+        assert_eq!(
+            Err(MoveError::OutOfBounds),
+            board.make_move((3, 0), Piece::O)
+        );
+        assert_eq!(
+            Err(MoveError::OutOfBounds),
+            board.make_move((0, 5), Piece::O)
+        );
+
+        assert_eq!(Ok(Piece::X), board.unmake_move((1, 1)));
+        assert_eq!(Err(MoveError::Empty), board.unmake_move((1, 1)));
+        assert_eq!(Err(MoveError::OutOfBounds), board.unmake_move((9, 9)));
+        assert_eq!(Board::new(), board);
+    }
+
     #[test]
     fn end() {
         let board = Board::from_str("x x x  - - -  x x x").unwrap();
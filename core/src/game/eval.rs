@@ -0,0 +1,124 @@
+use super::{board::Board, piece::Piece, state::GameState};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Result of a position under perfect play, from the point of view of a given player.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Outcome {
+    Loss,
+    Draw,
+    Win,
+}
+
+impl Outcome {
+    pub fn flip(self) -> Self {
+        match self {
+            Outcome::Loss => Outcome::Win,
+            Outcome::Draw => Outcome::Draw,
+            Outcome::Win => Outcome::Loss,
+        }
+    }
+}
+
+/// Solves positions by exhaustive search, caching every position it visits.
+#[derive(Default)]
+pub struct Evaluator {
+    cache: HashMap<(Board, Piece), Outcome>,
+}
+
+impl Evaluator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Outcome for `turn`, the player to move, if both players play perfectly.
+    pub fn evaluate(&mut self, board: &Board, turn: Piece) -> Outcome {
+        if let Some(&outcome) = self.cache.get(&(*board, turn)) {
+            return outcome;
+        }
+
+        // A finished board can only have been won by the player who just moved.
+        let outcome = match board.check_end(turn.other()) {
+            GameState::Win(_) => Outcome::Loss,
+            GameState::Stalemate => Outcome::Draw,
+            GameState::Playing => self
+                .evaluate_moves(board, turn)
+                .into_iter()
+                .map(|(_, outcome)| outcome)
+                .max()
+                .unwrap_or(Outcome::Draw),
+        };
+
+        self.cache.insert((*board, turn), outcome);
+        outcome
+    }
+
+    /// Outcome for `turn` of playing on each empty cell.
+    pub fn evaluate_moves(&mut self, board: &Board, turn: Piece) -> Vec<((usize, usize), Outcome)> {
+        let mut moves = Vec::new();
+        for idx in board.legal_moves() {
+            let mut next = *board;
+            next[idx] = Some(turn);
+            let outcome = self.evaluate(&next, turn.other()).flip();
+            moves.push((idx, outcome));
+        }
+
+        moves
+    }
+
+    /// Every move that keeps the best outcome reachable for `turn`.
+    pub fn best_moves(&mut self, board: &Board, turn: Piece) -> Vec<(usize, usize)> {
+        let moves = self.evaluate_moves(board, turn);
+        let Some(best) = moves.iter().map(|&(_, outcome)| outcome).max() else {
+            return Vec::new();
+        };
+
+        moves
+            .into_iter()
+            .filter(|&(_, outcome)| outcome == best)
+            .map(|(idx, _)| idx)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn empty_board_is_a_draw() {
+        let mut eval = Evaluator::new();
+        assert_eq!(Outcome::Draw, eval.evaluate(&Board::new(), Piece::X));
+        assert_eq!(9, eval.best_moves(&Board::new(), Piece::X).len());
+    }
+
+    #[test]
+    fn finds_winning_move() {
+        let mut eval = Evaluator::new();
+        let board = Board::from_str("x x -  o o -  - - -").unwrap();
+
+        assert_eq!(Outcome::Win, eval.evaluate(&board, Piece::X));
+        assert_eq!(vec![(0, 2)], eval.best_moves(&board, Piece::X));
+        assert_eq!(Outcome::Win, eval.evaluate(&board, Piece::O));
+        assert!(eval.best_moves(&board, Piece::O).contains(&(1, 2)));
+    }
+
+    #[test]
+    fn annotates_every_empty_cell() {
+        let mut eval = Evaluator::new();
+        let board = Board::from_str("x - -  - o -  - - -").unwrap();
+        let moves = eval.evaluate_moves(&board, Piece::X);
+
+        assert_eq!(7, moves.len());
+        assert!(moves.iter().all(|&(_, outcome)| outcome != Outcome::Win));
+    }
+
+    #[test]
+    fn finished_board() {
+        let mut eval = Evaluator::new();
+        let board = Board::from_str("x x x  o o -  - - -").unwrap();
+        assert_eq!(Outcome::Loss, eval.evaluate(&board, Piece::O));
+        assert!(eval.best_moves(&board, Piece::O).is_empty());
+    }
+}
@@ -1,3 +1,9 @@
+pub mod bitboard;
 pub mod board;
+pub mod eval;
+pub mod perft;
 pub mod piece;
+pub mod position;
 pub mod state;
+pub mod symmetry;
+pub mod zobrist;
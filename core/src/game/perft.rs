@@ -0,0 +1,77 @@
+//! Game tree counters, handy to check move generation against known totals.
+
+use super::{board::Board, piece::Piece};
+
+/// Size of the game tree below a position.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TreeSize {
+    /// Positions in the tree, counting the root.
+    pub nodes: u64,
+    /// Finished games, that is move sequences ending in a win or a stalemate.
+    pub games: u64,
+}
+
+/// Walks every game reachable from `board` with `turn` to move.
+pub fn tree_size(board: &mut Board, turn: Piece) -> TreeSize {
+    let moves = board.legal_moves();
+    if moves.is_empty() {
+        return TreeSize { nodes: 1, games: 1 };
+    }
+
+    let mut size = TreeSize { nodes: 1, games: 0 };
+    for idx in moves {
+        board.make_move(idx, turn).unwrap();
+        let child = tree_size(board, turn.other());
+        board.unmake_move(idx).unwrap();
+
+        size.nodes += child.nodes;
+        size.games += child.games;
+    }
+
+    size
+}
+
+/// Number of move sequences of exactly `depth` moves from `board`.
+pub fn perft(board: &mut Board, turn: Piece, depth: usize) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    let mut nodes = 0;
+    for idx in board.legal_moves() {
+        board.make_move(idx, turn).unwrap();
+        nodes += perft(board, turn.other(), depth - 1);
+        board.unmake_move(idx).unwrap();
+    }
+
+    nodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_tree() {
+        let mut board = Board::new();
+        let size = tree_size(&mut board, Piece::X);
+
+        assert_eq!(549_946, size.nodes);
+        assert_eq!(255_168, size.games);
+        assert_eq!(Board::new(), board);
+    }
+
+    #[test]
+    fn perft_by_depth() {
+        let expected = [
+            1, 9, 72, 504, 3_024, 15_120, 54_720, 148_176, 200_448, 127_872, 0,
+        ];
+
+        let mut board = Board::new();
+        for (depth, &nodes) in expected.iter().enumerate() {
+            assert_eq!(nodes, perft(&mut board, Piece::X, depth));
+        }
+
+        assert_eq!(549_946, expected.iter().sum::<u64>());
+    }
+}
@@ -1,8 +1,11 @@
 use serde::{Deserialize, Serialize};
 use std::{fmt::Display, str::FromStr};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(
+    Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
 pub enum Piece {
+    #[default]
     X,
     O,
 }
@@ -23,12 +26,6 @@ impl Piece {
     }
 }
 
-impl Default for Piece {
-    fn default() -> Self {
-        Self::X
-    }
-}
-
 impl FromStr for Piece {
     type Err = &'static str;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -0,0 +1,262 @@
+//! A FEN-like notation for positions.
+//!
+//! A position is written as four space separated fields: the rows from top to bottom
+//! joined by `/`, the side to move, the board size and the win length, as in
+//! `X1O/1X1/3 O 3 3`. Inside a row `X` and `O` are pieces and a digit is a run of that
+//! many empty cells.
+//!
+//! Only the canonical spelling is accepted, the one positions are displayed with, and
+//! only positions a game can reach without being over already.
+
+use super::{board::Board, piece::Piece, state::GameState};
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+const SIZE: usize = 3;
+const WIN_LENGTH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Position {
+    pub board: Board,
+    pub turn: Piece,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PositionError {
+    FieldCount(usize),
+    RowCount(usize),
+    RowWidth { row: usize, width: usize },
+    InvalidChar(char),
+    InvalidTurn(String),
+    InvalidNumber(String),
+    UnsupportedSize(usize),
+    UnsupportedWinLength(usize),
+    PieceCount,
+    TurnMismatch,
+    Finished,
+    NotCanonical(String),
+}
+
+impl Display for PositionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use PositionError as E;
+        match self {
+            E::FieldCount(n) => write!(f, "Expected 4 fields, found {n}"),
+            E::RowCount(n) => write!(f, "Expected {SIZE} rows, found {n}"),
+            E::RowWidth { row, width } => {
+                write!(f, "Row {} has {width} cells, expected {SIZE}", row + 1)
+            }
+            E::InvalidChar(c) => write!(f, "Invalid character `{c}` in row"),
+            E::InvalidTurn(s) => write!(f, "Invalid side to move `{s}`"),
+            E::InvalidNumber(s) => write!(f, "Invalid number `{s}`"),
+            E::UnsupportedSize(n) => write!(f, "Unsupported board size {n}"),
+            E::UnsupportedWinLength(n) => write!(f, "Unsupported win length {n}"),
+            E::PieceCount => write!(f, "Piece counts differ by more than one"),
+            E::TurnMismatch => write!(f, "The side with more pieces is to move"),
+            E::Finished => write!(f, "The game is already over"),
+            E::NotCanonical(s) => write!(f, "Not written canonically, expected `{s}`"),
+        }
+    }
+}
+
+impl Error for PositionError {}
+
+impl Position {
+    pub fn new(board: Board, turn: Piece) -> Self {
+        Self { board, turn }
+    }
+
+    /// The player that made the first move of the game leading to this position.
+    pub fn started(&self) -> Piece {
+        let (x, o) = piece_counts(&self.board);
+        match x.cmp(&o) {
+            Ordering::Equal => self.turn,
+            _ => self.turn.other(),
+        }
+    }
+}
+
+fn piece_counts(board: &Board) -> (usize, usize) {
+    let mut counts = (0, 0);
+    for i in 0..SIZE {
+        for j in 0..SIZE {
+            match board[(i, j)] {
+                Some(Piece::X) => counts.0 += 1,
+                Some(Piece::O) => counts.1 += 1,
+                None => {}
+            }
+        }
+    }
+
+    counts
+}
+
+fn parse_number(s: &str) -> Result<usize, PositionError> {
+    s.parse()
+        .map_err(|_| PositionError::InvalidNumber(s.to_string()))
+}
+
+fn parse_rows(s: &str) -> Result<Board, PositionError> {
+    let rows: Vec<&str> = s.split('/').collect();
+    if rows.len() != SIZE {
+        return Err(PositionError::RowCount(rows.len()));
+    }
+
+    let mut board = Board::new();
+    for (i, row) in rows.into_iter().enumerate() {
+        let mut j = 0;
+        for c in row.chars() {
+            let (piece, run) = match c {
+                '1'..='9' => (None, c as usize - '0' as usize),
+                'X' => (Some(Piece::X), 1),
+                'O' => (Some(Piece::O), 1),
+                _ => return Err(PositionError::InvalidChar(c)),
+            };
+
+            if j + run <= SIZE {
+                for k in j..j + run {
+                    board[(i, k)] = piece;
+                }
+            }
+
+            j += run;
+        }
+
+        if j != SIZE {
+            return Err(PositionError::RowWidth { row: i, width: j });
+        }
+    }
+
+    Ok(board)
+}
+
+impl FromStr for Position {
+    type Err = PositionError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        let &[rows, turn, size, win_length] = fields.as_slice() else {
+            return Err(PositionError::FieldCount(fields.len()));
+        };
+
+        let size = parse_number(size)?;
+        if size != SIZE {
+            return Err(PositionError::UnsupportedSize(size));
+        }
+
+        let win_length = parse_number(win_length)?;
+        if win_length != WIN_LENGTH {
+            return Err(PositionError::UnsupportedWinLength(win_length));
+        }
+
+        let board = parse_rows(rows)?;
+        let turn = match turn {
+            "X" => Piece::X,
+            "O" => Piece::O,
+            _ => return Err(PositionError::InvalidTurn(turn.to_string())),
+        };
+
+        let (x, o) = piece_counts(&board);
+        if x.abs_diff(o) > 1 {
+            return Err(PositionError::PieceCount);
+        }
+
+        if (x > o && turn == Piece::X) || (o > x && turn == Piece::O) {
+            return Err(PositionError::TurnMismatch);
+        }
+
+        if board.check_end(turn) != GameState::Playing {
+            return Err(PositionError::Finished);
+        }
+
+        // Split runs like `12`, padded numbers or extra spaces.
+        let pos = Self { board, turn };
+        let canonical = pos.to_string();
+        if canonical != s {
+            return Err(PositionError::NotCanonical(canonical));
+        }
+
+        Ok(pos)
+    }
+}
+
+impl Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {SIZE} {WIN_LENGTH}", self.board, self.turn)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let pos = Position::from_str("X1O/1X1/3 O 3 3").unwrap();
+        assert_eq!(Piece::O, pos.turn);
+        assert_eq!(Some(Piece::X), pos.board[(0, 0)]);
+        assert_eq!(None, pos.board[(0, 1)]);
+        assert_eq!(Some(Piece::O), pos.board[(0, 2)]);
+        assert_eq!(Some(Piece::X), pos.board[(1, 1)]);
+        assert_eq!(Piece::X, pos.started());
+
+        let pos = Position::from_str("3/3/3 O 3 3").unwrap();
+        assert_eq!(Board::new(), pos.board);
+        assert_eq!(Piece::O, pos.started());
+    }
+
+    #[test]
+    fn round_trip() {
+        for s in [
+            "3/3/3 X 3 3",
+            "X1O/1X1/3 O 3 3",
+            "XOX/OXO/O2 X 3 3",
+            "2O/1X1/X2 O 3 3",
+        ] {
+            let pos = Position::from_str(s).unwrap();
+            assert_eq!(s, pos.to_string());
+            assert_eq!(pos, Position::from_str(&pos.to_string()).unwrap());
+        }
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        use PositionError as E;
+        let err = |s| Position::from_str(s).unwrap_err();
+
+        assert_eq!(E::FieldCount(1), err("3/3/3"));
+        assert_eq!(E::FieldCount(5), err("3/3/3 X 3 3 3"));
+        assert_eq!(E::RowCount(2), err("3/3 X 3 3"));
+        assert_eq!(E::RowWidth { row: 1, width: 4 }, err("3/X3/3 X 3 3"));
+        assert_eq!(E::RowWidth { row: 2, width: 2 }, err("3/3/XO X 3 3"));
+        assert_eq!(E::InvalidChar('-'), err("3/-2/3 X 3 3"));
+        assert_eq!(E::InvalidTurn("Z".into()), err("3/3/3 Z 3 3"));
+        assert_eq!(E::InvalidNumber("three".into()), err("3/3/3 X three 3"));
+        assert_eq!(E::UnsupportedSize(4), err("4/4/4/4 X 4 3"));
+        assert_eq!(E::UnsupportedWinLength(2), err("3/3/3 X 3 2"));
+        assert_eq!(E::PieceCount, err("XX1/3/3 O 3 3"));
+        assert_eq!(E::TurnMismatch, err("X2/3/3 X 3 3"));
+        assert_eq!(E::InvalidChar('x'), err("x2/3/3 O 3 3"));
+        assert_eq!(E::InvalidTurn("o".into()), err("3/3/3 o 3 3"));
+    }
+
+    #[test]
+    fn rejects_finished_games() {
+        let err = |s| Position::from_str(s).unwrap_err();
+        assert_eq!(PositionError::Finished, err("XXX/OOO/3 X 3 3"));
+        assert_eq!(PositionError::Finished, err("XXX/OO1/3 O 3 3"));
+        assert_eq!(PositionError::Finished, err("XOX/OXO/OXO X 3 3"));
+    }
+
+    #[test]
+    fn rejects_non_canonical() {
+        let err = |s| Position::from_str(s).unwrap_err();
+        let canonical = |s: &str| PositionError::NotCanonical(s.to_string());
+        assert_eq!(canonical("3/3/3 X 3 3"), err("12/3/3 X 3 3"));
+        assert_eq!(canonical("3/3/3 X 3 3"), err("111/3/3 X 3 3"));
+        assert_eq!(canonical("3/3/3 X 3 3"), err("3/3/3  X 3 3"));
+        assert_eq!(canonical("3/3/3 X 3 3"), err("3/3/3 X 03 3"));
+        assert_eq!(canonical("X2/3/3 O 3 3"), err(" X2/3/3 O 3 3"));
+    }
+}
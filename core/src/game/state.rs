@@ -1,7 +1,7 @@
 use super::piece::Piece;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
 pub enum GameState {
     Playing,
     Win(Piece),
@@ -0,0 +1,181 @@
+//! The 8 symmetries of the board and a canonical form under them.
+//!
+//! Symmetric positions share their outcome and best moves, so tables keyed by the
+//! canonical form only need to store one of them. Anything computed on the canonical
+//! board maps back to the original one through [`Symmetry::inverse`].
+
+use super::{board::Board, zobrist};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    Identity,
+    /// Clockwise rotation by 90 degrees.
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Mirror around the vertical axis, swapping left and right.
+    FlipHorizontal,
+    /// Mirror around the horizontal axis, swapping top and bottom.
+    FlipVertical,
+    /// Mirror around the top left to bottom right diagonal.
+    FlipDiagonal,
+    /// Mirror around the top right to bottom left diagonal.
+    FlipAntiDiagonal,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipHorizontal,
+        Symmetry::FlipVertical,
+        Symmetry::FlipDiagonal,
+        Symmetry::FlipAntiDiagonal,
+    ];
+
+    /// Where the cell `idx` ends up after the transform.
+    pub fn apply(self, (i, j): (usize, usize)) -> (usize, usize) {
+        use Symmetry as S;
+        match self {
+            S::Identity => (i, j),
+            S::Rotate90 => (j, 2 - i),
+            S::Rotate180 => (2 - i, 2 - j),
+            S::Rotate270 => (2 - j, i),
+            S::FlipHorizontal => (i, 2 - j),
+            S::FlipVertical => (2 - i, j),
+            S::FlipDiagonal => (j, i),
+            S::FlipAntiDiagonal => (2 - j, 2 - i),
+        }
+    }
+
+    pub fn inverse(self) -> Self {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            other => other,
+        }
+    }
+
+    pub fn apply_board(self, board: &Board) -> Board {
+        let mut transformed = Board::new();
+        for i in 0..3 {
+            for j in 0..3 {
+                transformed[self.apply((i, j))] = board[(i, j)];
+            }
+        }
+
+        transformed
+    }
+}
+
+/// Base 3 encoding of a board, used to order boards.
+fn code(board: &Board) -> u32 {
+    (0..9).fold(0, |code, k| {
+        let cell = match board[(k / 3, k % 3)] {
+            None => 0,
+            Some(piece) => piece as u32 + 1,
+        };
+
+        code * 3 + cell
+    })
+}
+
+/// The smallest of the 8 transforms of `board` and the symmetry that produces it.
+pub fn canonical(board: &Board) -> (Board, Symmetry) {
+    Symmetry::ALL
+        .into_iter()
+        .map(|sym| (sym.apply_board(board), sym))
+        .min_by_key(|(board, _)| code(board))
+        .unwrap()
+}
+
+/// Zobrist hash of the canonical form, equal for every symmetric board.
+pub fn canonical_hash(board: &Board) -> u64 {
+    zobrist::hash(&canonical(board).0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::eval::Evaluator;
+    use crate::game::piece::Piece;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    fn reachable(board: Board, turn: Piece, seen: &mut HashSet<Board>) {
+        if !seen.insert(board) || board.check_end(turn.other()).is_end() {
+            return;
+        }
+
+        for k in 0..9 {
+            let idx = (k / 3, k % 3);
+            if board[idx].is_none() {
+                let mut next = board;
+                next[idx] = Some(turn);
+                reachable(next, turn.other(), seen);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse() {
+        for sym in Symmetry::ALL {
+            for k in 0..9 {
+                let idx = (k / 3, k % 3);
+                assert_eq!(idx, sym.inverse().apply(sym.apply(idx)));
+            }
+        }
+    }
+
+    #[test]
+    fn rotation() {
+        let board = Board::from_str("x o -  - - -  - - -").unwrap();
+        let rotated = Symmetry::Rotate90.apply_board(&board);
+        assert_eq!(Board::from_str("- - x  - - o  - - -").unwrap(), rotated);
+    }
+
+    #[test]
+    fn symmetric_boards_share_canonical_form() {
+        let board = Board::from_str("x o -  - x -  - - o").unwrap();
+        let hash = canonical_hash(&board);
+
+        for sym in Symmetry::ALL {
+            let transformed = sym.apply_board(&board);
+            assert_eq!(hash, canonical_hash(&transformed));
+
+            let (canon, to_canon) = canonical(&transformed);
+            assert_eq!(canon, to_canon.apply_board(&transformed));
+            assert_eq!(transformed, to_canon.inverse().apply_board(&canon));
+        }
+    }
+
+    #[test]
+    fn reachable_positions() {
+        let mut seen = HashSet::new();
+        reachable(Board::new(), Piece::X, &mut seen);
+        assert_eq!(5478, seen.len());
+
+        let canonical: HashSet<u64> = seen.iter().map(canonical_hash).collect();
+        assert_eq!(765, canonical.len());
+    }
+
+    #[test]
+    fn best_moves_map_back() {
+        let mut eval = Evaluator::new();
+        let board = Board::from_str("- - x  - o -  - - -").unwrap();
+        let (canon, sym) = canonical(&board);
+
+        let mut expected = eval.best_moves(&board, Piece::X);
+        let mut mapped: Vec<_> = eval
+            .best_moves(&canon, Piece::X)
+            .into_iter()
+            .map(|idx| sym.inverse().apply(idx))
+            .collect();
+
+        expected.sort();
+        mapped.sort();
+        assert_eq!(expected, mapped);
+    }
+}
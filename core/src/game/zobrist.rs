@@ -0,0 +1,111 @@
+//! Zobrist hashing of boards.
+//!
+//! The hash of a board is the xor of one key per occupied cell, so placing or removing
+//! a piece updates it with a single [`toggle`].
+
+use super::{board::Board, piece::Piece};
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (state, z ^ (z >> 31))
+}
+
+/// One key per cell and piece, generated at compile time so hashes are stable.
+const KEYS: [[u64; 2]; 9] = {
+    let mut keys = [[0; 2]; 9];
+    let mut state = 0x7469_632d_7461_632d;
+    let mut k = 0;
+
+    while k < 18 {
+        let (next, key) = splitmix64(state);
+        keys[k / 2][k % 2] = key;
+        state = next;
+        k += 1;
+    }
+
+    keys
+};
+
+/// Key xored into a hash to mark that `O` is to move.
+pub const SIDE: u64 = 0x6f2d_746f_2d6d_6f76;
+
+fn piece_index(piece: Piece) -> usize {
+    match piece {
+        Piece::X => 0,
+        Piece::O => 1,
+    }
+}
+
+pub fn key((i, j): (usize, usize), piece: Piece) -> u64 {
+    KEYS[i * 3 + j][piece_index(piece)]
+}
+
+/// Updates `hash` after placing `piece` on `idx`, or after removing it from there.
+pub fn toggle(hash: u64, idx: (usize, usize), piece: Piece) -> u64 {
+    hash ^ key(idx, piece)
+}
+
+pub fn hash(board: &Board) -> u64 {
+    let mut hash = 0;
+    for i in 0..3 {
+        for j in 0..3 {
+            if let Some(piece) = board[(i, j)] {
+                hash = toggle(hash, (i, j), piece);
+            }
+        }
+    }
+
+    hash
+}
+
+/// Hash of a board together with the side to move.
+pub fn hash_with_turn(board: &Board, turn: Piece) -> u64 {
+    match turn {
+        Piece::X => hash(board),
+        Piece::O => hash(board) ^ SIDE,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    #[test]
+    fn keys_are_distinct() {
+        let keys: HashSet<u64> = KEYS.iter().flatten().copied().chain([SIDE]).collect();
+        assert_eq!(19, keys.len());
+        assert!(!keys.contains(&0));
+    }
+
+    #[test]
+    fn incremental() {
+        let mut board = Board::new();
+        let mut h = hash(&board);
+        assert_eq!(0, h);
+
+        for (idx, piece) in [((1, 1), Piece::X), ((0, 0), Piece::O), ((2, 1), Piece::X)] {
+            board[idx] = Some(piece);
+            h = toggle(h, idx, piece);
+            assert_eq!(hash(&board), h);
+        }
+
+        board[(0, 0)] = None;
+        h = toggle(h, (0, 0), Piece::O);
+        assert_eq!(hash(&board), h);
+        assert_eq!(hash(&Board::from_str("- - -  - x -  - x -").unwrap()), h);
+    }
+
+    #[test]
+    fn turn_changes_hash() {
+        let board = Board::from_str("x - -  - - -  - - -").unwrap();
+        assert_ne!(
+            hash_with_turn(&board, Piece::X),
+            hash_with_turn(&board, Piece::O)
+        );
+    }
+}
@@ -1,6 +1,11 @@
+pub mod error;
 pub mod game;
+pub mod notation;
+pub mod record;
 pub mod request;
 pub mod response;
+pub mod stream;
+pub mod tls;
 
 use std::io::{self, Read, Write};
 use std::mem;
@@ -12,15 +17,26 @@ macro_rules! io_err {
     };
 }
 
-pub fn read_str<R: Read>(stream: &mut R) -> io::Result<String> {
+/// Largest frame [`read_bytes`] accepts, anything bigger is treated as garbage.
+pub const MAX_FRAME_LEN: usize = 1 << 16;
+
+pub fn read_bytes<R: Read>(stream: &mut R) -> io::Result<Vec<u8>> {
     let mut size = [0; mem::size_of::<usize>()];
     stream.read_exact(&mut size)?;
 
     // Get amount of bytes to read from stream.
     let size = usize::from_be_bytes(size);
-    let mut data = vec![0; size];
+    if size > MAX_FRAME_LEN {
+        return Err(io_err!("Frame too large"));
+    }
 
+    let mut data = vec![0; size];
     stream.read_exact(&mut data)?;
+    Ok(data)
+}
+
+pub fn read_str<R: Read>(stream: &mut R) -> io::Result<String> {
+    let data = read_bytes(stream)?;
     String::from_utf8(data).map_err(|_| io_err!("Failed to parse string"))
 }
 
@@ -28,3 +44,27 @@ pub fn write_str<W: Write>(stream: &mut W, s: &str) -> io::Result<()> {
     stream.write_all(&s.len().to_be_bytes())?;
     stream.write_all(s.as_bytes())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames() {
+        let mut buf = Vec::new();
+        write_str(&mut buf, "hello").unwrap();
+        write_str(&mut buf, "").unwrap();
+
+        let mut stream = buf.as_slice();
+        assert_eq!("hello", read_str(&mut stream).unwrap());
+        assert_eq!("", read_str(&mut stream).unwrap());
+        assert!(read_str(&mut stream).is_err());
+    }
+
+    #[test]
+    fn oversized_frame() {
+        let mut buf = (MAX_FRAME_LEN + 1).to_be_bytes().to_vec();
+        buf.extend([b'x'; 16]);
+        assert!(read_bytes(&mut buf.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,138 @@
+//! Textual notation for moves.
+//!
+//! Cells are written algebraically, a file letter `a`..`c` (left to right) followed by a
+//! rank `1`..`3` (bottom to top), so `a1` is the bottom left corner. They can also be
+//! written numpad-style as a single digit `1`..`9`, laid out like a keypad.
+//!
+//! Move sequences are numbered in pairs, `1. b2 a1 2. c3`.
+
+use std::error::Error;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotationError {
+    InvalidCell(String),
+}
+
+impl Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NotationError::InvalidCell(s) => write!(f, "Invalid cell `{s}`"),
+        }
+    }
+}
+
+impl Error for NotationError {}
+
+/// Parses a cell in either algebraic (`b2`) or numpad (`5`) notation.
+pub fn parse_cell(s: &str) -> Result<(usize, usize), NotationError> {
+    match *s.to_lowercase().as_bytes() {
+        [n @ b'1'..=b'9'] => {
+            let n = (n - b'1') as usize;
+            Ok((2 - n / 3, n % 3))
+        }
+
+        [file @ b'a'..=b'c', rank @ b'1'..=b'3'] => {
+            Ok(((b'3' - rank) as usize, (file - b'a') as usize))
+        }
+
+        _ => Err(NotationError::InvalidCell(s.to_string())),
+    }
+}
+
+/// Writes a cell in algebraic notation.
+///
+/// # Panics
+///
+/// If `idx` is outside of the board.
+pub fn format_cell((i, j): (usize, usize)) -> String {
+    assert!(i < 3 && j < 3, "Cell ({i}, {j}) is outside of the board");
+    format!("{}{}", (b'a' + j as u8) as char, 3 - i)
+}
+
+/// Writes a cell as its numpad digit.
+///
+/// # Panics
+///
+/// If `idx` is outside of the board.
+pub fn format_cell_numpad((i, j): (usize, usize)) -> char {
+    assert!(i < 3 && j < 3, "Cell ({i}, {j}) is outside of the board");
+    (b'1' + ((2 - i) * 3 + j) as u8) as char
+}
+
+/// Parses a sequence of cells separated by whitespace, skipping move numbers like `1.`.
+pub fn parse_moves(s: &str) -> Result<Vec<(usize, usize)>, NotationError> {
+    s.split_whitespace()
+        .filter(|token| !token.ends_with('.'))
+        .map(parse_cell)
+        .collect()
+}
+
+/// Writes a sequence of moves numbered in pairs.
+pub fn format_moves(moves: &[(usize, usize)]) -> String {
+    moves
+        .chunks(2)
+        .enumerate()
+        .map(|(n, pair)| format_pair(n + 1, pair))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Writes the `n`th pair of moves, as in `3. a1 c3`.
+pub fn format_pair(n: usize, pair: &[(usize, usize)]) -> String {
+    let cells: Vec<String> = pair.iter().map(|&idx| format_cell(idx)).collect();
+    format!("{n}. {}", cells.join(" "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algebraic() {
+        assert_eq!(Ok((2, 0)), parse_cell("a1"));
+        assert_eq!(Ok((1, 1)), parse_cell("b2"));
+        assert_eq!(Ok((0, 2)), parse_cell("C3"));
+        assert_eq!(Ok((0, 0)), parse_cell("a3"));
+
+        assert!(parse_cell("d1").is_err());
+        assert!(parse_cell("a4").is_err());
+        assert!(parse_cell("a0").is_err());
+        assert!(parse_cell("b22").is_err());
+        assert!(parse_cell("").is_err());
+    }
+
+    #[test]
+    fn numpad() {
+        assert_eq!(Ok((2, 0)), parse_cell("1"));
+        assert_eq!(Ok((1, 1)), parse_cell("5"));
+        assert_eq!(Ok((0, 0)), parse_cell("7"));
+        assert_eq!(Ok((0, 2)), parse_cell("9"));
+
+        assert!(parse_cell("0").is_err());
+        assert!(parse_cell("10").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        for i in 0..3 {
+            for j in 0..3 {
+                assert_eq!(Ok((i, j)), parse_cell(&format_cell((i, j))));
+                let numpad = format_cell_numpad((i, j)).to_string();
+                assert_eq!(Ok((i, j)), parse_cell(&numpad));
+            }
+        }
+    }
+
+    #[test]
+    fn sequences() {
+        let moves = vec![(1, 1), (2, 0), (0, 2)];
+        assert_eq!("1. b2 a1 2. c3", format_moves(&moves));
+        assert_eq!(Ok(moves.clone()), parse_moves("1. b2 a1 2. c3"));
+        assert_eq!(Ok(moves), parse_moves("5 1\n9"));
+
+        assert_eq!("", format_moves(&[]));
+        assert_eq!(Ok(vec![]), parse_moves(""));
+        assert!(parse_moves("1. b2 z9").is_err());
+    }
+}
@@ -0,0 +1,18 @@
+use super::game::piece::Piece;
+use serde::{Deserialize, Serialize};
+
+/// A finished game, as the server stores it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct GameRecord {
+    pub room: String,
+    pub x: String,
+    pub o: String,
+    /// Moves in algebraic notation, like "1. b2 a1 2. c3".
+    pub moves: String,
+    /// `None` for a draw.
+    pub winner: Option<Piece>,
+    /// Whether the loser ran out of time rather than being beaten on the board.
+    pub on_time: bool,
+    /// Seconds since the Unix epoch.
+    pub finished: u64,
+}
@@ -2,6 +2,26 @@ use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Request {
+    /// First request of a player's connection, takes a seat in `room` as `name`.
+    Join {
+        room: String,
+        name: String,
+    },
+    /// First request of a connection that only watches `room`.
+    Spectate {
+        room: String,
+    },
     Disconnect,
-    Play { idx: (usize, usize) },
+    Play {
+        idx: (usize, usize),
+    },
+    Takeback,
+    AcceptTakeback,
+    DeclineTakeback,
+    /// Gives the game up, the opponent wins it.
+    Resign,
+    /// Says something to everyone in the room.
+    Chat(String),
+    /// Keeps the connection alive, answered with a `Pong` carrying the same nonce.
+    Ping(u64),
 }
@@ -1,34 +1,58 @@
+use super::error::ErrorCode;
 use super::game::{board::Board, piece::Piece, state::GameState};
 use serde::{Deserialize, Serialize};
-use std::fmt::{self, Display};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Response {
     Valid {
         piece: Piece,
         idx: (usize, usize),
         state: GameState,
+        turn: Piece,
+        /// Milliseconds left to X and O, in timed games.
+        #[serde(default)]
+        clock: Option<[u64; 2]>,
     },
-    Invalid(String),
+    Invalid(ErrorCode),
     Init {
         board: Board,
         piece: Piece,
+        turn: Piece,
+        hints: bool,
+        opponent: Option<String>,
+    },
+    /// Answers `Spectate` with the state of the room.
+    Watch {
+        board: Board,
+        turn: Piece,
+        players: Vec<(Piece, String)>,
+    },
+    Connect {
+        piece: Piece,
+        name: String,
     },
-    Connect,
     Disconnect(Piece),
-}
-
-impl Display for Response {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        use Response as R;
-        let s = match self {
-            R::Valid { .. } => "Valid move".to_string(),
-            R::Invalid(msg) => format!("Invalid move: {msg}"),
-            R::Init { .. } => "Init".to_string(),
-            R::Connect => "The other player connected".to_string(),
-            R::Disconnect(piece) => format!("Player `{piece}` disconnected"),
-        };
-
-        write!(f, "{s}")
-    }
+    TakebackOffer(Piece),
+    TakebackDeclined,
+    Takeback {
+        board: Board,
+        turn: Piece,
+    },
+    Pong(u64),
+    /// `piece` ran out of time and lost, the next game starts with `turn` to move.
+    Timeout {
+        piece: Piece,
+        turn: Piece,
+    },
+    /// `piece` gave up, the next game starts with `turn` to move.
+    Resigned {
+        piece: Piece,
+        turn: Piece,
+    },
+    /// `name`, playing `piece`, said `text`.
+    Chat {
+        piece: Piece,
+        name: String,
+        text: String,
+    },
 }
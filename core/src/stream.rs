@@ -0,0 +1,130 @@
+use crate::tls::TlsStream;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpStream};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// Prefix of Unix socket addresses, like `unix:/tmp/tictactoe.sock`.
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// A connection between a client and the server, encrypted or not.
+pub enum Stream {
+    Tcp(TcpStream),
+    Tls(TlsStream),
+    /// A connection from the same machine, never encrypted.
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Stream {
+    /// Connects to the Unix socket at `path`.
+    pub fn unix(path: &Path) -> io::Result<Self> {
+        #[cfg(unix)]
+        return UnixStream::connect(path).map(Stream::Unix);
+
+        #[cfg(not(unix))]
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Can't connect to {}, no Unix sockets here", path.display()),
+        ));
+    }
+
+    /// Another handle to the same connection, to write to it from another thread.
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Stream::Tcp(tcp) => tcp.try_clone().map(Stream::Tcp),
+            Stream::Tls(tls) => tls.try_clone().map(Stream::Tls),
+            #[cfg(unix)]
+            Stream::Unix(unix) => unix.try_clone().map(Stream::Unix),
+        }
+    }
+
+    /// Peers on Unix sockets are on this machine, so they all count as `127.0.0.1:0`.
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Tcp(tcp) => tcp.peer_addr(),
+            Stream::Tls(tls) => tls.tcp().peer_addr(),
+            #[cfg(unix)]
+            Stream::Unix(_) => Ok((Ipv4Addr::LOCALHOST, 0).into()),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(tcp) => tcp.set_read_timeout(timeout),
+            Stream::Tls(tls) => tls.tcp().set_read_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(unix) => unix.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Tcp(tcp) => tcp.set_write_timeout(timeout),
+            Stream::Tls(tls) => tls.tcp().set_write_timeout(timeout),
+            #[cfg(unix)]
+            Stream::Unix(unix) => unix.set_write_timeout(timeout),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Tcp(tcp) => tcp.shutdown(how),
+            Stream::Tls(tls) => tls.tcp().shutdown(how),
+            #[cfg(unix)]
+            Stream::Unix(unix) => unix.shutdown(how),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(tcp: TcpStream) -> Self {
+        Stream::Tcp(tcp)
+    }
+}
+
+impl From<TlsStream> for Stream {
+    fn from(tls: TlsStream) -> Self {
+        Stream::Tls(tls)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(unix: UnixStream) -> Self {
+        Stream::Unix(unix)
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(tcp) => tcp.read(buf),
+            Stream::Tls(tls) => tls.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(unix) => unix.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Tcp(tcp) => tcp.write(buf),
+            Stream::Tls(tls) => tls.write(buf),
+            #[cfg(unix)]
+            Stream::Unix(unix) => unix.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Tcp(tcp) => tcp.flush(),
+            Stream::Tls(tls) => tls.flush(),
+            #[cfg(unix)]
+            Stream::Unix(unix) => unix.flush(),
+        }
+    }
+}
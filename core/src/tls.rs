@@ -0,0 +1,448 @@
+//! TLS for connections between clients and the server, using rustls.
+//!
+//! The server proves who it is with an [`Identity`], a certificate and its key. Clients
+//! check it against the certificate authorities they [`Trust`], or against the
+//! [`Fingerprint`] of the one certificate they expect.
+
+use crate::io_err;
+use ring::digest::{digest, SHA256};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring as provider, verify_tls12_signature, verify_tls13_signature};
+use rustls::crypto::{CryptoProvider, WebPkiSupportedAlgorithms};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, Connection, DigitallySignedStruct};
+use rustls::{RootCertStore, ServerConfig, ServerConnection, SignatureScheme};
+use std::fmt::{self, Display};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Name the certificates made by [`Identity::self_signed`] are valid for.
+pub const LOCALHOST: &str = "localhost";
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(provider::default_provider())
+}
+
+fn invalid(e: impl Display) -> io::Error {
+    io_err!(e.to_string())
+}
+
+/// A certificate chain and its private key, both PEM encoded.
+#[derive(Debug, Clone)]
+pub struct Identity {
+    pub cert: String,
+    pub key: String,
+}
+
+impl Identity {
+    pub fn load(cert: &Path, key: &Path) -> io::Result<Self> {
+        let read = |path: &Path| {
+            fs::read_to_string(path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {e}", path.display())))
+        };
+
+        Ok(Self {
+            cert: read(cert)?,
+            key: read(key)?,
+        })
+    }
+
+    /// A certificate for `localhost`, `127.0.0.1` and `::1`, signed by its own key. Only
+    /// good for testing, clients have to trust it explicitly.
+    pub fn self_signed() -> io::Result<Self> {
+        let names = [LOCALHOST, "127.0.0.1", "::1"].map(String::from).to_vec();
+        let certified = rcgen::generate_simple_self_signed(names).map_err(invalid)?;
+        Ok(Self {
+            cert: certified.cert.pem(),
+            key: certified.signing_key.serialize_pem(),
+        })
+    }
+
+    /// Writes `cert.pem` and `key.pem` to `dir`.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join("cert.pem"), &self.cert)?;
+        fs::write(dir.join("key.pem"), &self.key)
+    }
+
+    fn certs(&self) -> io::Result<Vec<CertificateDer<'static>>> {
+        let certs = CertificateDer::pem_slice_iter(self.cert.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(invalid)?;
+
+        if certs.is_empty() {
+            return Err(io_err!("No certificate found"));
+        }
+
+        Ok(certs)
+    }
+
+    /// Fingerprint of the server's own certificate, the first of the chain.
+    pub fn fingerprint(&self) -> io::Result<Fingerprint> {
+        Ok(Fingerprint::of(&self.certs()?[0]))
+    }
+
+    pub fn server_config(&self) -> io::Result<Arc<ServerConfig>> {
+        let key = PrivateKeyDer::from_pem_slice(self.key.as_bytes()).map_err(invalid)?;
+        let config = ServerConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?
+            .with_no_client_auth()
+            .with_single_cert(self.certs()?, key)
+            .map_err(invalid)?;
+
+        Ok(Arc::new(config))
+    }
+}
+
+/// SHA-256 of a certificate, written like `AB:CD:...` as `openssl x509 -fingerprint
+/// -sha256` does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    fn of(cert: &CertificateDer) -> Self {
+        let mut fingerprint = [0; 32];
+        fingerprint.copy_from_slice(digest(&SHA256, cert).as_ref());
+        Self(fingerprint)
+    }
+}
+
+impl Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (k, byte) in self.0.iter().enumerate() {
+            if k > 0 {
+                f.write_str(":")?;
+            }
+
+            write!(f, "{byte:02X}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = String;
+
+    /// Hex digits, upper or lower case, optionally separated by colons.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex: String = s.chars().filter(|&c| c != ':').collect();
+        let err = || format!("`{s}` isn't a SHA-256 fingerprint");
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+
+        let mut fingerprint = [0; 32];
+        for (k, byte) in fingerprint.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[2 * k..2 * k + 2], 16).map_err(|_| err())?;
+        }
+
+        Ok(Self(fingerprint))
+    }
+}
+
+/// Which servers a client is willing to talk to.
+#[derive(Debug, Clone)]
+pub enum Trust {
+    /// Servers with a certificate for their name signed by one of these PEM encoded
+    /// certificate authorities.
+    Ca(String),
+    /// The server with exactly this certificate, whatever its name.
+    Fingerprint(Fingerprint),
+}
+
+impl Trust {
+    pub fn client_config(&self) -> io::Result<Arc<ClientConfig>> {
+        let builder = ClientConfig::builder_with_provider(provider())
+            .with_safe_default_protocol_versions()
+            .map_err(invalid)?;
+
+        let config = match self {
+            Trust::Ca(pem) => {
+                let mut roots = RootCertStore::empty();
+                for cert in CertificateDer::pem_slice_iter(pem.as_bytes()) {
+                    roots.add(cert.map_err(invalid)?).map_err(invalid)?;
+                }
+
+                if roots.is_empty() {
+                    return Err(io_err!("No certificate authority found"));
+                }
+
+                builder.with_root_certificates(roots).with_no_client_auth()
+            }
+
+            Trust::Fingerprint(fingerprint) => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(Pinned {
+                    fingerprint: *fingerprint,
+                    algorithms: provider().signature_verification_algorithms,
+                }))
+                .with_no_client_auth(),
+        };
+
+        Ok(Arc::new(config))
+    }
+}
+
+/// Accepts the one certificate whose fingerprint it knows, still checking the server
+/// holds its key.
+#[derive(Debug)]
+struct Pinned {
+    fingerprint: Fingerprint,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+
+impl ServerCertVerifier for Pinned {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer,
+        _intermediates: &[CertificateDer],
+        _server_name: &ServerName,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if Fingerprint::of(end_entity) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(
+                "Certificate fingerprint doesn't match".to_string(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+/// An encrypted connection. Unlike rustls's own streams it can be cloned, so one thread
+/// can wait for the next frame while others write to the same connection.
+pub struct TlsStream {
+    tcp: TcpStream,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl TlsStream {
+    /// Completes the handshake as the server.
+    pub fn accept(tcp: TcpStream, config: Arc<ServerConfig>) -> io::Result<Self> {
+        let conn = ServerConnection::new(config).map_err(invalid)?;
+        Self::handshake(tcp, conn.into())
+    }
+
+    /// Completes the handshake with the server named `host`, an IP address or a name.
+    pub fn connect(tcp: TcpStream, config: Arc<ClientConfig>, host: &str) -> io::Result<Self> {
+        let name = ServerName::try_from(host.to_string()).map_err(invalid)?;
+        let conn = ClientConnection::new(config, name).map_err(invalid)?;
+        Self::handshake(tcp, conn.into())
+    }
+
+    fn handshake(mut tcp: TcpStream, mut conn: Connection) -> io::Result<Self> {
+        while conn.is_handshaking() {
+            conn.complete_io(&mut tcp)?;
+        }
+
+        Ok(Self {
+            tcp,
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            tcp: self.tcp.try_clone()?,
+            conn: Arc::clone(&self.conn),
+        })
+    }
+
+    /// The connection underneath, for its address and timeouts.
+    pub fn tcp(&self) -> &TcpStream {
+        &self.tcp
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Sends whatever rustls has to say, like records or alerts.
+    fn flush_tls(conn: &mut Connection, mut tcp: &TcpStream) -> io::Result<()> {
+        while conn.wants_write() {
+            conn.write_tls(&mut tcp)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Read for TlsStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut raw = [0; 4096];
+        loop {
+            match self.lock().reader().read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+
+            // Writers may use the connection while this one waits for the network.
+            let n = (&self.tcp).read(&mut raw)?;
+            if n == 0 {
+                return Ok(0);
+            }
+
+            let mut conn = self.lock();
+            let mut rest = &raw[..n];
+            while !rest.is_empty() {
+                conn.read_tls(&mut rest)?;
+                if let Err(e) = conn.process_new_packets() {
+                    Self::flush_tls(&mut conn, &self.tcp).ok();
+                    return Err(invalid(e));
+                }
+            }
+
+            Self::flush_tls(&mut conn, &self.tcp)?;
+        }
+    }
+}
+
+impl Write for TlsStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut conn = self.lock();
+        let n = conn.writer().write(buf)?;
+        Self::flush_tls(&mut conn, &self.tcp)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut conn = self.lock();
+        conn.writer().flush()?;
+        Self::flush_tls(&mut conn, &self.tcp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{read_str, write_str};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serves one connection with `identity`, echoing every frame.
+    fn echo(identity: &Identity) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let config = identity.server_config().unwrap();
+
+        thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let Ok(mut stream) = TlsStream::accept(tcp, config) else {
+                return;
+            };
+
+            while let Ok(s) = read_str(&mut stream) {
+                write_str(&mut stream, &s).unwrap();
+            }
+        });
+
+        address
+    }
+
+    fn connect(address: &str, trust: &Trust) -> io::Result<TlsStream> {
+        let tcp = TcpStream::connect(address)?;
+        TlsStream::connect(tcp, trust.client_config()?, LOCALHOST)
+    }
+
+    #[test]
+    fn fingerprints() {
+        let hex = "ab".repeat(32);
+        let fingerprint: Fingerprint = hex.parse().unwrap();
+        let colons = fingerprint.to_string();
+        assert_eq!(95, colons.len());
+        assert!(colons.starts_with("AB:AB:"));
+        assert_eq!(fingerprint, colons.parse().unwrap());
+
+        assert!("ab:cd".parse::<Fingerprint>().is_err());
+        assert!("zz".repeat(32).parse::<Fingerprint>().is_err());
+        assert!("+f".repeat(32).parse::<Fingerprint>().is_err());
+    }
+
+    #[test]
+    fn pinned() {
+        let identity = Identity::self_signed().unwrap();
+        let address = echo(&identity);
+        let trust = Trust::Fingerprint(identity.fingerprint().unwrap());
+
+        let mut stream = connect(&address, &trust).unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        write_str(&mut writer, "hello").unwrap();
+        assert_eq!("hello", read_str(&mut stream).unwrap());
+    }
+
+    #[test]
+    fn certificate_authority() {
+        let identity = Identity::self_signed().unwrap();
+        let address = echo(&identity);
+
+        let mut stream = connect(&address, &Trust::Ca(identity.cert.clone())).unwrap();
+        write_str(&mut stream, "hello").unwrap();
+        assert_eq!("hello", read_str(&mut stream).unwrap());
+    }
+
+    #[test]
+    fn untrusted() {
+        let identity = Identity::self_signed().unwrap();
+        let other = Identity::self_signed().unwrap();
+
+        let address = echo(&identity);
+        let trust = Trust::Fingerprint(other.fingerprint().unwrap());
+        assert!(connect(&address, &trust).is_err());
+
+        let address = echo(&identity);
+        assert!(connect(&address, &Trust::Ca(other.cert)).is_err());
+    }
+
+    #[test]
+    fn reads_while_writing() {
+        let identity = Identity::self_signed().unwrap();
+        let address = echo(&identity);
+        let trust = Trust::Fingerprint(identity.fingerprint().unwrap());
+        let mut stream = connect(&address, &trust).unwrap();
+
+        // The reader blocks waiting for the first echo while the writer keeps going.
+        let mut writer = stream.try_clone().unwrap();
+        let reader = thread::spawn(move || {
+            (0..50)
+                .map(|_| read_str(&mut stream).unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        for k in 0..50 {
+            write_str(&mut writer, &k.to_string()).unwrap();
+        }
+
+        let expected: Vec<_> = (0..50).map(|k| k.to_string()).collect();
+        assert_eq!(expected, reader.join().unwrap());
+    }
+}
@@ -0,0 +1,426 @@
+//! A JSON API over HTTP for dashboards and chat bots: open rooms, their boards, finished
+//! games and the leaderboard, plus closing rooms and kicking players with the admin token.
+//!
+//! | Method   | Path                            | Answer                                 |
+//! |----------|---------------------------------|----------------------------------------|
+//! | `GET`    | `/rooms`                        | Every open room, by name               |
+//! | `GET`    | `/rooms/{room}`                 | One room                               |
+//! | `GET`    | `/records`                      | Finished games, oldest first           |
+//! | `GET`    | `/leaderboard`                  | Players by wins                        |
+//! | `DELETE` | `/rooms/{room}`                 | Closes the room, admin only            |
+//! | `DELETE` | `/rooms/{room}/players/{piece}` | Kicks the player of `piece`, admin only |
+
+use crate::game::Game;
+use crate::room::Rooms;
+use crate::server::lock;
+use core::game::{board::Board, piece::Piece};
+use core::record::GameRecord;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::{io, thread};
+use tiny_http::{Header, Method, Request, StatusCode};
+
+type Response = tiny_http::Response<io::Cursor<Vec<u8>>>;
+
+/// A room, as the API shows it.
+#[derive(Serialize, Debug)]
+struct Room {
+    room: String,
+    board: Board,
+    turn: Piece,
+    players: BTreeMap<Piece, String>,
+    spectators: usize,
+}
+
+impl Room {
+    fn new(game: &Game) -> Self {
+        let players = [Piece::X, Piece::O]
+            .into_iter()
+            .filter_map(|piece| Some((piece, game.name(piece)?.to_string())))
+            .collect();
+
+        Self {
+            room: game.room.clone(),
+            board: game.board,
+            turn: game.turn(),
+            players,
+            spectators: game.spectators(),
+        }
+    }
+}
+
+/// How a player fared over every recorded game.
+#[derive(Serialize, Debug, Default, PartialEq, Eq)]
+struct Standing {
+    name: String,
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+/// Players by wins, then fewest losses, then name.
+fn leaderboard(records: &[GameRecord]) -> Vec<Standing> {
+    let mut standings: HashMap<&str, Standing> = HashMap::new();
+    for record in records {
+        for (piece, name) in [(Piece::X, &record.x), (Piece::O, &record.o)] {
+            let standing = standings.entry(name).or_insert_with(|| Standing {
+                name: name.clone(),
+                ..Standing::default()
+            });
+
+            match record.winner {
+                None => standing.draws += 1,
+                Some(winner) if winner == piece => standing.wins += 1,
+                Some(_) => standing.losses += 1,
+            }
+        }
+    }
+
+    let mut standings: Vec<_> = standings.into_values().collect();
+    standings.sort_by(|a, b| (b.wins, a.losses, &a.name).cmp(&(a.wins, b.losses, &b.name)));
+    standings
+}
+
+fn json<T: Serialize>(value: &T) -> Response {
+    let header = "Content-Type: application/json".parse::<Header>();
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::from_data(body).with_header(header.expect("Valid header")),
+        Err(_) => fail(500, "Failed to serialize the answer"),
+    }
+}
+
+/// An error as `{"error": message}`.
+fn fail(status: u16, message: &str) -> Response {
+    json(&HashMap::from([("error", message)])).with_status_code(StatusCode(status))
+}
+
+/// Success with nothing to say.
+fn done() -> Response {
+    Response::from_data(Vec::new()).with_status_code(StatusCode(204))
+}
+
+/// Decodes the `%XX` escapes of a path segment, `None` if they don't make UTF-8.
+fn unescape(segment: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2)?;
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+
+            bytes.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+/// Compares in constant time, so answers don't tell how much of a guess was right.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+struct Api {
+    rooms: Arc<Rooms>,
+    token: Option<String>,
+}
+
+impl Api {
+    /// Whether `req` carries the admin token, as `Authorization: Bearer {token}`.
+    fn authorized(&self, req: &Request) -> bool {
+        let Some(token) = &self.token else {
+            return false;
+        };
+
+        req.headers()
+            .iter()
+            .filter(|header| header.field.equiv("Authorization"))
+            .filter_map(|header| header.value.as_str().strip_prefix("Bearer "))
+            .any(|given| same(given.as_bytes(), token.as_bytes()))
+    }
+
+    fn answer(&self, req: &Request) -> Response {
+        let path = req.url().split('?').next().unwrap_or_default();
+        let Some(segments) = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .map(unescape)
+            .collect::<Option<Vec<_>>>()
+        else {
+            return fail(400, "Malformed path");
+        };
+
+        let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+        match (req.method(), segments.as_slice()) {
+            (Method::Get, ["rooms"]) => {
+                let games = self.rooms.games();
+                let mut rooms: Vec<_> = games.iter().map(|game| Room::new(&lock(game))).collect();
+                rooms.sort_by(|a, b| a.room.cmp(&b.room));
+                json(&rooms)
+            }
+
+            (Method::Get, ["rooms", room]) => match self.rooms.get(room) {
+                Some(game) => json(&Room::new(&lock(&game))),
+                None => fail(404, "No such room"),
+            },
+
+            (Method::Get, ["records"]) => match self.records() {
+                Ok(records) => json(&records),
+                Err(res) => res,
+            },
+
+            (Method::Get, ["leaderboard"]) => match self.records() {
+                Ok(records) => json(&leaderboard(&records)),
+                Err(res) => res,
+            },
+
+            (Method::Delete, ["rooms", ..]) if self.token.is_none() => {
+                fail(403, "No admin token is configured")
+            }
+
+            (Method::Delete, ["rooms", ..]) if !self.authorized(req) => {
+                fail(401, "Missing or wrong admin token")
+            }
+
+            (Method::Delete, ["rooms", room]) => {
+                if self.rooms.close(room) {
+                    done()
+                } else {
+                    fail(404, "No such room")
+                }
+            }
+
+            (Method::Delete, ["rooms", room, "players", piece]) => {
+                let Ok(piece) = piece.parse::<Piece>() else {
+                    return fail(404, "Pieces are X or O");
+                };
+
+                let kicked = self
+                    .rooms
+                    .get(room)
+                    .is_some_and(|game| lock(&game).kick(piece));
+                if kicked {
+                    info!(room = room; "Kicked player `{piece}`");
+                    done()
+                } else {
+                    fail(404, "No such player")
+                }
+            }
+
+            _ => fail(404, "Not found"),
+        }
+    }
+
+    fn records(&self) -> Result<Vec<GameRecord>, Response> {
+        let Some(recorder) = self.rooms.recorder() else {
+            return Err(fail(404, "Finished games aren't kept"));
+        };
+
+        recorder.read().map_err(|e| {
+            error!(error = e.to_string(); "Failed to read finished games");
+            fail(500, "Failed to read finished games")
+        })
+    }
+}
+
+/// Answers API requests on `listener` until the process exits. Without `token`, nobody
+/// can close rooms or kick players.
+pub fn serve(listener: TcpListener, rooms: Arc<Rooms>, token: Option<String>) -> io::Result<()> {
+    let server = tiny_http::Server::from_listener(listener, None).map_err(io::Error::other)?;
+    let api = Api { rooms, token };
+
+    thread::spawn(move || {
+        for req in server.incoming_requests() {
+            let res = api.answer(&req);
+            if let Err(e) = req.respond(res) {
+                debug!(error = e.to_string(); "Failed to answer an API request");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Recorder;
+    use crate::transport::Sink;
+    use core::error::ErrorCode;
+    use core::response;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::{env, fs, process};
+
+    /// Passes on what a player is sent, `None` once it's hung up on.
+    struct Probe(Sender<Option<response::Response>>);
+
+    impl Sink for Probe {
+        fn send(&mut self, res: &response::Response) -> io::Result<()> {
+            let json = serde_json::to_string(res)?;
+            self.0.send(serde_json::from_str(&json).ok()).ok();
+            Ok(())
+        }
+
+        fn close(&mut self) -> io::Result<()> {
+            self.0.send(None).ok();
+            Ok(())
+        }
+    }
+
+    fn probe() -> (Box<dyn Sink>, Receiver<Option<response::Response>>) {
+        let (tx, rx) = mpsc::channel();
+        (Box::new(Probe(tx)), rx)
+    }
+
+    fn request(address: SocketAddr, method: &str, path: &str, token: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer {token}\r\n\
+             Connection: close\r\n\r\n"
+        )
+        .unwrap();
+
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+        res
+    }
+
+    fn start(recorder: Option<Arc<Recorder>>) -> (SocketAddr, Arc<Rooms>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let rooms = Arc::new(Rooms::new(Arc::default(), recorder));
+        serve(listener, Arc::clone(&rooms), Some("hunter2".to_string())).unwrap();
+        (address, rooms)
+    }
+
+    fn record(x: &str, o: &str, winner: Option<Piece>) -> GameRecord {
+        GameRecord {
+            room: "zoo".to_string(),
+            x: x.to_string(),
+            o: o.to_string(),
+            moves: String::new(),
+            winner,
+            on_time: false,
+            finished: 0,
+        }
+    }
+
+    #[test]
+    fn unescapes() {
+        assert_eq!(Some("zoo keeper"), unescape("zoo%20keeper").as_deref());
+        assert_eq!(Some("é"), unescape("%C3%a9").as_deref());
+        assert_eq!(None, unescape("%+1"));
+        assert_eq!(None, unescape("%2"));
+        assert_eq!(None, unescape("%ff"));
+    }
+
+    #[test]
+    fn standings() {
+        let records = [
+            record("ann", "bob", Some(Piece::X)),
+            record("bob", "cat", None),
+            record("cat", "ann", Some(Piece::O)),
+            record("bob", "cat", Some(Piece::X)),
+        ];
+
+        let standing = |name: &str, wins, draws, losses| Standing {
+            name: name.to_string(),
+            wins,
+            draws,
+            losses,
+        };
+        assert_eq!(
+            vec![
+                standing("ann", 2, 0, 0),
+                standing("bob", 1, 1, 1),
+                standing("cat", 0, 1, 2),
+            ],
+            leaderboard(&records)
+        );
+    }
+
+    #[test]
+    fn rooms() {
+        let (address, rooms) = start(None);
+        let (sink, _rx) = probe();
+        rooms.join("zoo", "ann", sink).unwrap();
+
+        let res = request(address, "GET", "/rooms", "");
+        assert!(res.starts_with("HTTP/1.1 200"), "{res}");
+        assert!(res.contains("application/json"));
+        assert!(res.contains(r#"[{"room":"zoo","board":[null,"#), "{res}");
+        assert!(res.contains(r#""turn":"X","players":{"X":"ann"},"spectators":0}]"#));
+
+        let res = request(address, "GET", "/rooms/zoo", "");
+        assert!(res.contains(r#"{"room":"zoo","#), "{res}");
+        assert!(request(address, "GET", "/rooms/zoo%20keeper", "").starts_with("HTTP/1.1 404"));
+        assert!(request(address, "GET", "/records", "").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn admin() {
+        let (address, rooms) = start(None);
+        let (sink, rx) = probe();
+        rooms.join("zoo", "ann", sink).unwrap();
+        assert!(matches!(
+            rx.recv().unwrap(),
+            Some(response::Response::Init { .. })
+        ));
+
+        let res = request(address, "DELETE", "/rooms/zoo/players/X", "guess");
+        assert!(res.starts_with("HTTP/1.1 401"), "{res}");
+        assert!(request(address, "DELETE", "/rooms/zoo", "guess").starts_with("HTTP/1.1 401"));
+
+        let res = request(address, "DELETE", "/rooms/zoo/players/O", "hunter2");
+        assert!(res.starts_with("HTTP/1.1 404"), "{res}");
+        let res = request(address, "DELETE", "/rooms/zoo/players/X", "hunter2");
+        assert!(res.starts_with("HTTP/1.1 204"), "{res}");
+        assert!(matches!(
+            rx.recv().unwrap(),
+            Some(response::Response::Invalid(ErrorCode::Kicked))
+        ));
+        assert!(rx.recv().unwrap().is_none());
+
+        let res = request(address, "DELETE", "/rooms/zoo", "hunter2");
+        assert!(res.starts_with("HTTP/1.1 204"), "{res}");
+        assert!(matches!(
+            rx.recv().unwrap(),
+            Some(response::Response::Invalid(ErrorCode::RoomClosed))
+        ));
+        assert!(rooms.get("zoo").is_none());
+        assert!(request(address, "DELETE", "/rooms/zoo", "hunter2").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn records() {
+        let path = env::temp_dir().join(format!("api-{}.jsonl", process::id()));
+        let recorder = Arc::new(Recorder::open(&path).unwrap());
+        recorder
+            .record(&record("ann", "bob", Some(Piece::O)))
+            .unwrap();
+        let (address, _) = start(Some(recorder));
+
+        let res = request(address, "GET", "/records", "");
+        assert!(
+            res.contains(r#"[{"room":"zoo","x":"ann","o":"bob","#),
+            "{res}"
+        );
+
+        let res = request(address, "GET", "/leaderboard", "");
+        fs::remove_file(&path).ok();
+        assert!(res.contains(
+            r#"[{"name":"bob","wins":1,"draws":0,"losses":0},{"name":"ann","wins":0,"draws":0,"losses":1}]"#
+        ), "{res}");
+    }
+}
@@ -0,0 +1,195 @@
+use crate::config::{fraction, secs};
+use crate::transport::MemoryClient;
+use core::game::{board::Board, eval::Evaluator, piece::Piece};
+use core::request::Request;
+use core::response::Response;
+use serde::Deserialize;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a bot waits before reconnecting after losing its seat.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a bot pings the server while nobody moves.
+const PING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A bot sitting in a room, playing whoever joins it.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BotConfig {
+    pub room: String,
+    #[serde(default = "default_name")]
+    pub name: String,
+    /// Chance of playing a best move rather than any legal one, 1 never loses.
+    #[serde(default = "perfect", deserialize_with = "fraction")]
+    pub skill: f64,
+    /// Seconds the bot thinks before each move.
+    #[serde(default, deserialize_with = "secs")]
+    pub delay: Duration,
+}
+
+fn default_name() -> String {
+    "Bot".to_string()
+}
+
+fn perfect() -> f64 {
+    1.0
+}
+
+/// Opens a connection to the server, in memory since bots live in its process.
+pub type Connect = Arc<dyn Fn() -> io::Result<MemoryClient> + Send + Sync>;
+
+/// Keeps a bot seated in its room for as long as the server runs, taking the seat back
+/// whenever it's lost. It reaches the server through `connect`.
+pub fn spawn(connect: Connect, config: BotConfig) {
+    thread::spawn(move || loop {
+        match Bot::new(&config).play(&connect) {
+            Ok(()) => info!("Bot `{}` left room `{}`", config.name, config.room),
+            Err(e) => warn!("Bot `{}` in room `{}`: {e}", config.name, config.room),
+        }
+
+        thread::sleep(RETRY_INTERVAL);
+    });
+}
+
+struct Bot<'a> {
+    config: &'a BotConfig,
+    evaluator: Evaluator,
+    rng: u64,
+    board: Board,
+    piece: Piece,
+    turn: Piece,
+    opponent: bool,
+    /// Whether a move was sent and not answered yet.
+    pending: bool,
+}
+
+impl<'a> Bot<'a> {
+    fn new(config: &'a BotConfig) -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+
+        Self {
+            config,
+            evaluator: Evaluator::new(),
+            rng: seed | 1,
+            board: Board::new(),
+            piece: Piece::X,
+            turn: Piece::X,
+            opponent: false,
+            pending: false,
+        }
+    }
+
+    /// Xorshift, plenty for picking moves.
+    fn random(&mut self) -> u64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        self.rng
+    }
+
+    fn choose(&mut self) -> Option<(usize, usize)> {
+        let careless = (self.random() % 1000) as f64 >= self.config.skill * 1000.0;
+        let moves = if careless {
+            self.board.legal_moves()
+        } else {
+            self.evaluator.best_moves(&self.board, self.turn)
+        };
+
+        let k = self.random() as usize % moves.len().max(1);
+        moves.get(k).copied()
+    }
+
+    /// Plays in the room until the connection drops.
+    fn play(&mut self, connect: &Connect) -> io::Result<()> {
+        let mut conn = connect()?;
+        conn.set_read_timeout(Some(PING_INTERVAL));
+        let join = Request::Join {
+            room: self.config.room.clone(),
+            name: self.config.name.clone(),
+        };
+        conn.send(&join)?;
+
+        loop {
+            if self.opponent && self.turn == self.piece && !self.pending {
+                thread::sleep(self.config.delay);
+                if let Some(idx) = self.choose() {
+                    conn.send(&Request::Play { idx })?;
+                    self.pending = true;
+                }
+            }
+
+            let res = match conn.recv() {
+                Ok(res) => res,
+                Err(e) => match e.kind() {
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                        conn.send(&Request::Ping(0))?;
+                        continue;
+                    }
+
+                    io::ErrorKind::UnexpectedEof => return Ok(()),
+                    _ => return Err(e),
+                },
+            };
+
+            match res {
+                Response::Init {
+                    board,
+                    piece,
+                    turn,
+                    opponent,
+                    ..
+                } => {
+                    self.board = board;
+                    self.piece = piece;
+                    self.turn = turn;
+                    self.opponent = opponent.is_some();
+                }
+
+                Response::Valid {
+                    piece,
+                    idx,
+                    state,
+                    turn,
+                    ..
+                } => {
+                    self.board[idx] = Some(piece);
+                    if state.is_end() {
+                        self.board.clear();
+                    }
+
+                    self.turn = turn;
+                    self.pending = false;
+                }
+
+                Response::Timeout { turn, .. } | Response::Resigned { turn, .. } => {
+                    self.board.clear();
+                    self.turn = turn;
+                    self.pending = false;
+                }
+
+                Response::Takeback { board, turn } => {
+                    self.board = board;
+                    self.turn = turn;
+                }
+
+                Response::TakebackOffer(piece) if piece != self.piece => {
+                    conn.send(&Request::DeclineTakeback)?;
+                }
+
+                Response::Connect { .. } => self.opponent = true,
+                Response::Disconnect(_) => self.opponent = false,
+                // Out of sync with the game, joining again sets it straight.
+                Response::Invalid(code) => {
+                    return Err(io::Error::other(format!("request refused, {code:?}")));
+                }
+
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use crate::config::secs;
+use core::game::piece::Piece;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+/// How much thinking time players get, in seconds in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimeControl {
+    #[serde(deserialize_with = "secs")]
+    pub initial: Duration,
+    /// Added back to a player's time after each of its moves.
+    #[serde(deserialize_with = "secs")]
+    pub increment: Duration,
+}
+
+/// Time left to both players of a game. Nobody's time runs until the first move, then
+/// it runs for the side to move.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    control: TimeControl,
+    left: [Duration; 2],
+    running: Option<(Piece, Instant)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Self {
+            control,
+            left: [control.initial; 2],
+            running: None,
+        }
+    }
+
+    pub fn left(&self, piece: Piece, now: Instant) -> Duration {
+        let left = self.left[piece as usize];
+        match self.running {
+            Some((running, since)) if running == piece => {
+                left.saturating_sub(now.saturating_duration_since(since))
+            }
+
+            _ => left,
+        }
+    }
+
+    /// Milliseconds left to X and O.
+    pub fn millis(&self, now: Instant) -> [u64; 2] {
+        [Piece::X, Piece::O].map(|piece| self.left(piece, now).as_millis() as u64)
+    }
+
+    /// The player whose time ran out, if any.
+    pub fn flagged(&self, now: Instant) -> Option<Piece> {
+        let (piece, _) = self.running?;
+        self.left(piece, now).is_zero().then_some(piece)
+    }
+
+    /// Stops `piece`'s time after it moved and starts its opponent's.
+    pub fn punch(&mut self, piece: Piece, now: Instant) {
+        if self.running.is_some() {
+            let left = self.left(piece, now) + self.control.increment;
+            self.left[piece as usize] = left;
+        }
+
+        self.running = Some((piece.other(), now));
+    }
+
+    /// Hands the move to `piece` without touching anyone's time, e.g. after a takeback.
+    pub fn switch(&mut self, piece: Piece, now: Instant) {
+        if let Some((running, _)) = self.running {
+            self.left[running as usize] = self.left(running, now);
+            self.running = Some((piece, now));
+        }
+    }
+
+    /// Sets both players back to the initial time for a new game.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.control);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTROL: TimeControl = TimeControl {
+        initial: Duration::from_secs(10),
+        increment: Duration::from_secs(1),
+    };
+
+    #[test]
+    fn runs_for_side_to_move() {
+        let start = Instant::now();
+        let mut clock = Clock::new(CONTROL);
+
+        // The first move is free.
+        clock.punch(Piece::X, start + Duration::from_secs(5));
+        assert_eq!(Duration::from_secs(10), clock.left(Piece::X, start));
+
+        let now = start + Duration::from_secs(8);
+        assert_eq!(Duration::from_secs(7), clock.left(Piece::O, now));
+        assert_eq!([10_000, 7_000], clock.millis(now));
+
+        clock.punch(Piece::O, now);
+        assert_eq!(Duration::from_secs(8), clock.left(Piece::O, now));
+        assert_eq!(None, clock.flagged(now + Duration::from_secs(9)));
+        assert_eq!(Some(Piece::X), clock.flagged(now + Duration::from_secs(10)));
+
+        clock.reset();
+        assert_eq!([10_000, 10_000], clock.millis(now));
+        assert_eq!(None, clock.flagged(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn switch() {
+        let start = Instant::now();
+        let mut clock = Clock::new(CONTROL);
+        clock.punch(Piece::X, start);
+
+        let now = start + Duration::from_secs(3);
+        clock.switch(Piece::X, now);
+        assert_eq!(Duration::from_secs(7), clock.left(Piece::O, now));
+        assert_eq!(
+            Duration::from_secs(8),
+            clock.left(Piece::X, now + Duration::from_secs(2))
+        );
+    }
+}
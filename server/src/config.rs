@@ -0,0 +1,477 @@
+//! Server settings read from a TOML file, command line flags take precedence over them.
+//!
+//! Only [`Settings`] can change while the server runs, the rest of the config is read
+//! once at startup.
+
+use crate::bot::BotConfig;
+use crate::clock::TimeControl;
+use crate::limit::{Ban, RateLimit};
+use crate::log::{Format, Level};
+use crate::threadpool::{self, Policy};
+use core::game::position::Position;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use std::{error::Error, fs, io, thread};
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub port: Option<u16>,
+    /// Unix socket to listen on instead of the port.
+    pub unix: Option<PathBuf>,
+    pub bind: IpAddr,
+    /// Most workers completing handshakes with new connections at once.
+    pub threads: NonZeroUsize,
+    pub pool: Pool,
+    pub max_rooms: NonZeroUsize,
+    /// Address to serve Prometheus metrics on, none by default.
+    pub metrics: Option<SocketAddr>,
+    /// Address to accept WebSocket clients on, none by default.
+    pub websocket: Option<SocketAddr>,
+    /// Address to accept clients typing commands on, none by default.
+    pub line: Option<SocketAddr>,
+    /// Address to serve the JSON API on, none by default.
+    pub api: Option<SocketAddr>,
+    /// Token closing rooms and kicking players through the API, nobody can without one.
+    pub admin_token: Option<String>,
+    pub log_level: Level,
+    pub log_format: Format,
+    /// Whether clients may show move hints. Only advice, clients work hints out themselves.
+    pub hints: bool,
+    #[serde(deserialize_with = "parse")]
+    pub position: Option<Position>,
+    /// Seconds a silent player keeps its seat, 0 waits forever. Line clients, people
+    /// typing, get at least 30 minutes.
+    #[serde(deserialize_with = "timeout")]
+    pub idle_timeout: Option<Duration>,
+    pub time_control: Option<TimeControl>,
+    /// Requests allowed per connection, 20 a second in bursts of up to 40 by default.
+    pub rate_limit: Option<RateLimit>,
+    /// Most connections open at once from the same address.
+    pub max_connections_per_ip: Option<NonZeroUsize>,
+    /// Requests allowed per address, shared by all of its connections.
+    pub ip_rate_limit: Option<RateLimit>,
+    pub ban: Option<Ban>,
+    pub persistence: Persistence,
+    pub tls: Option<Tls>,
+    #[serde(rename = "bot")]
+    pub bots: Vec<BotConfig>,
+}
+
+/// How the workers completing handshakes come and go.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pool {
+    /// Workers kept even when nobody's connected.
+    pub min_threads: usize,
+    /// Connections that may wait for a worker to shake hands, once there are `threads`
+    /// of them.
+    pub queue: usize,
+    /// Seconds a spare worker waits for a connection before exiting.
+    #[serde(deserialize_with = "secs")]
+    pub keep_alive: Duration,
+    /// Whether to turn connections away or make them wait once the queue is full.
+    pub when_full: Policy,
+}
+
+impl Default for Pool {
+    fn default() -> Self {
+        let options = threadpool::Options::default();
+        Self {
+            min_threads: options.min,
+            queue: options.queue,
+            keep_alive: options.keep_alive,
+            when_full: options.policy,
+        }
+    }
+}
+
+/// Where the server keeps what outlives it.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Persistence {
+    /// JSON lines file every finished game is appended to.
+    pub games: Option<PathBuf>,
+    /// JSON lines file every accepted move is appended to.
+    pub audit: Option<PathBuf>,
+}
+
+/// Certificate and key the server proves who it is with, both PEM encoded. Clients
+/// then have to connect over TLS.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// The part of the config that can change while the server runs. Connections and rooms
+/// read it when they start, so running games keep the settings they started with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub max_rooms: usize,
+    pub hints: bool,
+    pub position: Option<Position>,
+    pub idle_timeout: Option<Duration>,
+    pub time_control: Option<TimeControl>,
+    pub rate_limit: Option<RateLimit>,
+    pub max_connections_per_ip: Option<usize>,
+    pub ip_rate_limit: Option<RateLimit>,
+    pub ban: Option<Ban>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            port: None,
+            unix: None,
+            bind: Ipv4Addr::LOCALHOST.into(),
+            threads: NonZeroUsize::new(2).unwrap(),
+            pool: Pool::default(),
+            max_rooms: NonZeroUsize::new(1000).unwrap(),
+            metrics: None,
+            websocket: None,
+            line: None,
+            api: None,
+            admin_token: None,
+            log_level: Level::Info,
+            log_format: Format::Text,
+            hints: true,
+            position: None,
+            idle_timeout: Some(Duration::from_secs(30)),
+            time_control: None,
+            rate_limit: Some(RateLimit {
+                rate: 20.0,
+                burst: 40.0,
+            }),
+            max_connections_per_ip: None,
+            ip_rate_limit: None,
+            ban: None,
+            persistence: Persistence::default(),
+            tls: None,
+            bots: Vec::new(),
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Config::default().settings()
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {e}", path.display()))?;
+
+        text.parse()
+            .map_err(|e| format!("Invalid config {}: {e}", path.display()).into())
+    }
+
+    pub fn settings(&self) -> Settings {
+        Settings {
+            max_rooms: self.max_rooms.get(),
+            hints: self.hints,
+            position: self.position,
+            idle_timeout: self.idle_timeout,
+            time_control: self.time_control,
+            rate_limit: self.rate_limit,
+            max_connections_per_ip: self.max_connections_per_ip.map(NonZeroUsize::get),
+            ip_rate_limit: self.ip_rate_limit,
+            ban: self.ban,
+        }
+    }
+
+    pub fn pool(&self) -> threadpool::Options {
+        threadpool::Options {
+            min: self.pool.min_threads,
+            max: self.threads.get(),
+            queue: self.pool.queue,
+            keep_alive: self.pool.keep_alive,
+            policy: self.pool.when_full,
+        }
+    }
+
+    /// Names of the settings that differ from `other` but only apply after a restart.
+    pub fn restart_needed(&self, other: &Config) -> Vec<&'static str> {
+        let fields = [
+            ("port", self.port != other.port),
+            ("unix", self.unix != other.unix),
+            ("bind", self.bind != other.bind),
+            ("threads", self.threads != other.threads),
+            ("pool", self.pool != other.pool),
+            ("metrics", self.metrics != other.metrics),
+            ("websocket", self.websocket != other.websocket),
+            ("line", self.line != other.line),
+            (
+                "api",
+                self.api != other.api || self.admin_token != other.admin_token,
+            ),
+            ("persistence", self.persistence != other.persistence),
+            ("tls", self.tls != other.tls),
+            ("bot", self.bots != other.bots),
+        ];
+
+        fields
+            .into_iter()
+            .filter_map(|(name, changed)| changed.then_some(name))
+            .collect()
+    }
+}
+
+impl FromStr for Config {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+/// Calls `reload` whenever the file at `path` changes, checking every `interval`, and on
+/// Unix whenever the process gets `SIGHUP`.
+pub fn watch<F>(path: PathBuf, interval: Duration, mut reload: F) -> io::Result<()>
+where
+    F: FnMut() + Send + 'static,
+{
+    let hangup = Arc::new(AtomicBool::new(false));
+    #[cfg(unix)]
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&hangup))?;
+
+    let modified = |path: &Path| -> Option<SystemTime> { fs::metadata(path).ok()?.modified().ok() };
+    let mut last = modified(&path);
+
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let now = modified(&path);
+        let changed = now.is_some() && now != last;
+        last = now;
+
+        if hangup.swap(false, Ordering::Relaxed) || changed {
+            reload();
+        }
+    });
+
+    Ok(())
+}
+
+/// Deserializes a string through `T`'s `FromStr`.
+fn parse<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: Display,
+{
+    let s = String::deserialize(deserializer)?;
+    s.parse().map(Some).map_err(de::Error::custom)
+}
+
+/// Deserializes a number of seconds.
+pub fn secs<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let secs = f64::deserialize(deserializer)?;
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| de::Error::custom(format!("expected a number of seconds, got {secs}")))
+}
+
+/// Deserializes a number of seconds, 0 meaning no timeout at all.
+fn timeout<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    let timeout = secs(deserializer)?;
+    Ok((!timeout.is_zero()).then_some(timeout))
+}
+
+/// Deserializes a number above 0.
+pub fn positive<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let x = f64::deserialize(deserializer)?;
+    if x > 0.0 && x.is_finite() {
+        Ok(x)
+    } else {
+        Err(de::Error::custom(format!(
+            "expected a positive number, got {x}"
+        )))
+    }
+}
+
+/// Deserializes a number between 0 and 1.
+pub fn fraction<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    let x = f64::deserialize(deserializer)?;
+    if (0.0..=1.0).contains(&x) {
+        Ok(x)
+    } else {
+        Err(de::Error::custom(format!(
+            "expected a number from 0 to 1, got {x}"
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn full() {
+        let config: Config = r#"
+            port = 8080
+            bind = "::"
+            threads = 4
+            max_rooms = 10
+            metrics = "127.0.0.1:9100"
+            websocket = "0.0.0.0:8081"
+            line = "127.0.0.1:2323"
+            api = "127.0.0.1:8082"
+            admin_token = "hunter2"
+            log_level = "debug"
+            log_format = "json"
+            hints = false
+            position = "X1O/1X1/3 O 3 3"
+            idle_timeout = 0
+            max_connections_per_ip = 4
+
+            [pool]
+            min_threads = 1
+            queue = 8
+            keep_alive = 10
+            when_full = "wait"
+
+            [time_control]
+            initial = 300
+            increment = 2.5
+
+            [rate_limit]
+            rate = 10
+            burst = 20
+
+            [ip_rate_limit]
+            rate = 30
+            burst = 60
+
+            [ban]
+            strikes = 5
+            duration = 300
+
+            [persistence]
+            games = "games.jsonl"
+            audit = "audit.jsonl"
+
+            [tls]
+            cert = "cert.pem"
+            key = "key.pem"
+
+            [[bot]]
+            room = "bots"
+            skill = 0.5
+        "#
+        .parse()
+        .unwrap();
+
+        assert_eq!(Some(8080), config.port);
+        assert!(config.bind.is_unspecified());
+        assert_eq!(4, config.threads.get());
+        let pool = config.pool();
+        assert_eq!((1, 4, 8), (pool.min, pool.max, pool.queue));
+        assert_eq!(Duration::from_secs(10), pool.keep_alive);
+        assert_eq!(Policy::Wait, pool.policy);
+        assert_eq!(Some("127.0.0.1:9100".parse().unwrap()), config.metrics);
+        assert_eq!(Some("0.0.0.0:8081".parse().unwrap()), config.websocket);
+        assert_eq!(Some("127.0.0.1:2323".parse().unwrap()), config.line);
+        assert_eq!(Some("127.0.0.1:8082".parse().unwrap()), config.api);
+        assert_eq!(Some("hunter2"), config.admin_token.as_deref());
+        assert!(!config.hints);
+        assert_eq!(Level::Debug, config.log_level);
+        assert_eq!(Format::Json, config.log_format);
+        assert_eq!("X1O/1X1/3 O 3 3", config.position.unwrap().to_string());
+
+        let settings = config.settings();
+        assert_eq!(10, settings.max_rooms);
+        assert_eq!(None, settings.idle_timeout);
+
+        let control = settings.time_control.unwrap();
+        assert_eq!(Duration::from_secs(300), control.initial);
+        assert_eq!(Duration::from_millis(2500), control.increment);
+        assert_eq!(20.0, settings.rate_limit.unwrap().burst);
+        assert_eq!(Some(4), settings.max_connections_per_ip);
+        assert_eq!(60.0, settings.ip_rate_limit.unwrap().burst);
+        let ban = settings.ban.unwrap();
+        assert_eq!(
+            (5, Duration::from_secs(300)),
+            (ban.strikes.get(), ban.duration)
+        );
+
+        assert_eq!(Some("games.jsonl".into()), config.persistence.games);
+        assert_eq!(Some("audit.jsonl".into()), config.persistence.audit);
+        assert_eq!(Some("key.pem".into()), config.tls.map(|tls| tls.key));
+        assert_eq!("bots", config.bots[0].room);
+        assert_eq!(0.5, config.bots[0].skill);
+    }
+
+    #[test]
+    fn defaults() {
+        let config: Config = "".parse().unwrap();
+        assert_eq!(Config::default(), config);
+        assert_eq!(None, config.port);
+        assert_eq!(2, config.threads.get());
+        assert_eq!(Some(Duration::from_secs(30)), config.idle_timeout);
+        assert_eq!(40.0, config.rate_limit.unwrap().burst);
+        assert!(config.hints);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("prot = 8080".parse::<Config>().is_err());
+        assert!("port = 65536".parse::<Config>().is_err());
+
+        let errors = [
+            ("port = 8080\nposition = \"XXX/3/3 O 3 3\"", "line 2"),
+            ("threads = 0", "line 1"),
+            ("\n\n[rate_limit]\nrate = -1\nburst = 2", "line 4"),
+            ("[time_control]\ninitial = 60", "missing field `increment`"),
+            ("[tls]\ncert = \"cert.pem\"", "missing field `key`"),
+            ("[ban]\nstrikes = 0\nduration = 60", "line 2"),
+            ("[[bot]]\nroom = \"bots\"\nskill = 2", "line 3"),
+        ];
+
+        for (config, expected) in errors {
+            let err = config.parse::<Config>().unwrap_err().to_string();
+            assert!(err.contains(expected), "{err}");
+        }
+    }
+
+    #[test]
+    fn restart_needed() {
+        let config = Config::default();
+        let mut other = config.clone();
+        other.hints = false;
+        other.port = Some(8080);
+
+        assert_eq!(vec!["port"], config.restart_needed(&other));
+    }
+
+    #[test]
+    fn reload_on_change() {
+        let dir = std::env::temp_dir().join(format!("config-watch-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server.toml");
+        fs::write(&path, "port = 8080").unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        watch(path.clone(), Duration::from_millis(20), move || {
+            tx.send(()).ok();
+        })
+        .unwrap();
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(rx.try_recv().is_err());
+
+        fs::write(&path, "port = 8081").unwrap();
+        assert!(rx.recv_timeout(Duration::from_secs(2)).is_ok());
+        fs::remove_dir_all(&dir).ok();
+    }
+}
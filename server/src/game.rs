@@ -1,13 +1,42 @@
-use core::game::{board::Board, piece::Piece};
+use crate::clock::{Clock, TimeControl};
+use crate::metrics::{Outcome, METRICS};
+use crate::record::Recorder;
+use crate::transport::Sink;
+use core::error::ErrorCode;
+use core::game::{board::Board, piece::Piece, position::Position, state::GameState};
+use core::io_err;
+use core::notation::format_moves;
+use core::record::GameRecord;
 use core::response::Response;
-use core::{io_err, write_str};
 use std::collections::BTreeMap;
 use std::io;
-use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Longest player name kept, longer ones are cut.
+const MAX_NAME_LEN: usize = 24;
+
+/// Longest chat message kept, longer ones are cut.
+const MAX_CHAT_LEN: usize = 200;
+
+struct Player {
+    sink: Box<dyn Sink>,
+    name: String,
+}
 
 pub struct Game {
     pub board: Board,
-    pub players: BTreeMap<Piece, TcpStream>,
+    players: BTreeMap<Piece, Player>,
+    spectators: BTreeMap<u64, Box<dyn Sink>>,
+    next_spectator: u64,
+    pub hints: bool,
+    /// Name of the room the game is played in, for its records.
+    pub room: String,
+    /// Where finished games are kept, if anywhere.
+    pub recorder: Option<Arc<Recorder>>,
+    clock: Option<Clock>,
+    history: Vec<(usize, usize)>,
+    takeback: Option<Piece>,
     turn: Piece,
     started: Piece,
 }
@@ -17,76 +46,350 @@ impl Game {
         Self::default()
     }
 
-    fn alert_other_player(&mut self, piece: Piece) -> io::Result<()> {
-        if let Some(other) = self.players.get_mut(&piece.other()) {
-            let res = Response::Connect;
-            let json = serde_json::to_string(&res)?;
-            write_str(other, &json)?;
+    pub fn position(&self) -> Position {
+        Position::new(self.board, self.turn)
+    }
+
+    /// Replaces the game in progress with `pos`.
+    pub fn set_position(&mut self, pos: Position) {
+        self.board = pos.board;
+        self.turn = pos.turn;
+        self.started = pos.started();
+        self.history.clear();
+        self.takeback = None;
+        if let Some(clock) = &mut self.clock {
+            clock.reset();
         }
+    }
 
-        Ok(())
+    /// Times both players from the next move on, `None` lets them think forever.
+    pub fn set_time_control(&mut self, control: Option<TimeControl>) {
+        self.clock = control.map(Clock::new);
     }
 
-    pub fn assign_piece(&mut self, stream: TcpStream) -> Option<Piece> {
-        (self.players.len() < 2).then_some({
-            let piece = if self.players.contains_key(&Piece::X) {
-                Piece::O
-            } else {
-                Piece::X
-            };
+    /// Seats whoever `sink` sends to as `name`, telling everyone already in the room. The
+    /// new player gets its `Init` before anything else can reach it.
+    pub fn assign_piece(&mut self, sink: Box<dyn Sink>, name: &str) -> Option<Piece> {
+        if self.is_full() {
+            return None;
+        }
+
+        let piece = if self.players.contains_key(&Piece::X) {
+            Piece::O
+        } else {
+            Piece::X
+        };
+
+        let name: String = name.trim().chars().take(MAX_NAME_LEN).collect();
+        let name = if name.is_empty() {
+            format!("Player {piece}")
+        } else {
+            name
+        };
+
+        self.broadcast(Response::Connect {
+            piece,
+            name: name.clone(),
+        });
+        self.players.insert(piece, Player { sink, name });
+        self.send(piece, self.init(piece)).ok();
+        Some(piece)
+    }
+
+    /// Starts sending every update to `sink`, which gets a `Watch` first.
+    pub fn add_spectator(&mut self, sink: Box<dyn Sink>) -> u64 {
+        let id = self.next_spectator;
+        self.next_spectator += 1;
+        self.spectators.insert(id, sink);
+        self.send_spectator(id, self.watch()).ok();
+        id
+    }
+
+    pub fn remove_spectator(&mut self, id: u64) {
+        self.spectators.remove(&id);
+    }
+
+    /// Whether both seats are taken.
+    pub fn is_full(&self) -> bool {
+        self.players.len() >= 2
+    }
+
+    /// Whether nobody plays or watches anymore.
+    pub fn is_empty(&self) -> bool {
+        self.players.is_empty() && self.spectators.is_empty()
+    }
+
+    pub fn name(&self, piece: Piece) -> Option<&str> {
+        self.players.get(&piece).map(|player| player.name.as_str())
+    }
+
+    pub fn turn(&self) -> Piece {
+        self.turn
+    }
+
+    pub fn spectators(&self) -> usize {
+        self.spectators.len()
+    }
+
+    /// Tells `piece` it's kicked and hangs up on it, its handler then frees the seat.
+    pub fn kick(&mut self, piece: Piece) -> bool {
+        let Some(player) = self.players.get_mut(&piece) else {
+            return false;
+        };
+
+        METRICS.errored(ErrorCode::Kicked);
+        player.sink.send(&Response::Invalid(ErrorCode::Kicked)).ok();
+        player.sink.close().ok();
+        true
+    }
+
+    /// Tells everyone the room is closed and hangs up on them.
+    pub fn close(&mut self) {
+        METRICS.errored(ErrorCode::RoomClosed);
+        self.broadcast(Response::Invalid(ErrorCode::RoomClosed));
+        let players = self.players.values_mut().map(|player| &mut player.sink);
+        for sink in players.chain(self.spectators.values_mut()) {
+            sink.close().ok();
+        }
+    }
+
+    /// What `piece` is told when it takes its seat.
+    pub fn init(&self, piece: Piece) -> Response {
+        Response::Init {
+            board: self.board,
+            piece,
+            turn: self.turn,
+            hints: self.hints,
+            opponent: self.players.get(&piece.other()).map(|p| p.name.clone()),
+        }
+    }
 
-            self.alert_other_player(piece).ok()?;
-            self.players.insert(piece, stream);
-            piece
-        })
+    /// What a spectator is told when it starts watching.
+    pub fn watch(&self) -> Response {
+        Response::Watch {
+            board: self.board,
+            turn: self.turn,
+            players: self
+                .players
+                .iter()
+                .map(|(&piece, player)| (piece, player.name.clone()))
+                .collect(),
+        }
     }
 
-    pub fn disconnect(&mut self, piece: Piece) -> io::Result<()> {
-        self.broadcast(Response::Disconnect(piece))?;
+    pub fn disconnect(&mut self, piece: Piece) {
+        self.broadcast(Response::Disconnect(piece));
         self.players.remove(&piece);
-        Ok(())
+
+        if self.takeback == Some(piece) {
+            self.takeback = None;
+        }
     }
 
-    pub fn play(&mut self, piece: Piece, idx: (usize, usize)) -> Response {
+    /// Plays `piece` at `idx`, as of `now` for the clocks.
+    pub fn play(&mut self, piece: Piece, idx: (usize, usize), now: Instant) -> Response {
         if piece != self.turn {
-            return Response::Invalid(String::from("Not your turn"));
+            return Response::Invalid(ErrorCode::NotYourTurn);
+        }
+
+        if self.clock.as_ref().and_then(|c| c.flagged(now)) == Some(piece) {
+            return self.timeout(piece);
         }
 
-        if self.board[idx].is_some() {
-            return Response::Invalid(String::from("Cell already occupied"));
+        if let Err(e) = self.board.make_move(idx, piece) {
+            return Response::Invalid(e.into());
         }
 
-        self.board[idx] = Some(piece);
+        self.history.push(idx);
+        self.takeback = None;
         self.turn.next();
+        METRICS.moved();
+
+        let clock = self.clock.as_mut().map(|clock| {
+            clock.punch(piece, now);
+            clock.millis(now)
+        });
 
         let state = self.board.check_end(piece);
+        match state {
+            GameState::Win(winner) => self.finish(Some(winner), false),
+            GameState::Stalemate => self.finish(None, false),
+            GameState::Playing => {}
+        }
+
+        Response::Valid {
+            piece,
+            idx,
+            state,
+            turn: self.turn,
+            clock,
+        }
+    }
 
-        if state.is_end() {
-            self.turn = self.started.other();
-            self.started = self.turn;
-            self.board.clear();
+    /// Ends the game if the side to move ran out of time, telling everyone.
+    pub fn tick(&mut self, now: Instant) {
+        if let Some(piece) = self.clock.as_ref().and_then(|c| c.flagged(now)) {
+            let res = self.timeout(piece);
+            self.broadcast(res);
         }
+    }
 
-        Response::Valid { piece, idx, state }
+    fn timeout(&mut self, piece: Piece) -> Response {
+        self.finish(Some(piece.other()), true);
+        Response::Timeout {
+            piece,
+            turn: self.turn,
+        }
     }
 
-    pub fn send(&mut self, piece: Piece, res: Response) -> io::Result<()> {
-        let json = serde_json::to_string(&res)?;
+    /// Records the game that just ended and sets up the next one, started by whoever
+    /// didn't start this one.
+    fn finish(&mut self, winner: Option<Piece>, on_time: bool) {
+        METRICS.finished(match (winner, on_time) {
+            (_, true) => Outcome::Timeout,
+            (Some(_), false) => Outcome::Win,
+            (None, false) => Outcome::Draw,
+        });
+
+        if let Some(recorder) = &self.recorder {
+            let name = |piece| match self.name(piece) {
+                Some(name) => name.to_string(),
+                None => format!("Player {piece}"),
+            };
+
+            let record = GameRecord {
+                room: self.room.clone(),
+                x: name(Piece::X),
+                o: name(Piece::O),
+                moves: format_moves(&self.history),
+                winner,
+                on_time,
+                finished: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs()),
+            };
+
+            if let Err(e) = recorder.record(&record) {
+                error!("Failed to record a game of room `{}`: {e}", self.room);
+            }
+        }
+
+        self.turn = self.started.other();
+        self.started = self.turn;
+        self.board.clear();
+        self.history.clear();
+        self.takeback = None;
+        if let Some(clock) = &mut self.clock {
+            clock.reset();
+        }
+    }
+
+    /// Gives the game up to `piece`'s opponent, if there's one.
+    pub fn resign(&mut self, piece: Piece) -> Response {
+        if !self.players.contains_key(&piece.other()) {
+            return Response::Invalid(ErrorCode::NoOpponent);
+        }
+
+        self.finish(Some(piece.other()), false);
+        Response::Resigned {
+            piece,
+            turn: self.turn,
+        }
+    }
 
+    /// What `piece` says to the room, cut to a reasonable length.
+    pub fn chat(&self, piece: Piece, text: &str) -> Response {
+        let text: String = text.trim().chars().take(MAX_CHAT_LEN).collect();
+        match self.name(piece) {
+            Some(name) if !text.is_empty() => Response::Chat {
+                piece,
+                name: name.to_string(),
+                text,
+            },
+            Some(_) => Response::Invalid(ErrorCode::Malformed),
+            None => Response::Invalid(ErrorCode::NotSeated),
+        }
+    }
+
+    /// Moves `piece` would take back: its last one and any reply made after it.
+    fn takeback_len(&self, piece: Piece) -> Option<usize> {
+        let last = *self.history.last()?;
+        if self.board[last] == Some(piece) {
+            Some(1)
+        } else {
+            (self.history.len() >= 2).then_some(2)
+        }
+    }
+
+    pub fn propose_takeback(&mut self, piece: Piece) -> Response {
+        if self.takeback_len(piece).is_none() {
+            return Response::Invalid(ErrorCode::NothingToTakeBack);
+        }
+
+        if !self.players.contains_key(&piece.other()) {
+            return Response::Invalid(ErrorCode::NoOpponent);
+        }
+
+        self.takeback = Some(piece);
+        Response::TakebackOffer(piece)
+    }
+
+    /// Answers the opponent's pending takeback, restoring the board if `accept`, as of
+    /// `now` for the clocks.
+    pub fn answer_takeback(&mut self, piece: Piece, accept: bool, now: Instant) -> Response {
+        let Some(proposer) = self.takeback.filter(|&p| p != piece) else {
+            return Response::Invalid(ErrorCode::NoPendingTakeback);
+        };
+
+        self.takeback = None;
+        if !accept {
+            return Response::TakebackDeclined;
+        }
+
+        let Some(len) = self.takeback_len(proposer) else {
+            return Response::Invalid(ErrorCode::NothingToTakeBack);
+        };
+
+        for idx in self.history.split_off(self.history.len() - len) {
+            self.board.unmake_move(idx).ok();
+        }
+
+        self.turn = proposer;
+        if let Some(clock) = &mut self.clock {
+            clock.switch(proposer, now);
+        }
+
+        Response::Takeback {
+            board: self.board,
+            turn: self.turn,
+        }
+    }
+
+    pub fn send(&mut self, piece: Piece, res: Response) -> io::Result<()> {
         self.players
             .get_mut(&piece)
-            .map(|stream| write_str(stream, &json))
+            .map(|player| player.sink.send(&res))
             .ok_or(io_err!("Failed to send reponse"))?
     }
 
-    pub fn broadcast(&mut self, res: Response) -> io::Result<()> {
-        let json = serde_json::to_string(&res)?;
+    pub fn send_spectator(&mut self, id: u64, res: Response) -> io::Result<()> {
+        self.spectators
+            .get_mut(&id)
+            .map(|sink| sink.send(&res))
+            .ok_or(io_err!("Failed to send reponse"))?
+    }
 
-        for stream in self.players.values_mut() {
-            write_str(stream, &json)?;
+    /// Sends `res` to every player and spectator. Sinks don't wait on clients: one that
+    /// can't be written to, or doesn't keep up, is hung up on and its handler then frees
+    /// its seat.
+    pub fn broadcast(&mut self, res: Response) {
+        for player in self.players.values_mut() {
+            player.sink.send(&res).ok();
         }
 
-        Ok(())
+        for sink in self.spectators.values_mut() {
+            sink.send(&res).ok();
+        }
     }
 }
 
@@ -95,8 +398,224 @@ impl Default for Game {
         Self {
             board: Board::new(),
             players: BTreeMap::new(),
+            spectators: BTreeMap::new(),
+            next_spectator: 0,
+            hints: true,
+            room: String::new(),
+            recorder: None,
+            clock: None,
+            history: Vec::new(),
+            takeback: None,
             turn: Piece::default(),
             started: Piece::default(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::time::Duration;
+    use std::{env, fs, process, thread};
+
+    struct Nowhere;
+
+    impl Sink for Nowhere {
+        fn send(&mut self, _: &Response) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn close(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn game_with_moves(moves: &[(usize, usize)]) -> Game {
+        let mut game = Game::new();
+        for &idx in moves {
+            let piece = game.turn;
+            assert!(matches!(
+                game.play(piece, idx, Instant::now()),
+                Response::Valid { .. }
+            ));
+        }
+
+        game
+    }
+
+    #[test]
+    fn takeback_own_move() {
+        let mut game = game_with_moves(&[(1, 1)]);
+        game.takeback = Some(Piece::X);
+
+        let res = game.answer_takeback(Piece::O, true, Instant::now());
+        assert!(matches!(res, Response::Takeback { turn: Piece::X, .. }));
+        assert!(game.board[(1, 1)].is_none());
+        assert!(game.history.is_empty());
+    }
+
+    #[test]
+    fn takeback_after_reply() {
+        let mut game = game_with_moves(&[(1, 1), (0, 0)]);
+        game.takeback = Some(Piece::X);
+
+        let res = game.answer_takeback(Piece::O, true, Instant::now());
+        assert!(matches!(res, Response::Takeback { turn: Piece::X, .. }));
+        assert!(game.board[(1, 1)].is_none());
+        assert!(game.board[(0, 0)].is_none());
+    }
+
+    #[test]
+    fn takeback_declined() {
+        let mut game = game_with_moves(&[(1, 1)]);
+        game.takeback = Some(Piece::X);
+
+        assert!(matches!(
+            game.answer_takeback(Piece::X, true, Instant::now()),
+            Response::Invalid(ErrorCode::NoPendingTakeback)
+        ));
+
+        let res = game.answer_takeback(Piece::O, false, Instant::now());
+        assert!(matches!(res, Response::TakebackDeclined));
+        assert_eq!(Some(Piece::X), game.board[(1, 1)]);
+        assert!(matches!(
+            game.answer_takeback(Piece::O, true, Instant::now()),
+            Response::Invalid(ErrorCode::NoPendingTakeback)
+        ));
+    }
+
+    #[test]
+    fn takeback_without_moves() {
+        let mut game = Game::new();
+        assert!(matches!(
+            game.propose_takeback(Piece::X),
+            Response::Invalid(ErrorCode::NothingToTakeBack)
+        ));
+
+        let mut game = game_with_moves(&[(1, 1)]);
+        assert!(matches!(
+            game.propose_takeback(Piece::O),
+            Response::Invalid(ErrorCode::NothingToTakeBack)
+        ));
+    }
+
+    #[test]
+    fn set_position() {
+        let mut game = game_with_moves(&[(1, 1)]);
+        game.set_position(Position::from_str("X1O/1X1/3 O 3 3").unwrap());
+
+        assert_eq!("X1O/1X1/3 O 3 3", game.position().to_string());
+        let res = game.play(Piece::X, (2, 2), Instant::now());
+        assert!(matches!(res, Response::Invalid(ErrorCode::NotYourTurn)));
+
+        let res = game.play(Piece::O, (1, 0), Instant::now());
+        assert!(matches!(res, Response::Valid { turn: Piece::X, .. }));
+
+        // X started this game, so O starts the next one.
+        let res = game.play(Piece::X, (2, 2), Instant::now());
+        assert!(matches!(res, Response::Valid { turn: Piece::O, .. }));
+        assert_eq!(Board::new(), game.board);
+    }
+
+    #[test]
+    fn invalid_moves() {
+        let mut game = game_with_moves(&[(1, 1)]);
+
+        let res = game.play(Piece::O, (5, 0), Instant::now());
+        assert!(matches!(res, Response::Invalid(ErrorCode::OutOfBounds)));
+
+        let res = game.play(Piece::O, (0, 3), Instant::now());
+        assert!(matches!(res, Response::Invalid(ErrorCode::OutOfBounds)));
+
+        let res = game.play(Piece::O, (1, 1), Instant::now());
+        assert!(matches!(res, Response::Invalid(ErrorCode::Occupied)));
+
+        let res = game.play(Piece::X, (0, 0), Instant::now());
+        assert!(matches!(res, Response::Invalid(ErrorCode::NotYourTurn)));
+        assert_eq!(Piece::O, game.turn);
+    }
+
+    #[test]
+    fn move_cancels_takeback() {
+        let mut game = game_with_moves(&[(1, 1)]);
+        game.takeback = Some(Piece::X);
+        game.play(Piece::O, (0, 0), Instant::now());
+
+        assert!(matches!(
+            game.answer_takeback(Piece::O, true, Instant::now()),
+            Response::Invalid(ErrorCode::NoPendingTakeback)
+        ));
+    }
+
+    #[test]
+    fn resign() {
+        let mut game = game_with_moves(&[(1, 1)]);
+        assert!(matches!(
+            game.resign(Piece::O),
+            Response::Invalid(ErrorCode::NoOpponent)
+        ));
+
+        game.assign_piece(Box::new(Nowhere), "Alex");
+
+        assert!(matches!(
+            game.resign(Piece::O),
+            Response::Resigned {
+                piece: Piece::O,
+                turn: Piece::O
+            }
+        ));
+        assert_eq!(Board::new(), game.board);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut game = Game::new();
+        game.set_time_control(Some(TimeControl {
+            initial: Duration::from_millis(50),
+            increment: Duration::ZERO,
+        }));
+
+        assert!(matches!(
+            game.play(Piece::X, (1, 1), Instant::now()),
+            Response::Valid {
+                clock: Some([50, 50]),
+                ..
+            }
+        ));
+
+        thread::sleep(Duration::from_millis(60));
+        let res = game.play(Piece::O, (0, 0), Instant::now());
+        assert!(matches!(
+            res,
+            Response::Timeout {
+                piece: Piece::O,
+                turn: Piece::O
+            }
+        ));
+        assert_eq!(Board::new(), game.board);
+    }
+
+    #[test]
+    fn records_finished_games() {
+        let path = env::temp_dir().join(format!("games-{}.jsonl", process::id()));
+        let mut game = Game::new();
+        game.room = "zoo".to_string();
+        game.recorder = Some(Arc::new(Recorder::open(&path).unwrap()));
+
+        for idx in [(1, 1), (0, 0), (0, 1), (2, 0), (2, 1)] {
+            let piece = game.turn;
+            game.play(piece, idx, Instant::now());
+        }
+
+        let text = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).ok();
+
+        let record: GameRecord = serde_json::from_str(text.trim()).unwrap();
+        assert_eq!("zoo", record.room);
+        assert_eq!("Player X", record.x);
+        assert_eq!("1. b2 a3 2. b3 a1 3. b1", record.moves);
+        assert_eq!(Some(Piece::X), record.winner);
+        assert!(!record.on_time);
+    }
+}
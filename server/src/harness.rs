@@ -0,0 +1,399 @@
+//! End-to-end scenarios: a server on fresh rooms, and scripted clients sending it
+//! requests and checking every response they get, in order. Clients connect in memory
+//! or over TCP, and either way go through the same handlers as real ones.
+
+use crate::config::{Config, Settings};
+use crate::server::{Context, Server};
+use crate::threadpool::ThreadPool;
+use crate::transport::{Listener, MemoryClient, Protocol};
+use core::request::Request;
+use core::response::Response;
+use core::{read_str, write_str};
+use std::io;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+/// How long a client waits for a response before failing the scenario.
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client listens to make sure nothing comes.
+const QUIET_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Checks the next response `$client` gets matches a pattern, naming both if it doesn't.
+macro_rules! expect {
+    ($client:expr, $($pattern:tt)+) => {
+        $client.expect(stringify!($($pattern)+), |res| matches!(res, $($pattern)+))
+    };
+}
+
+/// A server nobody else talks to.
+pub struct Harness {
+    context: Context,
+    address: Option<SocketAddr>,
+}
+
+impl Harness {
+    /// A server only reachable in memory.
+    pub fn new(settings: Settings) -> Self {
+        Self {
+            context: Context::new(settings),
+            address: None,
+        }
+    }
+
+    /// A server also accepting TCP clients on an ephemeral port, through a thread pool
+    /// sized like the one the default config ships with.
+    pub fn tcp(settings: Settings) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let context = Context::new(settings);
+        let shared = context.clone();
+        thread::spawn(move || {
+            let pool = ThreadPool::new(Config::default().pool());
+            Server::accept_loop(Listener::Tcp(listener), Protocol::Framed, shared, &pool);
+        });
+
+        Self {
+            context,
+            address: Some(address),
+        }
+    }
+
+    /// A client connected in memory, called `name` in failures.
+    pub fn client(&self, name: &str) -> Client {
+        let mut conn = self.context.connect().unwrap();
+        conn.set_read_timeout(Some(RECV_TIMEOUT));
+        Client {
+            name: name.to_string(),
+            conn: Conn::Memory(conn),
+        }
+    }
+
+    /// A client connected over TCP, called `name` in failures.
+    pub fn tcp_client(&self, name: &str) -> Client {
+        let address = self
+            .address
+            .expect("Not listening on TCP, use `Harness::tcp`");
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(RECV_TIMEOUT)).unwrap();
+        Client {
+            name: name.to_string(),
+            conn: Conn::Tcp(stream),
+        }
+    }
+}
+
+enum Conn {
+    Memory(MemoryClient),
+    Tcp(TcpStream),
+}
+
+/// One side of a scenario. Every method panics, naming the client, as soon as the
+/// server doesn't answer as expected.
+pub struct Client {
+    name: String,
+    conn: Conn,
+}
+
+impl Client {
+    pub fn send(&mut self, req: Request) -> &mut Self {
+        let sent = match &mut self.conn {
+            Conn::Memory(conn) => conn.send(&req),
+            Conn::Tcp(stream) => write_str(stream, &serde_json::to_string(&req).unwrap()),
+        };
+
+        if let Err(e) = sent {
+            panic!("{} couldn't send {req:?}: {e}", self.name);
+        }
+
+        self
+    }
+
+    /// Joins the lobby as `name`.
+    pub fn join(&mut self) -> &mut Self {
+        let name = self.name.clone();
+        self.send(Request::Join {
+            room: String::new(),
+            name,
+        })
+    }
+
+    pub fn play(&mut self, idx: (usize, usize)) -> &mut Self {
+        self.send(Request::Play { idx })
+    }
+
+    fn try_recv(&mut self) -> io::Result<Response> {
+        match &mut self.conn {
+            Conn::Memory(conn) => conn.recv(),
+            Conn::Tcp(stream) => {
+                let json = read_str(stream)?;
+                Ok(serde_json::from_str(&json)?)
+            }
+        }
+    }
+
+    /// The next response.
+    pub fn recv(&mut self) -> Response {
+        match self.try_recv() {
+            Ok(res) => res,
+            Err(e) => panic!("{} got no response: {e}", self.name),
+        }
+    }
+
+    /// Checks the next response passes `check`, described by `what`. The [`expect!`]
+    /// macro describes patterns by themselves.
+    pub fn expect(&mut self, what: &str, check: impl FnOnce(&Response) -> bool) -> &mut Self {
+        let res = self.recv();
+        assert!(check(&res), "{} expected {what}, got {res:?}", self.name);
+        self
+    }
+
+    /// Checks nothing more comes for a while.
+    pub fn quiet(&mut self) -> &mut Self {
+        let res = match &mut self.conn {
+            Conn::Memory(conn) => {
+                conn.set_read_timeout(Some(QUIET_TIMEOUT));
+                let res = conn.recv();
+                conn.set_read_timeout(Some(RECV_TIMEOUT));
+                res
+            }
+            Conn::Tcp(stream) => {
+                stream.set_read_timeout(Some(QUIET_TIMEOUT)).unwrap();
+                let res = read_str(stream).map(|json| serde_json::from_str(&json).unwrap());
+                stream.set_read_timeout(Some(RECV_TIMEOUT)).unwrap();
+                res
+            }
+        };
+
+        if let Ok(res) = res {
+            panic!("{} expected nothing, got {res:?}", self.name);
+        }
+
+        self
+    }
+
+    /// Checks the server hung up.
+    pub fn closed(&mut self) {
+        if let Ok(res) = self.try_recv() {
+            panic!("{} expected the server to hang up, got {res:?}", self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::error::ErrorCode;
+    use core::game::{piece::Piece, state::GameState};
+
+    /// X and O seated in the lobby of `harness`, done with greetings.
+    fn seat(harness: &Harness, tcp: bool) -> (Client, Client) {
+        let connect = |name| match tcp {
+            true => harness.tcp_client(name),
+            false => harness.client(name),
+        };
+
+        let mut x = connect("Xavier");
+        expect!(
+            x.join(),
+            Response::Init {
+                piece: Piece::X,
+                opponent: None,
+                ..
+            }
+        );
+
+        let mut o = connect("Olga");
+        expect!(o.join(), Response::Init { piece: Piece::O, opponent: Some(name), .. } if name == "Xavier");
+        expect!(x, Response::Connect { piece: Piece::O, name } if name == "Olga");
+        (x, o)
+    }
+
+    /// Plays `moves` in turns from X, checking both players see each one.
+    fn play(x: &mut Client, o: &mut Client, moves: &[(usize, usize)]) {
+        for (i, &idx) in moves.iter().enumerate() {
+            let piece = [Piece::X, Piece::O][i % 2];
+            match piece {
+                Piece::X => x.play(idx),
+                Piece::O => o.play(idx),
+            };
+
+            for player in [&mut *x, &mut *o] {
+                expect!(player, Response::Valid { piece: p, idx: cell, .. } if *p == piece && *cell == idx);
+            }
+        }
+    }
+
+    #[test]
+    fn join() {
+        for tcp in [false, true] {
+            let harness = Harness::tcp(Settings::default());
+            let (mut x, mut o) = seat(&harness, tcp);
+            x.quiet();
+            o.quiet();
+        }
+    }
+
+    #[test]
+    fn moves() {
+        let harness = Harness::new(Settings::default());
+        let (mut x, mut o) = seat(&harness, false);
+
+        x.play((1, 1));
+        for player in [&mut x, &mut o] {
+            expect!(
+                player,
+                Response::Valid {
+                    piece: Piece::X,
+                    idx: (1, 1),
+                    state: GameState::Playing,
+                    turn: Piece::O,
+                    ..
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_moves() {
+        let harness = Harness::tcp(Settings::default());
+        let (mut x, mut o) = seat(&harness, true);
+
+        expect!(o.play((0, 0)), Response::Invalid(ErrorCode::NotYourTurn));
+        play(&mut x, &mut o, &[(1, 1)]);
+        expect!(o.play((1, 1)), Response::Invalid(ErrorCode::Occupied));
+        expect!(o.play((3, 0)), Response::Invalid(ErrorCode::OutOfBounds));
+        x.quiet();
+
+        // Still O's turn after all that.
+        o.play((0, 0));
+        expect!(
+            x,
+            Response::Valid {
+                piece: Piece::O,
+                ..
+            }
+        );
+    }
+
+    #[test]
+    fn win() {
+        let harness = Harness::new(Settings::default());
+        let (mut x, mut o) = seat(&harness, false);
+
+        play(&mut x, &mut o, &[(0, 0), (1, 0), (0, 1), (1, 1)]);
+        x.play((0, 2));
+        for player in [&mut x, &mut o] {
+            expect!(
+                player,
+                Response::Valid {
+                    idx: (0, 2),
+                    state: GameState::Win(Piece::X),
+                    ..
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn stalemate() {
+        let harness = Harness::tcp(Settings::default());
+        let (mut x, mut o) = seat(&harness, true);
+
+        // X O X
+        // X O O
+        // O X X
+        #[rustfmt::skip]
+        let moves = [(0, 0), (0, 1), (0, 2), (1, 1), (1, 0), (1, 2), (2, 1), (2, 0)];
+        play(&mut x, &mut o, &moves);
+        x.play((2, 2));
+        for player in [&mut x, &mut o] {
+            expect!(
+                player,
+                Response::Valid {
+                    idx: (2, 2),
+                    state: GameState::Stalemate,
+                    ..
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn disconnect() {
+        let harness = Harness::new(Settings::default());
+        let (mut x, mut o) = seat(&harness, false);
+        o.send(Request::Disconnect);
+        expect!(x, Response::Disconnect(Piece::O));
+
+        // Hanging up without a word counts the same.
+        let harness = Harness::tcp(Settings::default());
+        let (mut x, o) = seat(&harness, true);
+        drop(o);
+        expect!(x, Response::Disconnect(Piece::O));
+
+        // The seat is free again.
+        let mut late = harness.client("Late");
+        expect!(
+            late.join(),
+            Response::Init {
+                piece: Piece::O,
+                ..
+            }
+        );
+        expect!(
+            x,
+            Response::Connect {
+                piece: Piece::O,
+                ..
+            }
+        );
+    }
+
+    #[test]
+    fn local_clients_are_not_limited() {
+        let harness = Harness::tcp(Settings {
+            max_connections_per_ip: Some(1),
+            ..Settings::default()
+        });
+
+        let mut x = harness.tcp_client("Xavier");
+        expect!(
+            x.join(),
+            Response::Init {
+                piece: Piece::X,
+                ..
+            }
+        );
+
+        // In memory, everyone is on 127.0.0.1 too.
+        let mut o = harness.client("Olga");
+        expect!(
+            o.join(),
+            Response::Init {
+                piece: Piece::O,
+                ..
+            }
+        );
+        let mut third = harness.client("Thea");
+        expect!(third.join(), Response::Invalid(ErrorCode::RoomFull));
+
+        let mut fourth = harness.tcp_client("Fred");
+        expect!(fourth, Response::Invalid(ErrorCode::TooManyConnections));
+    }
+
+    #[test]
+    fn third_player() {
+        let harness = Harness::tcp(Settings::default());
+        let (mut x, mut o) = seat(&harness, true);
+
+        let mut third = harness.tcp_client("Thea");
+        expect!(third.join(), Response::Invalid(ErrorCode::RoomFull));
+        third.closed();
+        x.quiet();
+        o.quiet();
+
+        play(&mut x, &mut o, &[(1, 1)]);
+    }
+}
@@ -0,0 +1,338 @@
+//! Limits on how much a client may ask of the server: requests per connection and per
+//! address, connections per address, and bans for clients that keep breaking the
+//! protocol.
+
+use crate::config::{positive, secs, Settings};
+use crate::server::lock;
+use core::error::ErrorCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::NonZeroU32;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How many requests a connection may send.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// Requests per second allowed in the long run.
+    #[serde(deserialize_with = "positive")]
+    pub rate: f64,
+    /// Requests allowed in a quick burst.
+    #[serde(deserialize_with = "positive")]
+    pub burst: f64,
+}
+
+/// When a client breaking the protocol gets banned.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ban {
+    /// Violations, like malformed requests, oversized frames or not reading what's sent,
+    /// that get an address banned. They're forgotten after `duration` without any.
+    pub strikes: NonZeroU32,
+    /// Seconds a banned address is turned away for.
+    #[serde(deserialize_with = "secs")]
+    pub duration: Duration,
+}
+
+/// Token bucket refilled at `rate` per second up to `burst` tokens, each request takes one.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit, now: Instant) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst,
+            last: now,
+        }
+    }
+
+    /// Takes a token, `false` if there's none left.
+    pub fn take(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate).min(self.limit.burst);
+        self.last = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// What's known about one address.
+#[derive(Debug)]
+struct Client {
+    connections: usize,
+    bucket: Option<TokenBucket>,
+    strikes: u32,
+    /// When the strikes are forgotten.
+    forgiven: Instant,
+    banned: Option<Instant>,
+}
+
+impl Client {
+    fn new(now: Instant) -> Self {
+        Self {
+            connections: 0,
+            bucket: None,
+            strikes: 0,
+            forgiven: now,
+            banned: None,
+        }
+    }
+
+    fn banned(&self, now: Instant) -> bool {
+        self.banned.is_some_and(|until| now < until)
+    }
+
+    /// Whether there's nothing worth remembering about the address anymore.
+    fn forgotten(&self, now: Instant) -> bool {
+        self.connections == 0 && now >= self.forgiven && !self.banned(now)
+    }
+}
+
+/// Connections, requests and violations of every address connected lately.
+#[derive(Debug, Default)]
+pub struct Clients {
+    clients: Mutex<HashMap<IpAddr, Client>>,
+}
+
+impl Clients {
+    /// Counts a new connection from `ip`, unless the address is banned or already has
+    /// as many connections as `settings` allow. The connection is counted until the
+    /// returned [`Peer`] is dropped.
+    pub fn connect(
+        self: &Arc<Self>,
+        ip: IpAddr,
+        settings: &Settings,
+        now: Instant,
+    ) -> Result<Peer, ErrorCode> {
+        let mut clients = lock(&self.clients);
+        clients.retain(|_, client| !client.forgotten(now));
+
+        let client = clients.entry(ip).or_insert_with(|| Client::new(now));
+        if client.banned(now) {
+            return Err(ErrorCode::Banned);
+        }
+
+        if settings
+            .max_connections_per_ip
+            .is_some_and(|max| client.connections >= max)
+        {
+            return Err(ErrorCode::TooManyConnections);
+        }
+
+        client.connections += 1;
+        Ok(Peer {
+            clients: Arc::clone(self),
+            ip: Some(ip),
+            rate_limit: settings.ip_rate_limit,
+            ban: settings.ban,
+        })
+    }
+
+    /// A connection from the server's own machine without an address of its own, like a
+    /// bot in memory or a client on a Unix socket. Those all look like `127.0.0.1`, so
+    /// they're neither counted, rate limited nor banned as one address.
+    pub fn local(self: &Arc<Self>) -> Peer {
+        Peer {
+            clients: Arc::clone(self),
+            ip: None,
+            rate_limit: None,
+            ban: None,
+        }
+    }
+}
+
+/// One connection from an address, with the limits in force when it was made.
+#[derive(Debug)]
+pub struct Peer {
+    clients: Arc<Clients>,
+    /// `None` for local connections, which no limit applies to.
+    ip: Option<IpAddr>,
+    rate_limit: Option<RateLimit>,
+    ban: Option<Ban>,
+}
+
+impl Peer {
+    /// Takes a token from the bucket every connection of the address shares, `false` if
+    /// there's none left.
+    pub fn take(&self, now: Instant) -> bool {
+        let (Some(limit), Some(ip)) = (self.rate_limit, self.ip) else {
+            return true;
+        };
+
+        let mut clients = lock(&self.clients.clients);
+        let Some(client) = clients.get_mut(&ip) else {
+            return true;
+        };
+
+        let bucket = match &mut client.bucket {
+            Some(bucket) if bucket.limit == limit => bucket,
+            bucket => bucket.insert(TokenBucket::new(limit, now)),
+        };
+
+        bucket.take(now)
+    }
+
+    /// Counts a protocol violation, `true` if it got the address banned.
+    pub fn strike(&self, now: Instant) -> bool {
+        let (Some(ban), Some(ip)) = (self.ban, self.ip) else {
+            return false;
+        };
+
+        let mut clients = lock(&self.clients.clients);
+        let Some(client) = clients.get_mut(&ip) else {
+            return false;
+        };
+
+        if now >= client.forgiven {
+            client.strikes = 0;
+        }
+
+        client.strikes += 1;
+        client.forgiven = now + ban.duration;
+        if client.strikes < ban.strikes.get() {
+            return false;
+        }
+
+        client.strikes = 0;
+        client.banned = Some(now + ban.duration);
+        true
+    }
+}
+
+impl Drop for Peer {
+    fn drop(&mut self) {
+        let Some(ip) = self.ip else {
+            return;
+        };
+
+        if let Some(client) = lock(&self.clients.clients).get_mut(&ip) {
+            client.connections -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn settings() -> Settings {
+        Settings {
+            max_connections_per_ip: Some(2),
+            ip_rate_limit: Some(RateLimit {
+                rate: 1.0,
+                burst: 3.0,
+            }),
+            ban: Some(Ban {
+                strikes: NonZeroU32::new(2).unwrap(),
+                duration: Duration::from_secs(60),
+            }),
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn bucket() {
+        let start = Instant::now();
+        let limit = RateLimit {
+            rate: 2.0,
+            burst: 3.0,
+        };
+
+        let mut bucket = TokenBucket::new(limit, start);
+        assert!((0..3).all(|_| bucket.take(start)));
+        assert!(!bucket.take(start));
+
+        let later = start + Duration::from_millis(500);
+        assert!(bucket.take(later));
+        assert!(!bucket.take(later));
+
+        let much_later = later + Duration::from_secs(60);
+        assert_eq!(3, (0..10).filter(|_| bucket.take(much_later)).count());
+    }
+
+    #[test]
+    fn connections_per_ip() {
+        let now = Instant::now();
+        let clients = Arc::new(Clients::default());
+        let first = clients.connect(IP, &settings(), now).unwrap();
+        let _second = clients.connect(IP, &settings(), now).unwrap();
+        assert_eq!(
+            ErrorCode::TooManyConnections,
+            clients.connect(IP, &settings(), now).unwrap_err()
+        );
+
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert!(clients.connect(other, &settings(), now).is_ok());
+
+        drop(first);
+        assert_eq!(1, lock(&clients.clients)[&IP].connections);
+        assert!(clients.connect(IP, &settings(), now).is_ok());
+    }
+
+    #[test]
+    fn shared_bucket() {
+        let now = Instant::now();
+        let clients = Arc::new(Clients::default());
+        let first = clients.connect(IP, &settings(), now).unwrap();
+        let second = clients.connect(IP, &settings(), now).unwrap();
+
+        assert!(first.take(now) && second.take(now) && first.take(now));
+        assert!(!second.take(now));
+        assert!(first.take(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn bans() {
+        let now = Instant::now();
+        let clients = Arc::new(Clients::default());
+        let peer = clients.connect(IP, &settings(), now).unwrap();
+
+        // Strikes are forgotten after a while without any.
+        assert!(!peer.strike(now));
+        let later = now + Duration::from_secs(61);
+        assert!(!peer.strike(later));
+        assert!(peer.strike(later));
+        drop(peer);
+
+        let err = clients.connect(IP, &settings(), later).unwrap_err();
+        assert_eq!(ErrorCode::Banned, err);
+
+        let much_later = later + Duration::from_secs(60);
+        assert!(clients.connect(IP, &settings(), much_later).is_ok());
+    }
+
+    #[test]
+    fn local_peers_are_exempt() {
+        let now = Instant::now();
+        let clients = Arc::new(Clients::default());
+        let _first = clients.connect(IP, &settings(), now).unwrap();
+        let _second = clients.connect(IP, &settings(), now).unwrap();
+
+        let local: Vec<_> = (0..5).map(|_| clients.local()).collect();
+        assert!(local.iter().all(|peer| peer.take(now) && !peer.strike(now)));
+        assert!(local.iter().all(|peer| !peer.strike(now)));
+        assert_eq!(2, lock(&clients.clients)[&IP].connections);
+
+        drop(local);
+        assert_eq!(2, lock(&clients.clients)[&IP].connections);
+        assert_eq!(
+            ErrorCode::TooManyConnections,
+            clients.connect(IP, &settings(), now).unwrap_err()
+        );
+    }
+}
@@ -0,0 +1,359 @@
+//! A protocol for people typing into `nc` or `telnet`: one command per line, like
+//! `play b2`, answered with lines of text and ASCII boards. Commands are turned into the
+//! same requests as other clients send, so everyone shares the same rooms.
+
+use crate::transport::{Sink, Transport};
+use core::game::{board::Board, piece::Piece, state::GameState};
+use core::io_err;
+use core::notation::{format_cell, parse_cell};
+use core::request::Request;
+use core::response::Response;
+use core::stream::Stream;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::time::Duration;
+
+/// Longest line read, longer ones are malformed like oversized frames.
+const MAX_LINE_LEN: u64 = 1024;
+
+/// Least time people typing may stay silent and keep their seat. They don't ping like
+/// programs do, and may well read the help or think a move over for a while.
+const MIN_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+const HELP: &str = "\
+join [ROOM] [NAME]  take a seat in ROOM, the lobby by default
+watch [ROOM]        watch ROOM
+play CELL           play CELL, from a1 (bottom left) to c3 or 1 to 9 like a numpad
+undo                ask to take back your last move
+accept, decline     answer a takeback
+resign              give the game up
+chat TEXT           say TEXT to the room
+ping                check the server is there
+quit                leave
+";
+
+/// What `line` asks for, or why it can't be understood.
+fn parse(line: &str) -> Result<Request, String> {
+    let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let rest = rest.trim();
+    let mut args = rest.split_whitespace();
+    let req = match command.to_lowercase().as_str() {
+        "join" => Request::Join {
+            room: args.next().unwrap_or_default().to_string(),
+            name: args.collect::<Vec<_>>().join(" "),
+        },
+        "watch" => Request::Spectate {
+            room: rest.to_string(),
+        },
+        "play" => match parse_cell(rest) {
+            Ok(idx) => Request::Play { idx },
+            Err(e) => return Err(e.to_string()),
+        },
+        "undo" => Request::Takeback,
+        "accept" => Request::AcceptTakeback,
+        "decline" => Request::DeclineTakeback,
+        "resign" => Request::Resign,
+        "chat" => Request::Chat(rest.to_string()),
+        "ping" => Request::Ping(0),
+        "quit" => Request::Disconnect,
+        _ => return Err(format!("Unknown command `{command}`, try `help`")),
+    };
+
+    Ok(req)
+}
+
+fn turn(out: &mut String, board: &Board, turn: Piece) {
+    write!(out, "\n{board:?}\n{turn} to move\n").ok();
+}
+
+/// `res` as lines of text.
+fn render(res: &Response) -> String {
+    let mut out = String::new();
+    match res {
+        Response::Valid {
+            piece, idx, state, ..
+        } => {
+            writeln!(out, "{piece} played {}", format_cell(*idx)).ok();
+            match state {
+                GameState::Win(winner) => writeln!(out, "{winner} wins, new game"),
+                GameState::Stalemate => writeln!(out, "Stalemate, new game"),
+                GameState::Playing => Ok(()),
+            }
+            .ok();
+        }
+
+        Response::Invalid(code) => {
+            writeln!(out, "Refused: {code:?}").ok();
+        }
+
+        Response::Init {
+            board,
+            piece,
+            turn: next,
+            opponent,
+            ..
+        } => {
+            match opponent {
+                Some(name) => writeln!(out, "You play {piece} against {name}"),
+                None => writeln!(out, "You play {piece}, waiting for an opponent"),
+            }
+            .ok();
+            turn(&mut out, board, *next);
+        }
+
+        Response::Watch {
+            board,
+            turn: next,
+            players,
+        } => {
+            for (piece, name) in players {
+                writeln!(out, "{name} plays {piece}").ok();
+            }
+
+            turn(&mut out, board, *next);
+        }
+
+        Response::Connect { piece, name } => {
+            writeln!(out, "{name} joined as {piece}").ok();
+        }
+
+        Response::Disconnect(piece) => {
+            writeln!(out, "{piece} left").ok();
+        }
+
+        Response::TakebackOffer(piece) => {
+            writeln!(
+                out,
+                "{piece} asks to take back a move, `accept` or `decline`"
+            )
+            .ok();
+        }
+
+        Response::TakebackDeclined => {
+            writeln!(out, "Takeback declined").ok();
+        }
+
+        Response::Takeback { board, turn: next } => {
+            writeln!(out, "Move taken back").ok();
+            turn(&mut out, board, *next);
+        }
+
+        Response::Pong(_) => {
+            writeln!(out, "pong").ok();
+        }
+
+        Response::Timeout { piece, turn: next } => {
+            writeln!(out, "{piece} ran out of time, new game with {next} to move").ok();
+        }
+
+        Response::Resigned { piece, turn: next } => {
+            writeln!(out, "{piece} resigned, new game with {next} to move").ok();
+        }
+
+        Response::Chat { piece, name, text } => {
+            writeln!(out, "{name} ({piece}): {text}").ok();
+        }
+    }
+
+    out
+}
+
+/// Keeps the board of the room, which moves don't carry, to draw it after each one.
+struct LineSink {
+    stream: Stream,
+    board: Board,
+}
+
+impl Sink for LineSink {
+    fn send(&mut self, res: &Response) -> io::Result<()> {
+        let mut out = render(res);
+        match res {
+            Response::Valid {
+                piece,
+                idx,
+                state,
+                turn: next,
+                ..
+            } => {
+                self.board.make_move(*idx, *piece).ok();
+                turn(&mut out, &self.board, *next);
+                if *state != GameState::Playing {
+                    self.board.clear();
+                }
+            }
+
+            Response::Init { board, .. }
+            | Response::Watch { board, .. }
+            | Response::Takeback { board, .. } => self.board = *board,
+            Response::Timeout { .. } | Response::Resigned { .. } => self.board.clear(),
+            _ => {}
+        }
+
+        self.stream.write_all(out.as_bytes())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
+
+/// A client typing commands.
+pub struct Connection {
+    reader: BufReader<Stream>,
+    writer: Stream,
+}
+
+impl Connection {
+    pub fn new(stream: Stream) -> io::Result<Self> {
+        let mut writer = stream.try_clone()?;
+        writer.write_all(b"Welcome! `join [ROOM] [NAME]` to play, `help` for every command\n")?;
+        writer.write_all(b"You keep your seat for 30 minutes without typing anything\n")?;
+        Ok(Self {
+            reader: BufReader::new(stream),
+            writer,
+        })
+    }
+}
+
+impl Sink for Connection {
+    fn send(&mut self, res: &Response) -> io::Result<()> {
+        self.writer.write_all(render(res).as_bytes())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.writer.shutdown(Shutdown::Both)
+    }
+}
+
+impl Transport for Connection {
+    /// The next command, as the request it stands for. Commands that can't be understood
+    /// are answered here, without bothering the game.
+    fn recv(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            let mut line = String::new();
+            let read = (&mut self.reader).take(MAX_LINE_LEN).read_line(&mut line)?;
+            if read == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            if !line.ends_with('\n') && read as u64 == MAX_LINE_LEN {
+                return Err(io_err!("Line too long"));
+            }
+
+            let answer = match line.trim() {
+                "" => continue,
+                "help" => HELP.to_string(),
+                line => match parse(line) {
+                    Ok(req) => return Ok(serde_json::to_vec(&req)?),
+                    Err(e) => format!("{e}\n"),
+                },
+            };
+
+            self.writer.write_all(answer.as_bytes())?;
+        }
+    }
+
+    fn sink(&self) -> io::Result<Box<dyn Sink>> {
+        Ok(Box::new(LineSink {
+            stream: self.writer.try_clone()?,
+            board: Board::new(),
+        }))
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.writer.peer_addr()
+    }
+
+    /// Never shorter than [`MIN_IDLE_TIMEOUT`].
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let timeout = timeout.map(|timeout| timeout.max(MIN_IDLE_TIMEOUT));
+        self.writer.set_read_timeout(timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands() {
+        assert_eq!(
+            Ok(Request::Join {
+                room: "zoo".to_string(),
+                name: "Marty the zebra".to_string()
+            }),
+            parse("join zoo Marty the zebra")
+        );
+        assert_eq!(
+            Ok(Request::Join {
+                room: String::new(),
+                name: String::new()
+            }),
+            parse("JOIN")
+        );
+        assert_eq!(Ok(Request::Play { idx: (1, 1) }), parse("play b2"));
+        assert_eq!(Ok(Request::Play { idx: (0, 2) }), parse("play 9"));
+        assert_eq!(Ok(Request::Resign), parse("resign"));
+        assert_eq!(
+            Ok(Request::Chat("hi  there".to_string())),
+            parse("chat hi  there ")
+        );
+        assert_eq!(Err("Invalid cell `d4`".to_string()), parse("play d4"));
+        assert!(parse("dance").unwrap_err().contains("try `help`"));
+    }
+
+    /// Both ends of a fresh TCP connection.
+    fn pair() -> (std::net::TcpStream, std::net::TcpStream) {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| {
+                let tx = std::net::TcpStream::connect(listener.local_addr()?)?;
+                Ok((tx, listener.accept()?.0))
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn patient_with_people() {
+        let (tx, _rx) = pair();
+        let connection = Connection::new(Stream::Tcp(tx.try_clone().unwrap())).unwrap();
+
+        connection
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+        assert_eq!(Some(MIN_IDLE_TIMEOUT), tx.read_timeout().unwrap());
+
+        let long = MIN_IDLE_TIMEOUT * 2;
+        connection.set_read_timeout(Some(long)).unwrap();
+        assert_eq!(Some(long), tx.read_timeout().unwrap());
+
+        connection.set_read_timeout(None).unwrap();
+        assert_eq!(None, tx.read_timeout().unwrap());
+    }
+
+    #[test]
+    fn boards() {
+        let (tx, rx) = pair();
+        let mut sink = LineSink {
+            stream: Stream::Tcp(tx),
+            board: Board::new(),
+        };
+
+        for (piece, idx) in [(Piece::X, (1, 1)), (Piece::O, (0, 0))] {
+            let res = Response::Valid {
+                piece,
+                idx,
+                state: GameState::Playing,
+                turn: piece.other(),
+                clock: None,
+            };
+            sink.send(&res).unwrap();
+        }
+        drop(sink);
+
+        let mut text = String::new();
+        BufReader::new(rx).read_to_string(&mut text).unwrap();
+        assert!(text.starts_with("X played b2\n\n   |   |   \n - + - + - \n   | X |"));
+        assert!(text.ends_with("O played a3\n\n O |   |   \n - + - + - \n   | X |   \n - + - + - \n   |   |   \n\nX to move\n"), "{text}");
+    }
+}
@@ -0,0 +1,260 @@
+//! Levelled logging, warnings and errors go to stderr and everything else to stdout.
+//!
+//! The `error!`, `warn!`, `info!` and `debug!` macros are available to every module
+//! declared after this one. Fields can go before the message, like
+//! `info!(cell = "b2", took_us = 12; "Played")`, and every message also carries the
+//! fields of the thread's [`scope`], e.g. the connection it serves.
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::fmt::{self, Arguments, Display, Write};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:5}", self.name())
+    }
+}
+
+/// How messages are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// `[INFO ] message key=value`, for people.
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static FORMAT: AtomicU8 = AtomicU8::new(Format::Text as u8);
+
+thread_local! {
+    static CONTEXT: RefCell<Vec<(&'static str, Value)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Drops every message less severe than `level`.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn set_format(format: Format) {
+    FORMAT.store(format as u8, Ordering::Relaxed);
+}
+
+fn format() -> Format {
+    match FORMAT.load(Ordering::Relaxed) {
+        0 => Format::Text,
+        _ => Format::Json,
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+/// Clears the thread's fields once dropped.
+pub struct Scope(());
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        CONTEXT.with(|context| context.borrow_mut().clear());
+    }
+}
+
+/// Starts attaching fields set with [`set`] to every message of this thread, until the
+/// returned guard is dropped.
+#[must_use]
+pub fn scope() -> Scope {
+    CONTEXT.with(|context| context.borrow_mut().clear());
+    Scope(())
+}
+
+/// Attaches `key` to every message of this thread's scope, replacing its old value.
+pub fn set<T: Serialize + ?Sized>(key: &'static str, value: &T) {
+    let value = self::value(value);
+    CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        match context.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, v)) => *v = value,
+            None => context.push((key, value)),
+        }
+    });
+}
+
+pub fn value<T: Serialize + ?Sized>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or(Value::Null)
+}
+
+pub fn log(level: Level, fields: &[(&str, Value)], args: Arguments) {
+    if !enabled(level) {
+        return;
+    }
+
+    let line = CONTEXT.with(|context| {
+        let context = context.borrow();
+        let fields = context
+            .iter()
+            .map(|(k, v)| (*k, v))
+            .chain(fields.iter().map(|(k, v)| (*k, v)));
+
+        let ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+
+        render(format(), level, ms, &args.to_string(), fields)
+    });
+
+    match level {
+        Level::Error | Level::Warn => eprintln!("{line}"),
+        Level::Info | Level::Debug => println!("{line}"),
+    }
+}
+
+/// One log line, `ms` being the time since the Unix epoch.
+fn render<'a>(
+    format: Format,
+    level: Level,
+    ms: u64,
+    msg: &str,
+    fields: impl Iterator<Item = (&'a str, &'a Value)>,
+) -> String {
+    let mut line = String::new();
+    match format {
+        Format::Text => {
+            write!(line, "[{level}] {msg}").ok();
+            for (key, value) in fields {
+                match value {
+                    Value::String(s) if !s.is_empty() && !s.contains(char::is_whitespace) => {
+                        write!(line, " {key}={s}").ok();
+                    }
+
+                    _ => {
+                        write!(line, " {key}={value}").ok();
+                    }
+                }
+            }
+        }
+
+        Format::Json => {
+            let level = level.name().to_lowercase();
+            write!(
+                line,
+                r#"{{"ts":{ms},"level":"{level}","msg":{}"#,
+                Value::from(msg)
+            )
+            .ok();
+            for (key, value) in fields {
+                write!(line, ",{}:{value}", Value::from(key)).ok();
+            }
+
+            line.push('}');
+        }
+    }
+
+    line
+}
+
+macro_rules! log_at {
+    ($level:expr, $($key:ident = $value:expr),+; $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            let fields = [$((stringify!($key), $crate::log::value(&$value))),+];
+            $crate::log::log($level, &fields, format_args!($($arg)+));
+        }
+    };
+
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::log($level, &[], format_args!($($arg)+))
+    };
+}
+
+macro_rules! error {
+    ($($arg:tt)+) => { log_at!($crate::log::Level::Error, $($arg)+) };
+}
+
+macro_rules! warn {
+    ($($arg:tt)+) => { log_at!($crate::log::Level::Warn, $($arg)+) };
+}
+
+macro_rules! info {
+    ($($arg:tt)+) => { log_at!($crate::log::Level::Info, $($arg)+) };
+}
+
+macro_rules! debug {
+    ($($arg:tt)+) => { log_at!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(format: Format) -> String {
+        let fields = [
+            ("conn", value(&3)),
+            ("room", value("lobby")),
+            ("name", value("Gloria T")),
+        ];
+        let fields = fields.iter().map(|(k, v)| (*k, v));
+        render(
+            format,
+            Level::Info,
+            1_700_000_000_000,
+            "Played \"b2\"",
+            fields,
+        )
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            r#"[INFO ] Played "b2" conn=3 room=lobby name="Gloria T""#,
+            line(Format::Text)
+        );
+    }
+
+    #[test]
+    fn json() {
+        let json: Value = serde_json::from_str(&line(Format::Json)).unwrap();
+        assert_eq!("info", json["level"]);
+        assert_eq!("Played \"b2\"", json["msg"]);
+        assert_eq!(3, json["conn"]);
+        assert_eq!("Gloria T", json["name"]);
+        assert_eq!(1_700_000_000_000u64, json["ts"]);
+    }
+
+    #[test]
+    fn scoped_fields() {
+        {
+            let _scope = scope();
+            set("conn", &7);
+            set("conn", &8);
+            CONTEXT.with(|context| assert_eq!(vec![("conn", value(&8))], *context.borrow()));
+        }
+
+        CONTEXT.with(|context| assert!(context.borrow().is_empty()));
+    }
+}
@@ -1,18 +1,311 @@
+#[macro_use]
+mod log;
+mod api;
+mod bot;
+mod clock;
+mod config;
 mod game;
+#[cfg(test)]
+mod harness;
+mod limit;
+mod line;
+mod metrics;
+mod record;
+mod room;
 mod server;
+#[cfg(test)]
+mod sim;
 mod threadpool;
+mod transport;
+mod websocket;
+use clap::Parser;
+use config::{Config, Settings, Tls};
+use core::game::position::Position;
+use core::tls::Identity;
+use log::{Format, Level};
+use record::Recorder;
 use server::Server;
-use std::env;
+use std::error::Error;
+use std::net::{IpAddr, SocketAddr};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
 
-fn main() -> Result<(), &'static str> {
-    let mut args = env::args().skip(1);
+/// How often the config file is checked for changes.
+const RELOAD_INTERVAL: Duration = Duration::from_secs(2);
 
-    let port = args
-        .next()
-        .ok_or("Usage: server <port>")?
-        .parse::<u16>()
-        .map_err(|_| "Invalid port number")?;
+/// Online tic-tac-toe server, each room hosts one game at a time.
+#[derive(Parser, Debug)]
+#[command(name = "server", version = env!("CARGO_PKG_VERSION"))]
+struct Args {
+    /// Port to listen on, required unless the config file sets it or `--unix` is given
+    port: Option<u16>,
 
-    let sv = Server::new([127, 0, 0, 1], port);
-    sv.run(2)
+    /// Unix socket to listen on instead of a port, like `/tmp/tictactoe.sock`, for clients
+    /// on the same machine
+    #[arg(long, value_name = "PATH", conflicts_with = "port")]
+    unix: Option<PathBuf>,
+
+    /// Address to listen on, `::` for every IPv4 and IPv6 one [default: 127.0.0.1]
+    #[arg(long, value_parser = parse_ip)]
+    bind: Option<IpAddr>,
+
+    /// Worker threads completing handshakes with new connections [default: 2]
+    #[arg(short, long)]
+    threads: Option<NonZeroUsize>,
+
+    /// Address to serve Prometheus metrics on, like `127.0.0.1:9100`
+    #[arg(long)]
+    metrics: Option<SocketAddr>,
+
+    /// Address to serve the JSON API on, like `127.0.0.1:8082`
+    #[arg(long)]
+    api: Option<SocketAddr>,
+
+    /// Token to send as `Authorization: Bearer TOKEN` to close rooms or kick players
+    /// through the API, better kept in the config file where `ps` can't see it
+    #[arg(long, requires = "api")]
+    admin_token: Option<String>,
+
+    /// Address to accept WebSocket clients on, like `0.0.0.0:8081`. They share rooms
+    /// with everyone else
+    #[arg(long)]
+    websocket: Option<SocketAddr>,
+
+    /// Address to accept clients typing commands on, like `127.0.0.1:2323`, for `nc` or
+    /// `telnet`. They share rooms with everyone else
+    #[arg(long)]
+    line: Option<SocketAddr>,
+
+    /// Least severe messages logged [default: info]
+    #[arg(long, value_enum)]
+    log_level: Option<Level>,
+
+    /// How messages are written, `json` for one object per line [default: text]
+    #[arg(long, value_enum)]
+    log_format: Option<Format>,
+
+    /// TOML file to read settings from, flags take precedence over it. It's read again
+    /// whenever it changes or on SIGHUP
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Ask clients not to show move hints, e.g. for rated games. Clients work hints out
+    /// themselves, so modified ones can ignore this
+    #[arg(long)]
+    no_hints: bool,
+
+    /// Position every room starts from, like "X1O/1X1/3 O 3 3"
+    #[arg(long)]
+    position: Option<Position>,
+
+    /// Seconds a silent player keeps its seat, 0 to wait forever [default: 30]
+    #[arg(long, value_parser = parse_secs)]
+    idle_timeout: Option<Duration>,
+
+    /// PEM certificate chain to accept TLS connections with, clients then have to use TLS
+    #[arg(long, requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of `--tls-cert`
+    #[arg(long, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Write a self-signed certificate for localhost and its key to this directory, for
+    /// testing, then exit
+    #[arg(long, value_name = "DIR", exclusive = true)]
+    generate_cert: Option<PathBuf>,
+}
+
+impl Args {
+    /// Overrides what `config` says with the flags given.
+    fn apply(&self, config: &mut Config) {
+        config.port = self.port.or(config.port);
+        config.unix = match (&self.unix, self.port) {
+            (Some(path), _) => Some(path.clone()),
+            (None, Some(_)) => None,
+            (None, None) => config.unix.take(),
+        };
+        config.bind = self.bind.unwrap_or(config.bind);
+        config.threads = self.threads.unwrap_or(config.threads);
+        config.metrics = self.metrics.or(config.metrics);
+        config.api = self.api.or(config.api);
+        config.admin_token = self.admin_token.clone().or(config.admin_token.take());
+        config.websocket = self.websocket.or(config.websocket);
+        config.line = self.line.or(config.line);
+        config.log_level = self.log_level.unwrap_or(config.log_level);
+        config.log_format = self.log_format.unwrap_or(config.log_format);
+        config.hints &= !self.no_hints;
+        config.position = self.position.or(config.position);
+        if let Some(timeout) = self.idle_timeout {
+            config.idle_timeout = (!timeout.is_zero()).then_some(timeout);
+        }
+
+        if let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) {
+            config.tls = Some(Tls {
+                cert: cert.clone(),
+                key: key.clone(),
+            });
+        }
+    }
+
+    fn config(&self) -> Result<Config, Box<dyn Error>> {
+        let mut config = match &self.config {
+            Some(path) => Config::load(path)?,
+            None => Config::default(),
+        };
+
+        self.apply(&mut config);
+        Ok(config)
+    }
+}
+
+/// IP address, IPv6 ones may be bracketed like in `[::1]:8080`.
+fn parse_ip(s: &str) -> Result<IpAddr, String> {
+    let ip = s.strip_prefix('[').and_then(|s| s.strip_suffix(']'));
+    ip.unwrap_or(s)
+        .parse()
+        .map_err(|_| format!("`{s}` isn't an IP address"))
+}
+
+fn parse_secs(s: &str) -> Result<Duration, String> {
+    let secs: f64 = s.parse().map_err(|_| format!("`{s}` isn't a number"))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("`{s}` isn't a number of seconds"))
+}
+
+/// Reads the config again every time it changes, applying what can change at runtime.
+fn reload(args: Args, mut config: Config, settings: Arc<RwLock<Settings>>) {
+    let Some(path) = args.config.clone() else {
+        return;
+    };
+
+    let watched = config::watch(path, RELOAD_INTERVAL, move || {
+        let next = match args.config() {
+            Ok(next) => next,
+            Err(e) => return error!("Kept the previous config, {e}"),
+        };
+
+        if next == config {
+            return;
+        }
+
+        let ignored = config.restart_needed(&next);
+        if !ignored.is_empty() {
+            warn!("Restart to apply the new {}", ignored.join(", "));
+        }
+
+        log::set_level(next.log_level);
+        log::set_format(next.log_format);
+        *settings.write().unwrap_or_else(PoisonError::into_inner) = next.settings();
+        info!("Reloaded the config");
+        config = next;
+    });
+
+    if let Err(e) = watched {
+        warn!("Not watching the config for changes: {e}");
+    }
+}
+
+/// Writes a self-signed certificate and its key to `dir`, telling its fingerprint for
+/// clients to pin.
+fn generate_cert(dir: &Path) -> Result<(), Box<dyn Error>> {
+    let identity = Identity::self_signed()?;
+    identity.save(dir)?;
+    println!("Wrote cert.pem and key.pem to {}", dir.display());
+    println!("Fingerprint: {}", identity.fingerprint()?);
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args = Args::parse();
+    if let Some(dir) = &args.generate_cert {
+        return generate_cert(dir);
+    }
+
+    let config = args.config()?;
+    log::set_level(config.log_level);
+    log::set_format(config.log_format);
+
+    let sv = match (&config.unix, config.port) {
+        (Some(path), _) => Server::unix(path),
+        (None, Some(port)) => Server::new(port).bind(config.bind),
+        (None, None) => return Err("No port given, pass one or set it in the config file".into()),
+    };
+
+    let mut sv = sv.settings(config.settings());
+
+    let open = |path: &PathBuf| {
+        Recorder::open(path).map_err(|e| format!("Failed to open {}: {e}", path.display()))
+    };
+
+    if let Some(path) = &config.persistence.games {
+        sv = sv.recorder(open(path)?);
+    }
+
+    if let Some(path) = &config.persistence.audit {
+        sv = sv.audit(open(path)?);
+    }
+
+    if let Some(address) = config.metrics {
+        sv = sv.metrics(address);
+    }
+
+    if let Some(address) = config.api {
+        sv = sv.api(address, config.admin_token.clone());
+    }
+
+    if let Some(address) = config.websocket {
+        sv = sv.websocket(address);
+    }
+
+    if let Some(address) = config.line {
+        sv = sv.line(address);
+    }
+
+    if let Some(tls) = &config.tls {
+        sv = sv.tls(Identity::load(&tls.cert, &tls.key)?);
+    }
+
+    for bot in &config.bots {
+        sv = sv.bot(bot.clone());
+    }
+
+    sv = sv.pool(config.pool());
+    reload(args, config, sv.shared_settings());
+    Ok(sv.run()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn cli() {
+        Args::command().debug_assert();
+
+        let args = Args::try_parse_from(["server", "8080", "--bind", "[::1]", "-t", "4"]).unwrap();
+        assert_eq!(Some(8080), args.port);
+        assert_eq!(Some("::1".parse().unwrap()), args.bind);
+        assert_eq!(NonZeroUsize::new(4), args.threads);
+
+        assert!(Args::try_parse_from(["server", "--bind", "gamebox.lan"]).is_err());
+        assert!(Args::try_parse_from(["server", "--position", "XXX"]).is_err());
+        assert!(Args::try_parse_from(["server", "-t", "0"]).is_err());
+        assert!(Args::try_parse_from(["server", "--tls-cert", "cert.pem"]).is_err());
+        assert!(Args::try_parse_from(["server", "8080", "--generate-cert", "certs"]).is_err());
+        assert!(Args::try_parse_from(["server", "8080", "--unix", "/tmp/t.sock"]).is_err());
+    }
+
+    #[test]
+    fn flags_override_config() {
+        let args = Args::try_parse_from(["server", "--no-hints", "--idle-timeout", "0"]).unwrap();
+        let mut config: Config = "port = 8080\nidle_timeout = 10".parse().unwrap();
+        args.apply(&mut config);
+
+        assert_eq!(Some(8080), config.port);
+        assert!(!config.hints);
+        assert_eq!(None, config.idle_timeout);
+    }
 }
@@ -0,0 +1,363 @@
+//! Counters and gauges in the Prometheus text format, served over HTTP at `/metrics`.
+
+use crate::room::Rooms;
+use crate::server::lock;
+use crate::threadpool::Stats;
+use core::error::ErrorCode;
+use core::response::Response;
+use serde::de::DeserializeOwned;
+use std::fmt::Write;
+use std::net::TcpListener;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use std::{io, thread};
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// Upper bounds of the latency buckets, in microseconds.
+const BUCKETS: [u64; 8] = [5, 10, 25, 50, 100, 250, 1000, 10_000];
+
+/// How games ended, in the order of [`Metrics::finished`].
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    Win,
+    Draw,
+    Timeout,
+}
+
+pub struct Histogram {
+    counts: [AtomicU64; BUCKETS.len()],
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            counts: [const { AtomicU64::new(0) }; BUCKETS.len()],
+            count: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, us: u64) {
+        if let Some(k) = BUCKETS.iter().position(|&le| us <= le) {
+            self.counts[k].fetch_add(1, Ordering::Relaxed);
+        }
+
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        writeln!(out, "# HELP {name} {help}").ok();
+        writeln!(out, "# TYPE {name} histogram").ok();
+
+        let mut total = 0;
+        for (le, count) in BUCKETS.iter().zip(&self.counts) {
+            total += count.load(Ordering::Relaxed);
+            let le = *le as f64 / 1e6;
+            writeln!(out, "{name}_bucket{{le=\"{le}\"}} {total}").ok();
+        }
+
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = self.sum_us.load(Ordering::Relaxed) as f64 / 1e6;
+        writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}").ok();
+        writeln!(out, "{name}_sum {sum}").ok();
+        writeln!(out, "{name}_count {count}").ok();
+    }
+}
+
+pub struct Metrics {
+    connections: AtomicU64,
+    moves: AtomicU64,
+    finished: [AtomicU64; 3],
+    invalid: Mutex<Vec<(ErrorCode, u64)>>,
+    /// Errors the server sent on its own rather than answering a request.
+    errors: Mutex<Vec<(ErrorCode, u64)>>,
+    encode: Histogram,
+    decode: Histogram,
+}
+
+/// Counts a connection as active until dropped.
+pub struct Connection(());
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        METRICS.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Self {
+            connections: AtomicU64::new(0),
+            moves: AtomicU64::new(0),
+            finished: [const { AtomicU64::new(0) }; 3],
+            invalid: Mutex::new(Vec::new()),
+            errors: Mutex::new(Vec::new()),
+            encode: Histogram::new(),
+            decode: Histogram::new(),
+        }
+    }
+
+    pub fn connection(&self) -> Connection {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        Connection(())
+    }
+
+    pub fn moved(&self) {
+        self.moves.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn finished(&self, outcome: Outcome) {
+        self.finished[outcome as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a request refused with `code`, once whoever gets told about it.
+    pub fn refused(&self, code: ErrorCode) {
+        count(&self.invalid, code);
+    }
+
+    /// Counts an error the server sent without being asked anything, like turning a
+    /// connection away or closing a room.
+    pub fn errored(&self, code: ErrorCode) {
+        count(&self.errors, code);
+    }
+
+    fn render(&self, out: &mut String) {
+        gauge(
+            out,
+            "tictactoe_connections",
+            "Open connections, players and spectators",
+            self.connections.load(Ordering::Relaxed),
+        );
+
+        counter(out, "tictactoe_moves_total", "Moves played");
+        let moves = self.moves.load(Ordering::Relaxed);
+        writeln!(out, "tictactoe_moves_total {moves}").ok();
+
+        counter(
+            out,
+            "tictactoe_games_finished_total",
+            "Games finished by outcome",
+        );
+        for (outcome, n) in ["win", "draw", "timeout"].iter().zip(&self.finished) {
+            let n = n.load(Ordering::Relaxed);
+            writeln!(
+                out,
+                "tictactoe_games_finished_total{{outcome=\"{outcome}\"}} {n}"
+            )
+            .ok();
+        }
+
+        counter(
+            out,
+            "tictactoe_invalid_requests_total",
+            "Requests refused by reason",
+        );
+        for (code, n) in lock(&self.invalid).iter() {
+            writeln!(
+                out,
+                "tictactoe_invalid_requests_total{{reason=\"{code:?}\"}} {n}"
+            )
+            .ok();
+        }
+
+        counter(
+            out,
+            "tictactoe_errors_total",
+            "Errors sent unprompted by reason, like turning connections away",
+        );
+        for (code, n) in lock(&self.errors).iter() {
+            writeln!(out, "tictactoe_errors_total{{reason=\"{code:?}\"}} {n}").ok();
+        }
+
+        let help = "Time spent serializing responses";
+        self.encode.render(out, "tictactoe_encode_seconds", help);
+        let help = "Time spent parsing requests";
+        self.decode.render(out, "tictactoe_decode_seconds", help);
+    }
+}
+
+fn count(counts: &Mutex<Vec<(ErrorCode, u64)>>, code: ErrorCode) {
+    let mut counts = lock(counts);
+    match counts.iter_mut().find(|(c, _)| *c == code) {
+        Some((_, n)) => *n += 1,
+        None => counts.push((code, 1)),
+    }
+}
+
+fn counter(out: &mut String, name: &str, help: &str) {
+    writeln!(out, "# HELP {name} {help}").ok();
+    writeln!(out, "# TYPE {name} counter").ok();
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: impl std::fmt::Display) {
+    writeln!(out, "# HELP {name} {help}").ok();
+    writeln!(out, "# TYPE {name} gauge").ok();
+    writeln!(out, "{name} {value}").ok();
+}
+
+/// Serializes `res`, timing it.
+pub fn encode(res: &Response) -> serde_json::Result<String> {
+    let start = Instant::now();
+    let json = serde_json::to_string(res);
+    METRICS.encode.observe(start.elapsed().as_micros() as u64);
+    json
+}
+
+/// Parses a request, timing it.
+pub fn decode<T: DeserializeOwned>(data: &[u8]) -> serde_json::Result<T> {
+    let start = Instant::now();
+    let req = serde_json::from_slice(data);
+    METRICS.decode.observe(start.elapsed().as_micros() as u64);
+    req
+}
+
+/// Every metric, gauges being read from `rooms` and `pool`.
+pub fn render(rooms: &Rooms, pool: &Stats) -> String {
+    let mut out = String::new();
+    METRICS.render(&mut out);
+
+    let games = rooms.games();
+    let full = games.iter().filter(|game| lock(game).is_full()).count();
+    gauge(&mut out, "tictactoe_rooms", "Open rooms", games.len());
+    gauge(
+        &mut out,
+        "tictactoe_games",
+        "Rooms with both seats taken",
+        full,
+    );
+
+    let help = "Connections waiting for a worker";
+    gauge(&mut out, "tictactoe_pool_queued", help, pool.queued());
+    let help = "Workers shaking hands with a connection";
+    gauge(&mut out, "tictactoe_pool_busy", help, pool.busy());
+
+    let workers = pool.workers();
+    gauge(
+        &mut out,
+        "tictactoe_pool_workers",
+        "Live workers",
+        workers.len(),
+    );
+    counter(
+        &mut out,
+        "tictactoe_pool_panics_total",
+        "Handshakes that panicked",
+    );
+    writeln!(out, "tictactoe_pool_panics_total {}", pool.panics()).ok();
+    counter(
+        &mut out,
+        "tictactoe_pool_rejected_total",
+        "Connections turned away as the server was busy",
+    );
+    writeln!(out, "tictactoe_pool_rejected_total {}", pool.rejected()).ok();
+
+    counter(
+        &mut out,
+        "tictactoe_worker_jobs_total",
+        "Connections served by each worker",
+    );
+    for (id, worker) in &workers {
+        writeln!(
+            out,
+            "tictactoe_worker_jobs_total{{worker=\"{id}\"}} {}",
+            worker.jobs
+        )
+        .ok();
+    }
+
+    out
+}
+
+/// Answers `GET /metrics` on `listener` until the process exits.
+pub fn serve(listener: TcpListener, rooms: Arc<Rooms>, pool: Arc<Stats>) -> io::Result<()> {
+    let server = tiny_http::Server::from_listener(listener, None).map_err(io::Error::other)?;
+
+    thread::spawn(move || {
+        for req in server.incoming_requests() {
+            let res = if req.url() == "/metrics" {
+                let header = "Content-Type: text/plain; version=0.0.4".parse::<tiny_http::Header>();
+                tiny_http::Response::from_string(render(&rooms, &pool))
+                    .with_header(header.expect("Valid header"))
+            } else {
+                tiny_http::Response::from_string("Not found").with_status_code(404)
+            };
+
+            if let Err(e) = req.respond(res) {
+                debug!(error = e.to_string(); "Failed to answer a metrics scrape");
+            }
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write as _};
+    use std::net::TcpStream;
+
+    fn get(listener: &TcpListener, path: &str) -> String {
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"
+        )
+        .unwrap();
+
+        let mut res = String::new();
+        stream.read_to_string(&mut res).unwrap();
+        res
+    }
+
+    #[test]
+    fn histogram() {
+        let histogram = Histogram::new();
+        for us in [3, 40, 40, 20_000] {
+            histogram.observe(us);
+        }
+
+        let mut out = String::new();
+        histogram.render(&mut out, "latency_seconds", "Latency");
+        assert!(out.contains("# TYPE latency_seconds histogram\n"));
+        assert!(out.contains("latency_seconds_bucket{le=\"0.000005\"} 1\n"));
+        assert!(out.contains("latency_seconds_bucket{le=\"0.00005\"} 3\n"));
+        assert!(out.contains("latency_seconds_bucket{le=\"0.01\"} 3\n"));
+        assert!(out.contains("latency_seconds_bucket{le=\"+Inf\"} 4\n"));
+        assert!(out.contains("latency_seconds_sum 0.020083\n"));
+        assert!(out.contains("latency_seconds_count 4\n"));
+    }
+
+    #[test]
+    fn refused_requests() {
+        METRICS.refused(ErrorCode::GameOver);
+        METRICS.errored(ErrorCode::ServerBusy);
+
+        let mut out = String::new();
+        METRICS.render(&mut out);
+        assert!(out.contains("tictactoe_invalid_requests_total{reason=\"GameOver\"} "));
+        assert!(out.contains("tictactoe_errors_total{reason=\"ServerBusy\"} "));
+        assert!(!out.contains("tictactoe_invalid_requests_total{reason=\"ServerBusy\"} "));
+    }
+
+    #[test]
+    fn scrape() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let rooms = Arc::new(Rooms::new(Arc::default(), None));
+        serve(listener.try_clone().unwrap(), rooms, Arc::default()).unwrap();
+
+        let res = get(&listener, "/metrics");
+        assert!(res.starts_with("HTTP/1.1 200"), "{res}");
+        assert!(res.contains("text/plain; version=0.0.4"));
+        assert!(res.contains("\ntictactoe_rooms 0\n"));
+        assert!(res.contains("\ntictactoe_pool_busy 0\n"));
+        assert!(res.contains("# TYPE tictactoe_moves_total counter\n"));
+
+        assert!(get(&listener, "/").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,58 @@
+use crate::server::lock;
+use core::game::{piece::Piece, state::GameState};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Appends records to a JSON lines file, one per line. Finished games are kept as
+/// [`GameRecord`](core::record::GameRecord)s and accepted moves as [`MoveRecord`]s.
+pub struct Recorder {
+    file: Mutex<File>,
+    path: PathBuf,
+}
+
+impl Recorder {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Every record kept so far, skipping lines that aren't a `T`.
+    pub fn read<T: DeserializeOwned>(&self) -> io::Result<Vec<T>> {
+        // Holding the lock keeps half-written lines out.
+        let _file = lock(&self.file);
+        let text = fs::read_to_string(&self.path)?;
+        Ok(text
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect())
+    }
+
+    pub fn record<T: Serialize>(&self, record: &T) -> io::Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        lock(&self.file).write_all(line.as_bytes())
+    }
+}
+
+/// A move the server accepted, as the audit log keeps it.
+#[derive(Serialize, Debug)]
+pub struct MoveRecord<'a> {
+    /// Milliseconds since the Unix epoch.
+    pub time: u64,
+    pub conn: u64,
+    pub ip: IpAddr,
+    pub room: &'a str,
+    pub name: &'a str,
+    pub piece: Piece,
+    /// Cell in algebraic notation, like "b2".
+    pub cell: String,
+    pub state: &'a GameState,
+}
@@ -0,0 +1,147 @@
+use crate::config::Settings;
+use crate::game::Game;
+use crate::record::Recorder;
+use crate::server::lock;
+use crate::transport::Sink;
+use core::error::ErrorCode;
+use core::game::piece::Piece;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+/// Room joined by clients that don't name one.
+pub const DEFAULT_ROOM: &str = "lobby";
+
+/// Longest room name accepted.
+pub const MAX_ROOM_LEN: usize = 32;
+
+/// Games by room name. A room opens with its first player or spectator and closes
+/// once everyone left it.
+pub struct Rooms {
+    rooms: Mutex<HashMap<String, Arc<Mutex<Game>>>>,
+    settings: Arc<RwLock<Settings>>,
+    recorder: Option<Arc<Recorder>>,
+}
+
+impl Rooms {
+    pub fn new(settings: Arc<RwLock<Settings>>, recorder: Option<Arc<Recorder>>) -> Self {
+        Self {
+            rooms: Mutex::new(HashMap::new()),
+            settings,
+            recorder,
+        }
+    }
+
+    /// The settings in force right now.
+    pub fn settings(&self) -> Settings {
+        self.settings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    fn key(room: &str) -> &str {
+        match room.trim() {
+            "" => DEFAULT_ROOM,
+            room => room,
+        }
+    }
+
+    /// The game of `room`, opening it if there's space for one more. Callers keep the
+    /// rooms locked until they're in the game, so `leave` can't close it under them.
+    fn open(
+        &self,
+        rooms: &mut HashMap<String, Arc<Mutex<Game>>>,
+        room: &str,
+    ) -> Result<Arc<Mutex<Game>>, ErrorCode> {
+        let key = Self::key(room);
+        if let Some(game) = rooms.get(key) {
+            return Ok(Arc::clone(game));
+        }
+
+        let settings = self.settings();
+        if rooms.len() >= settings.max_rooms {
+            return Err(ErrorCode::TooManyRooms);
+        }
+
+        let mut game = Game::new();
+        game.room = key.to_string();
+        game.hints = settings.hints;
+        game.recorder = self.recorder.clone();
+        game.set_time_control(settings.time_control);
+        if let Some(pos) = settings.position {
+            game.set_position(pos);
+        }
+
+        debug!("Opened room `{key}` at {}", game.position());
+        let game = Arc::new(Mutex::new(game));
+        rooms.insert(key.to_string(), Arc::clone(&game));
+        Ok(game)
+    }
+
+    /// Seats whoever `sink` sends to as `name` in `room`.
+    pub fn join(
+        &self,
+        room: &str,
+        name: &str,
+        sink: Box<dyn Sink>,
+    ) -> Result<(Arc<Mutex<Game>>, Piece), ErrorCode> {
+        let mut rooms = lock(&self.rooms);
+        let game = self.open(&mut rooms, room)?;
+        let piece = lock(&game).assign_piece(sink, name);
+        let piece = piece.ok_or(ErrorCode::RoomFull)?;
+        Ok((game, piece))
+    }
+
+    /// Adds whoever `sink` sends to to the spectators of `room`.
+    pub fn spectate(
+        &self,
+        room: &str,
+        sink: Box<dyn Sink>,
+    ) -> Result<(Arc<Mutex<Game>>, u64), ErrorCode> {
+        let mut rooms = lock(&self.rooms);
+        let game = self.open(&mut rooms, room)?;
+        let id = lock(&game).add_spectator(sink);
+        Ok((game, id))
+    }
+
+    /// Closes `room` if nobody plays or watches in it anymore.
+    pub fn leave(&self, room: &str) {
+        let mut rooms = lock(&self.rooms);
+        let key = Self::key(room);
+        if rooms.get(key).is_some_and(|game| lock(game).is_empty()) {
+            rooms.remove(key);
+            debug!("Closed room `{key}`");
+        }
+    }
+
+    /// The game of `room`, if it's open.
+    pub fn get(&self, room: &str) -> Option<Arc<Mutex<Game>>> {
+        lock(&self.rooms).get(Self::key(room)).cloned()
+    }
+
+    /// Closes `room` whoever is still in it, the next to join it opens a new one.
+    pub fn close(&self, room: &str) -> bool {
+        let key = Self::key(room);
+        let Some(game) = lock(&self.rooms).remove(key) else {
+            return false;
+        };
+
+        lock(&game).close();
+        info!("Closed room `{key}`");
+        true
+    }
+
+    /// Where finished games are kept, if anywhere.
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_deref()
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.rooms).len()
+    }
+
+    /// Every open game.
+    pub fn games(&self) -> Vec<Arc<Mutex<Game>>> {
+        lock(&self.rooms).values().cloned().collect()
+    }
+}
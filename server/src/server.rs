@@ -1,89 +1,1892 @@
+use crate::api;
+use crate::bot::{self, BotConfig};
+use crate::config::Settings;
 use crate::game::Game;
-use crate::threadpool::ThreadPool;
-use core::game::piece::Piece;
-use core::{read_str, write_str};
-use core::{request::Request, response::Response};
-use std::io::{self, Write};
-use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
+use crate::limit::{Clients, Peer, TokenBucket};
+use crate::log;
+use crate::metrics::{self, METRICS};
+use crate::record::{MoveRecord, Recorder};
+use crate::room::{Rooms, MAX_ROOM_LEN};
+use crate::threadpool::{self, ThreadPool};
+use crate::transport::{self, Listener, MemoryClient, Outbox, Protocol, Sink, Transport};
+use core::error::ErrorCode;
+use core::game::{piece::Piece, state::GameState};
+use core::notation::format_cell;
+use core::request::Request;
+use core::response::Response;
+use core::stream::{Stream, UNIX_PREFIX};
+use core::tls::{Identity, TlsStream};
+use rustls::ServerConfig;
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, Weak};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-pub struct Server(SocketAddr);
+/// How often clocks are checked for players out of time.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a client has to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a write may block before the client counts as gone.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What every connection needs, whichever listener accepted it.
+#[derive(Clone)]
+pub(crate) struct Context {
+    rooms: Arc<Rooms>,
+    clients: Arc<Clients>,
+    audit: Option<Arc<Recorder>>,
+    tls: Option<Arc<ServerConfig>>,
+}
+
+impl Context {
+    /// Fresh rooms with their clocks ticking, no limits on clients, no audit trail and
+    /// no TLS.
+    #[cfg(test)]
+    pub(crate) fn new(settings: Settings) -> Self {
+        let rooms = Arc::new(Rooms::new(Arc::new(RwLock::new(settings)), None));
+        Server::tick(Arc::downgrade(&rooms));
+        Self {
+            rooms,
+            clients: Arc::default(),
+            audit: None,
+            tls: None,
+        }
+    }
+
+    /// Whether clients speaking `protocol` need a handshake before they can understand
+    /// anything.
+    fn handshake(&self, protocol: Protocol) -> bool {
+        self.tls.is_some() || protocol.handshake()
+    }
+
+    /// Connects a client living in the server's process, like a bot, served like any
+    /// other on a thread of its own but without a socket in between.
+    pub(crate) fn connect(&self) -> io::Result<MemoryClient> {
+        let peer = self.clients.local();
+        let (server, client) = transport::memory();
+        let (rooms, audit) = (Arc::clone(&self.rooms), self.audit.clone());
+        thread::spawn(move || {
+            if let Err(e) = Server::handle_client(Box::new(server), rooms, peer, audit) {
+                debug!(error = e.to_string(); "In-memory client dropped");
+            }
+        });
+
+        Ok(client)
+    }
+}
+
+/// Who holds a place in a room.
+pub(crate) enum Occupant {
+    Player(Piece),
+    Spectator(u64),
+}
+
+/// A place in a room, which a client's first request got it.
+pub(crate) struct Seat {
+    pub room: String,
+    pub game: Arc<Mutex<Game>>,
+    pub occupant: Occupant,
+}
+
+impl Seat {
+    /// Gives the place up, disconnecting a player first if `disconnect`, when its
+    /// handler didn't already, then closes the room if nobody's left in it.
+    pub(crate) fn leave(&self, rooms: &Rooms, disconnect: bool) {
+        let mut game = lock(&self.game);
+        match self.occupant {
+            Occupant::Player(piece) if disconnect => game.disconnect(piece),
+            Occupant::Player(_) => {}
+            Occupant::Spectator(id) => game.remove_spectator(id),
+        }
+
+        drop(game);
+        rooms.leave(&self.room);
+    }
+}
+
+/// Gives a seat up once its connection is done with it, even if its handler panicked,
+/// so that a seat never stays taken by nobody.
+struct Leave<'a> {
+    rooms: &'a Rooms,
+    seat: &'a Seat,
+}
+
+impl Drop for Leave<'_> {
+    fn drop(&mut self) {
+        let panicking = thread::panicking();
+        if let (true, Occupant::Player(piece)) = (panicking, &self.seat.occupant) {
+            error!("Player `{piece}` panicked");
+        }
+
+        self.seat.leave(self.rooms, panicking);
+    }
+}
+
+/// Connection ids, in the logs and the audit trail.
+static NEXT_CONN: AtomicU64 = AtomicU64::new(1);
+
+/// What the server knows about one connection.
+pub(crate) struct Conn {
+    id: u64,
+    ip: IpAddr,
+    limiter: Option<TokenBucket>,
+    peer: Peer,
+    audit: Option<Arc<Recorder>>,
+}
+
+impl Conn {
+    pub(crate) fn new(ip: IpAddr, peer: Peer, audit: Option<Arc<Recorder>>) -> Self {
+        Self {
+            id: NEXT_CONN.fetch_add(1, Ordering::Relaxed),
+            ip,
+            limiter: None,
+            peer,
+            audit,
+        }
+    }
+
+    /// Whether the connection sent more than its share of requests by `now`.
+    fn limited(&mut self, now: Instant) -> bool {
+        let limited = self
+            .limiter
+            .as_mut()
+            .is_some_and(|bucket| !bucket.take(now));
+
+        limited || !self.peer.take(now)
+    }
+
+    /// Counts a protocol violation at `now`, `true` if it got the address banned.
+    fn violated(&self, now: Instant) -> bool {
+        let banned = self.peer.strike(now);
+        if banned {
+            warn!("Banned after repeated protocol violations");
+        }
+
+        banned
+    }
+
+    /// Reads a frame, counting oversized ones as violations.
+    fn read(&self, stream: &mut dyn Transport) -> io::Result<Vec<u8>> {
+        let data = stream.recv();
+        if matches!(&data, Err(e) if e.kind() == io::ErrorKind::InvalidData) {
+            self.violated(Instant::now());
+        }
+
+        data
+    }
+
+    /// Keeps the move `piece` just made in the audit log.
+    fn audit(&self, game: &Game, piece: Piece, idx: (usize, usize), state: &GameState) {
+        let Some(audit) = &self.audit else {
+            return;
+        };
+
+        let record = MoveRecord {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            conn: self.id,
+            ip: self.ip,
+            room: &game.room,
+            name: game.name(piece).unwrap_or_default(),
+            piece,
+            cell: format_cell(idx),
+            state,
+        };
+
+        if let Err(e) = audit.record(&record) {
+            error!(error = e.to_string(); "Failed to audit a move");
+        }
+    }
+}
+
+pub struct Server {
+    address: SocketAddr,
+    /// Unix socket listened on instead of `address`.
+    unix: Option<PathBuf>,
+    settings: Arc<RwLock<Settings>>,
+    recorder: Option<Arc<Recorder>>,
+    audit: Option<Arc<Recorder>>,
+    metrics: Option<SocketAddr>,
+    api: Option<(SocketAddr, Option<String>)>,
+    /// Listeners besides the main one, for clients speaking other protocols.
+    listeners: Vec<(Protocol, SocketAddr)>,
+    pool: threadpool::Options,
+    bots: Vec<BotConfig>,
+    tls: Option<Identity>,
+}
 
 impl Server {
-    pub fn new(address: [u8; 4], port: u16) -> Self {
-        let [a, b, c, d] = address;
-        let address = SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(a, b, c, d), port));
-        Self(address)
+    pub fn new(port: u16) -> Self {
+        Self {
+            address: SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port),
+            unix: None,
+            settings: Arc::default(),
+            recorder: None,
+            audit: None,
+            metrics: None,
+            api: None,
+            listeners: Vec::new(),
+            pool: threadpool::Options::default(),
+            bots: Vec::new(),
+            tls: None,
+        }
+    }
+
+    /// Listens on the Unix socket at `path` instead of a port, for clients on the same
+    /// machine. They're never asked for TLS.
+    pub fn unix(path: impl Into<PathBuf>) -> Self {
+        Self {
+            unix: Some(path.into()),
+            ..Self::new(0)
+        }
+    }
+
+    /// Address to listen on instead of the loopback one, `::` listens on every IPv4 and
+    /// IPv6 address.
+    pub fn bind(mut self, ip: IpAddr) -> Self {
+        self.address.set_ip(ip);
+        self
+    }
+
+    /// Replaces every setting that can change at runtime.
+    pub fn settings(self, settings: Settings) -> Self {
+        *self
+            .settings
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = settings;
+        self
+    }
+
+    /// Handle to the running server's settings, whatever is written there applies to the
+    /// connections and rooms opened afterwards.
+    pub fn shared_settings(&self) -> Arc<RwLock<Settings>> {
+        Arc::clone(&self.settings)
+    }
+
+    /// Keeps every finished game in `recorder`.
+    pub fn recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Keeps every move the server accepts in `audit`, to settle disputes.
+    pub fn audit(mut self, audit: Recorder) -> Self {
+        self.audit = Some(Arc::new(audit));
+        self
+    }
+
+    /// Serves Prometheus metrics at `http://{address}/metrics`.
+    pub fn metrics(mut self, address: SocketAddr) -> Self {
+        self.metrics = Some(address);
+        self
+    }
+
+    /// Serves the JSON API at `http://{address}`, letting whoever sends `token` close
+    /// rooms and kick players.
+    pub fn api(mut self, address: SocketAddr, token: Option<String>) -> Self {
+        self.api = Some((address, token));
+        self
+    }
+
+    /// Also accepts WebSocket clients at `address`, into the same rooms.
+    pub fn websocket(mut self, address: SocketAddr) -> Self {
+        self.listeners.push((Protocol::WebSocket, address));
+        self
+    }
+
+    /// Also accepts clients typing commands at `address`, into the same rooms.
+    pub fn line(mut self, address: SocketAddr) -> Self {
+        self.listeners.push((Protocol::Line, address));
+        self
+    }
+
+    /// How many workers complete handshakes with new connections, and what happens once
+    /// they're all busy.
+    pub fn pool(mut self, options: threadpool::Options) -> Self {
+        self.pool = options;
+        self
+    }
+
+    /// Has a bot sit in a room once the server is up.
+    pub fn bot(mut self, config: BotConfig) -> Self {
+        self.bots.push(config);
+        self
+    }
+
+    /// Only accepts TLS connections, proving who the server is with `identity`.
+    pub fn tls(mut self, identity: Identity) -> Self {
+        self.tls = Some(identity);
+        self
+    }
+
+    /// Listens on `address`. The unspecified IPv6 address `::` also accepts IPv4 clients,
+    /// whatever the platform's default is.
+    fn listen(address: SocketAddr) -> io::Result<TcpListener> {
+        let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+        if address.is_ipv6() && address.ip().is_unspecified() {
+            socket.set_only_v6(false)?;
+        }
+
+        #[cfg(unix)]
+        socket.set_reuse_address(true)?;
+        socket.bind(&address.into())?;
+        socket.listen(128)?;
+        Ok(socket.into())
     }
 
-    fn send_init<W: Write>(stream: &mut W, res: Response) -> io::Result<()> {
-        let json = serde_json::to_string(&res)?;
-        write_str(stream, &json)
+    /// Counts a connection from `stream`'s address, or turns it away if the address is
+    /// banned or already has too many connections open.
+    /// Connections on Unix sockets all look like `127.0.0.1`, so they're let in without
+    /// counting, like bots in memory.
+    fn admit(stream: &Stream, context: &Context, protocol: Protocol) -> Option<Peer> {
+        #[cfg(unix)]
+        if let Stream::Unix(_) = stream {
+            return Some(context.clients.local());
+        }
+
+        let ip = stream.peer_addr().ok()?.ip();
+        match context
+            .clients
+            .connect(ip, &context.rooms.settings(), Instant::now())
+        {
+            Ok(peer) => Some(peer),
+            Err(code) => {
+                info!(ip = ip, code = code; "Turned a connection away");
+                Self::turn_away(stream, code, context, protocol);
+                None
+            }
+        }
     }
 
-    fn register_user(stream: TcpStream, game: &Arc<Mutex<Game>>) -> Option<Piece> {
-        game.lock()
-            .map(|mut game| game.assign_piece(stream))
-            .ok()
-            .flatten()
+    /// Tells a client why it's turned away, unless it expects a handshake first, which
+    /// isn't worth it for a connection about to be closed.
+    fn turn_away(stream: &Stream, code: ErrorCode, context: &Context, protocol: Protocol) {
+        METRICS.errored(code);
+        if context.handshake(protocol) {
+            return;
+        }
+
+        let transport = stream
+            .try_clone()
+            .and_then(|stream| protocol.accept(stream));
+        if let Ok(mut transport) = transport {
+            transport.send(&Response::Invalid(code)).ok();
+        }
     }
 
-    fn handle_client(mut stream: TcpStream, game: Arc<Mutex<Game>>) -> io::Result<()> {
-        let Some(piece) = Self::register_user(stream.try_clone()?, &game) else {
+    /// Completes the TLS handshake if the server uses TLS, then the protocol's own.
+    fn accept(
+        stream: Stream,
+        protocol: Protocol,
+        tls: Option<Arc<ServerConfig>>,
+    ) -> io::Result<Box<dyn Transport>> {
+        if tls.is_some() || protocol.handshake() {
+            stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+        }
+
+        stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+
+        let stream = match (tls, stream) {
+            (Some(config), Stream::Tcp(tcp)) => TlsStream::accept(tcp, config)?.into(),
+            (_, stream) => stream,
+        };
+
+        protocol.accept(stream)
+    }
+
+    /// Completes the handshakes with the client on the other end of `stream`, then serves
+    /// it until it leaves on a thread of its own. Only handshakes, which time out, are
+    /// left to the pool, so that a game never holds a worker for as long as it lasts.
+    fn handle(stream: Stream, protocol: Protocol, peer: Peer, context: Context) {
+        let transport = match Self::accept(stream, protocol, context.tls) {
+            Ok(transport) => transport,
+            Err(e) => {
+                error!(error = e.to_string(); "Handshake failed");
+                return;
+            }
+        };
+
+        let (rooms, audit) = (context.rooms, context.audit);
+        let spawned = thread::Builder::new().spawn(move || {
+            if let Err(e) = Self::handle_client(transport, rooms, peer, audit) {
+                error!(error = e.to_string(); "Connection failed");
+            }
+        });
+
+        if let Err(e) = spawned {
+            error!(error = e.to_string(); "No thread left for a connection");
+        }
+    }
+
+    /// Hands every client of `listener`, speaking `protocol`, to `pool` for its handshakes.
+    pub(crate) fn accept_loop(
+        listener: Listener,
+        protocol: Protocol,
+        context: Context,
+        pool: &ThreadPool,
+    ) {
+        for stream in listener.incoming() {
+            let Ok(busy) = stream.try_clone() else {
+                continue;
+            };
+
+            let Some(peer) = Self::admit(&busy, &context, protocol) else {
+                continue;
+            };
+
+            let shared = context.clone();
+            let job = move || Self::handle(stream, protocol, peer, shared);
+            if pool.execute(job).is_err() {
+                warn!(ip = busy.peer_addr().ok(); "Every worker is busy, turned a connection away");
+                Self::turn_away(&busy, ErrorCode::ServerBusy, &context, protocol);
+            }
+        }
+    }
+
+    /// Seats or seats as a spectator whoever is on the other end of `stream`, depending
+    /// on its first request, then serves it until it leaves.
+    fn handle_client(
+        mut stream: Box<dyn Transport>,
+        rooms: Arc<Rooms>,
+        peer: Peer,
+        audit: Option<Arc<Recorder>>,
+    ) -> io::Result<()> {
+        let _scope = log::scope();
+        let _connection = METRICS.connection();
+        let mut conn = Conn::new(stream.peer_addr()?.ip(), peer, audit);
+
+        log::set("conn", &conn.id);
+        log::set("ip", &conn.ip);
+        let connected = Instant::now();
+        debug!("Connected");
+
+        let settings = rooms.settings();
+        stream.set_read_timeout(settings.idle_timeout)?;
+        conn.limiter = settings
+            .rate_limit
+            .map(|limit| TokenBucket::new(limit, Instant::now()));
+
+        let req = metrics::decode(&conn.read(&mut *stream)?);
+        let outbox = Outbox::spawn(stream.sink()?, stream.sink()?)?;
+        let stalled = outbox.stalled();
+        let sink = Box::new(outbox);
+        let Some(seat) = Self::seat(&rooms, req, &mut *stream, sink, &conn, Instant::now())? else {
             return Ok(());
         };
 
-        let ip = stream.peer_addr()?.ip();
-        println!("Player `{piece}` ({ip}) connected");
+        let leave = Leave {
+            rooms: &rooms,
+            seat: &seat,
+        };
+
+        log::set("room", &lock(&seat.game).room);
+        match seat.occupant {
+            Occupant::Player(piece) => {
+                log::set("piece", &piece);
+                info!(open_rooms = rooms.len(); "Player `{piece}` joined");
+                Self::handle_player(&mut *stream, piece, &seat.game, &mut conn);
+            }
+
+            Occupant::Spectator(id) => {
+                log::set("spectator", &id);
+                info!("Spectator {id} started watching");
+
+                let result = Self::watch(&mut *stream, id, &seat.game, &mut conn);
+                info!("Spectator {id} left");
+                if let Err(e) = result {
+                    debug!(error = e.to_string(); "Spectator connection ended");
+                }
+            }
+        }
+
+        drop(leave);
+        if stalled.load(Ordering::Relaxed) {
+            warn!("Hung up on a client that stopped reading");
+            conn.violated(Instant::now());
+        }
+
+        debug!(secs = connected.elapsed().as_secs_f64(); "Connection closed");
+        Ok(())
+    }
+
+    /// Seats whoever sent `req`, its first request, as a player or a spectator of the
+    /// room it names, to be told what happens there through `sink`. Anything else is
+    /// answered on `stream`, as of `now`, and seats nobody.
+    pub(crate) fn seat(
+        rooms: &Rooms,
+        req: serde_json::Result<Request>,
+        stream: &mut dyn Sink,
+        sink: Box<dyn Sink>,
+        conn: &Conn,
+        now: Instant,
+    ) -> io::Result<Option<Seat>> {
+        let refused = match req {
+            Ok(Request::Join { room, .. } | Request::Spectate { room })
+                if room.len() > MAX_ROOM_LEN =>
+            {
+                warn!(len = room.len(); "Room name too long");
+                return Self::refuse(stream, conn, now).map(|()| None);
+            }
+
+            Ok(Request::Join { room, name }) => match rooms.join(&room, &name, sink) {
+                Ok((game, piece)) => {
+                    let occupant = Occupant::Player(piece);
+                    return Ok(Some(Seat {
+                        room,
+                        game,
+                        occupant,
+                    }));
+                }
+
+                Err(code) => {
+                    info!(room = room, code = code; "Rejected player");
+                    code
+                }
+            },
+
+            Ok(Request::Spectate { room }) => match rooms.spectate(&room, sink) {
+                Ok((game, id)) => {
+                    let occupant = Occupant::Spectator(id);
+                    return Ok(Some(Seat {
+                        room,
+                        game,
+                        occupant,
+                    }));
+                }
+
+                Err(code) => {
+                    info!(room = room, code = code; "Rejected spectator");
+                    code
+                }
+            },
+
+            Ok(req) => {
+                warn!(request = format!("{req:?}"); "Request before joining a room");
+                ErrorCode::NotSeated
+            }
+
+            Err(e) => {
+                warn!(error = e.to_string(); "Malformed first request");
+                return Self::refuse(stream, conn, now).map(|()| None);
+            }
+        };
+
+        METRICS.refused(refused);
+        stream.send(&Response::Invalid(refused)).map(|()| None)
+    }
+
+    /// Answers a malformed first request, telling the client if it got banned for it as
+    /// of `now`.
+    fn refuse(stream: &mut dyn Sink, conn: &Conn, now: Instant) -> io::Result<()> {
+        METRICS.refused(ErrorCode::Malformed);
+        stream.send(&Response::Invalid(ErrorCode::Malformed))?;
+        if conn.violated(now) {
+            METRICS.errored(ErrorCode::Banned);
+            stream.send(&Response::Invalid(ErrorCode::Banned))?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_player(
+        stream: &mut dyn Transport,
+        piece: Piece,
+        game: &Mutex<Game>,
+        conn: &mut Conn,
+    ) {
+        let Err(e) = Self::serve(stream, piece, game, conn) else {
+            info!("Player `{piece}` disconnected");
+            return;
+        };
 
-        let board = game.lock().unwrap().board;
-        Self::send_init(&mut stream, Response::Init { piece, board })?;
+        lock(game).disconnect(piece);
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                info!("Player `{piece}` timed out");
+            }
 
+            _ => warn!(error = e.to_string(); "Player `{piece}` dropped"),
+        }
+    }
+
+    /// Answers `piece`'s requests until it disconnects.
+    fn serve(
+        stream: &mut dyn Transport,
+        piece: Piece,
+        game: &Mutex<Game>,
+        conn: &mut Conn,
+    ) -> io::Result<()> {
         loop {
-            let req = read_str(&mut stream)?;
-            let req: Request = serde_json::from_str(&req)?;
-            let mut game = game.lock().unwrap();
-
-            match req {
-                Request::Play { idx } => {
-                    let res = game.play(piece, idx);
-                    match res {
-                        Response::Valid { .. } => game.broadcast(res)?,
-                        _ => game.send(piece, res)?,
+            let data = conn.read(stream)?;
+            let now = Instant::now();
+            let req = metrics::decode(&data);
+            if Self::answer(&mut lock(game), piece, req, conn, now)?.is_break() {
+                return Ok(());
+            }
+
+            debug!(took_us = now.elapsed().as_micros() as u64; "Answered");
+        }
+    }
+
+    /// Answers one request from `piece`, as of `now`, breaking once it's gone. Whatever
+    /// carries requests calls this with the game locked, so games never see a socket.
+    pub(crate) fn answer(
+        game: &mut Game,
+        piece: Piece,
+        req: serde_json::Result<Request>,
+        conn: &mut Conn,
+        now: Instant,
+    ) -> io::Result<ControlFlow<()>> {
+        if conn.limited(now) {
+            warn!("Rate limited");
+            Self::reply(game, piece, Response::Invalid(ErrorCode::RateLimited))?;
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        let req = match req {
+            Ok(req) => req,
+            Err(e) => {
+                warn!(error = e.to_string(); "Malformed request");
+                Self::reply(game, piece, Response::Invalid(ErrorCode::Malformed))?;
+                if conn.violated(now) {
+                    METRICS.errored(ErrorCode::Banned);
+                    game.send(piece, Response::Invalid(ErrorCode::Banned))?;
+                    game.disconnect(piece);
+                    return Ok(ControlFlow::Break(()));
+                }
+
+                return Ok(ControlFlow::Continue(()));
+            }
+        };
+
+        debug!("Player `{piece}` sent {req:?}");
+        match req {
+            Request::Play { idx } => {
+                let res = game.play(piece, idx, now);
+                match res {
+                    Response::Valid { ref state, .. } => {
+                        info!(cell = format_cell(idx), state = state; "Player `{piece}` moved");
+                        conn.audit(game, piece, idx, state);
+                        game.broadcast(res);
                     }
+
+                    Response::Timeout { .. } => {
+                        info!("Player `{piece}` ran out of time");
+                        game.broadcast(res);
+                    }
+
+                    Response::Invalid(code) => {
+                        info!(idx = idx, code = code; "Refused move");
+                        Self::reply(game, piece, res)?;
+                    }
+
+                    _ => Self::reply(game, piece, res)?,
                 }
+            }
 
-                Request::Disconnect => {
-                    game.disconnect(piece)?;
-                    println!("Player `{piece}` ({ip}) disconnected");
-                    break;
+            Request::Takeback => {
+                let res = game.propose_takeback(piece);
+                if let Response::TakebackOffer(_) = res {
+                    game.send(piece.other(), res).ok();
+                } else {
+                    Self::reply(game, piece, res)?;
                 }
-            };
+            }
+
+            Request::AcceptTakeback | Request::DeclineTakeback => {
+                let accept = req == Request::AcceptTakeback;
+                let res = game.answer_takeback(piece, accept, now);
+                match res {
+                    Response::Takeback { .. } => {
+                        info!("Move taken back");
+                        game.broadcast(res);
+                    }
+
+                    Response::TakebackDeclined => {
+                        game.send(piece.other(), res).ok();
+                    }
+
+                    _ => Self::reply(game, piece, res)?,
+                }
+            }
+
+            Request::Resign => {
+                let res = game.resign(piece);
+                if let Response::Resigned { .. } = res {
+                    info!("Player `{piece}` resigned");
+                    game.broadcast(res);
+                } else {
+                    Self::reply(game, piece, res)?;
+                }
+            }
+
+            Request::Chat(text) => match game.chat(piece, &text) {
+                res @ Response::Chat { .. } => game.broadcast(res),
+                res => Self::reply(game, piece, res)?,
+            },
+
+            Request::Ping(nonce) => Self::reply(game, piece, Response::Pong(nonce))?,
+
+            Request::Join { .. } | Request::Spectate { .. } => {
+                warn!("Join after joining");
+                Self::reply(game, piece, Response::Invalid(ErrorCode::AlreadyJoined))?;
+            }
+
+            Request::Disconnect => {
+                game.disconnect(piece);
+                return Ok(ControlFlow::Break(()));
+            }
+        };
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Answers a request from `piece`, counting it if it's refused.
+    fn reply(game: &mut Game, piece: Piece, res: Response) -> io::Result<()> {
+        if let Response::Invalid(code) = res {
+            METRICS.refused(code);
+        }
+
+        game.send(piece, res)
+    }
+
+    /// Answers a request from spectator `id`, counting it if it's refused.
+    fn reply_spectator(game: &mut Game, id: u64, res: Response) -> io::Result<()> {
+        if let Response::Invalid(code) = res {
+            METRICS.refused(code);
         }
 
+        game.send_spectator(id, res)
+    }
+
+    /// Answers a spectator's requests until it leaves, it can only ping.
+    fn watch(
+        stream: &mut dyn Transport,
+        id: u64,
+        game: &Mutex<Game>,
+        conn: &mut Conn,
+    ) -> io::Result<()> {
+        loop {
+            let data = conn.read(stream)?;
+            let req = metrics::decode(&data);
+            let now = Instant::now();
+            if Self::answer_spectator(&mut lock(game), id, req, conn, now)?.is_break() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Answers one request from spectator `id`, as of `now`, breaking once it's gone.
+    pub(crate) fn answer_spectator(
+        game: &mut Game,
+        id: u64,
+        req: serde_json::Result<Request>,
+        conn: &mut Conn,
+        now: Instant,
+    ) -> io::Result<ControlFlow<()>> {
+        if conn.limited(now) {
+            warn!("Rate limited");
+            Self::reply_spectator(game, id, Response::Invalid(ErrorCode::RateLimited))?;
+            return Ok(ControlFlow::Continue(()));
+        }
+
+        match req {
+            Ok(Request::Ping(nonce)) => Self::reply_spectator(game, id, Response::Pong(nonce))?,
+            Ok(Request::Disconnect) => return Ok(ControlFlow::Break(())),
+            Ok(Request::Join { .. } | Request::Spectate { .. }) => {
+                warn!("Join after joining");
+                Self::reply_spectator(game, id, Response::Invalid(ErrorCode::AlreadyJoined))?;
+            }
+
+            Ok(req) => {
+                warn!(request = format!("{req:?}"); "Spectator tried to play");
+                Self::reply_spectator(game, id, Response::Invalid(ErrorCode::NotSeated))?;
+            }
+
+            Err(e) => {
+                warn!(error = e.to_string(); "Malformed request");
+                Self::reply_spectator(game, id, Response::Invalid(ErrorCode::Malformed))?;
+                if conn.violated(now) {
+                    METRICS.errored(ErrorCode::Banned);
+                    game.send_spectator(id, Response::Invalid(ErrorCode::Banned))?;
+                    return Ok(ControlFlow::Break(()));
+                }
+            }
+        }
+
+        Ok(ControlFlow::Continue(()))
+    }
+
+    /// Ends the games whose side to move ran out of time, until `rooms` is dropped.
+    fn tick(rooms: Weak<Rooms>) {
+        thread::spawn(move || {
+            while let Some(rooms) = rooms.upgrade() {
+                let now = Instant::now();
+                for game in rooms.games() {
+                    lock(&game).tick(now);
+                }
+
+                drop(rooms);
+                thread::sleep(TICK_INTERVAL);
+            }
+        });
+    }
+
+    /// Listens where clients are told to connect.
+    fn main_listener(&self) -> Result<Listener, &'static str> {
+        if let Some(path) = &self.unix {
+            #[cfg(unix)]
+            return Listener::unix(path).map_err(|_| "Failed to bind to Unix socket");
+
+            #[cfg(not(unix))]
+            return Err("Unix sockets aren't supported on this platform");
+        }
+
+        Self::listen(self.address)
+            .map(Listener::Tcp)
+            .map_err(|_| "Failed to bind to address")
+    }
+
+    pub fn run(self) -> Result<(), &'static str> {
+        let tls = match &self.tls {
+            Some(identity) => Some(
+                identity
+                    .server_config()
+                    .map_err(|_| "Invalid TLS certificate or key")?,
+            ),
+            None => None,
+        };
+
+        let listener = self.main_listener()?;
+
+        let pool = Arc::new(ThreadPool::new(self.pool));
+        let rooms = Arc::new(Rooms::new(self.settings, self.recorder));
+        Self::tick(Arc::downgrade(&rooms));
+        let context = Context {
+            rooms: Arc::clone(&rooms),
+            clients: Arc::default(),
+            audit: self.audit,
+            tls,
+        };
+
+        // Bots play in the server's own process, so they need neither sockets nor TLS.
+        let shared = context.clone();
+        let connect: bot::Connect = Arc::new(move || shared.connect());
+        for config in self.bots {
+            bot::spawn(Arc::clone(&connect), config);
+        }
+
+        if let Some(metrics) = self.metrics {
+            let listener = Self::listen(metrics).map_err(|_| "Failed to bind metrics address")?;
+            metrics::serve(listener, Arc::clone(&rooms), pool.stats())
+                .map_err(|_| "Failed to serve metrics")?;
+            info!("Serving metrics at http://{metrics}/metrics");
+        }
+
+        if let Some((address, token)) = self.api {
+            let listener = Self::listen(address).map_err(|_| "Failed to bind API address")?;
+            api::serve(listener, Arc::clone(&rooms), token).map_err(|_| "Failed to serve API")?;
+            info!("Serving the API at http://{address}");
+        }
+
+        for (protocol, address) in self.listeners {
+            let listener = Self::listen(address).map_err(|_| match protocol {
+                Protocol::WebSocket => "Failed to bind WebSocket address",
+                _ => "Failed to bind line address",
+            })?;
+            let listener = Listener::Tcp(listener);
+            let (context, pool) = (context.clone(), Arc::clone(&pool));
+            thread::spawn(move || Self::accept_loop(listener, protocol, context, &pool));
+            info!("Accepting {protocol:?} clients at {address}");
+        }
+
+        // Connections from the same machine have nothing to hide.
+        let (address, context) = match &self.unix {
+            Some(path) => {
+                let address = format!("{UNIX_PREFIX}{}", path.display());
+                (
+                    address,
+                    Context {
+                        tls: None,
+                        ..context
+                    },
+                )
+            }
+            None => (self.address.to_string(), context),
+        };
+
+        let threads = self.pool.max;
+        let encrypted = context.tls.is_some();
+        info!("Ready to rumble!!! (address: {address}, threads: {threads}, tls: {encrypted})");
+        Self::accept_loop(listener, Protocol::Framed, context, &pool);
         Ok(())
     }
+}
+
+/// Locks `mutex` even if a handler panicked while holding it, so one bad connection
+/// can't take a game down for everyone else.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::TimeControl;
+    use crate::config::Config;
+    use crate::limit::{Ban, RateLimit};
+    use crate::room::DEFAULT_ROOM;
+    use core::game::board::Board;
+    use core::tls::Trust;
+    use core::{read_str, write_str};
+    use std::io::{BufRead, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use tungstenite::{Message, WebSocket};
+
+    /// Serves fresh rooms on an ephemeral port.
+    fn start() -> SocketAddr {
+        start_with_timeout(None)
+    }
+
+    fn start_with_timeout(idle_timeout: Option<Duration>) -> SocketAddr {
+        start_with(Settings {
+            idle_timeout,
+            ..Settings::default()
+        })
+    }
+
+    fn start_with(settings: Settings) -> SocketAddr {
+        serve_on(
+            TcpListener::bind("127.0.0.1:0").unwrap(),
+            settings,
+            None,
+            None,
+        )
+    }
+
+    fn serve_on(
+        listener: TcpListener,
+        settings: Settings,
+        audit: Option<Arc<Recorder>>,
+        tls: Option<Arc<ServerConfig>>,
+    ) -> SocketAddr {
+        let context = Context {
+            audit,
+            tls,
+            ..Context::new(settings)
+        };
+
+        listen_on(listener, Protocol::Framed, context)
+    }
+
+    /// Serves clients of `listener` speaking `protocol`, a thread each.
+    fn listen_on(listener: TcpListener, protocol: Protocol, context: Context) -> SocketAddr {
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in Listener::Tcp(listener).incoming() {
+                let Some(peer) = Server::admit(&stream, &context, protocol) else {
+                    continue;
+                };
+
+                let context = context.clone();
+                thread::spawn(move || Server::handle(stream, protocol, peer, context));
+            }
+        });
+
+        address
+    }
+
+    fn send(stream: &mut impl Write, req: &Request) {
+        write_str(stream, &serde_json::to_string(req).unwrap()).unwrap();
+    }
+
+    fn recv(stream: &mut impl io::Read) -> Response {
+        serde_json::from_str(&read_str(stream).unwrap()).unwrap()
+    }
+
+    fn send_ws(ws: &mut WebSocket<TcpStream>, req: &Request) {
+        let json = serde_json::to_string(req).unwrap();
+        ws.send(Message::text(json)).unwrap();
+    }
+
+    fn recv_ws(ws: &mut WebSocket<TcpStream>) -> Response {
+        loop {
+            match ws.read().unwrap() {
+                Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+                Message::Ping(_) | Message::Pong(_) => {}
+                message => panic!("Expected a text message, got {message:?}"),
+            }
+        }
+    }
+
+    fn join(address: SocketAddr) -> (TcpStream, Piece) {
+        join_room(address, DEFAULT_ROOM)
+    }
+
+    fn join_room(address: SocketAddr, room: &str) -> (TcpStream, Piece) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let room = room.to_string();
+        let name = "tester".to_string();
+        send(&mut stream, &Request::Join { room, name });
+
+        match recv(&mut stream) {
+            Response::Init { piece, .. } => (stream, piece),
+            res => panic!("Expected Init, got {res:?}"),
+        }
+    }
+
+    /// Seats both players, consuming the notice X gets when O joins.
+    fn join_both(address: SocketAddr) -> (TcpStream, TcpStream) {
+        let (mut x, _) = join(address);
+        let (o, _) = join(address);
+        assert!(matches!(
+            recv(&mut x),
+            Response::Connect {
+                piece: Piece::O,
+                ..
+            }
+        ));
+        (x, o)
+    }
+
+    #[test]
+    fn out_of_bounds_move() {
+        let (mut x, mut o) = join_both(start());
+
+        send(&mut x, &Request::Play { idx: (5, 0) });
+        assert!(matches!(
+            recv(&mut x),
+            Response::Invalid(ErrorCode::OutOfBounds)
+        ));
+
+        send(&mut x, &Request::Play { idx: (0, 0) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+        assert!(matches!(recv(&mut o), Response::Valid { .. }));
+    }
+
+    #[test]
+    fn malformed_request() {
+        let (mut x, mut o) = join_both(start());
+
+        write_str(&mut x, "{ not json").unwrap();
+        assert!(matches!(
+            recv(&mut x),
+            Response::Invalid(ErrorCode::Malformed)
+        ));
+
+        x.write_all(&4usize.to_be_bytes()).unwrap();
+        x.write_all(&[0xff, 0xfe, 0xfd, 0xfc]).unwrap();
+        assert!(matches!(
+            recv(&mut x),
+            Response::Invalid(ErrorCode::Malformed)
+        ));
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+        assert!(matches!(recv(&mut o), Response::Valid { .. }));
+    }
+
+    #[test]
+    fn third_player_rejected() {
+        let address = start();
+        let (mut x, mut o) = join_both(address);
+
+        let mut third = TcpStream::connect(address).unwrap();
+        let room = DEFAULT_ROOM.to_string();
+        let name = "third".to_string();
+        send(&mut third, &Request::Join { room, name });
+        assert!(matches!(
+            recv(&mut third),
+            Response::Invalid(ErrorCode::RoomFull)
+        ));
+        assert!(read_str(&mut third).is_err());
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+        assert!(matches!(recv(&mut o), Response::Valid { .. }));
+    }
+
+    #[test]
+    fn oversized_frame_frees_seat() {
+        let address = start();
+        let (mut x, mut o) = join_both(address);
+
+        x.write_all(&usize::MAX.to_be_bytes()).unwrap();
+        assert!(matches!(recv(&mut o), Response::Disconnect(Piece::X)));
+
+        let (_, piece) = join(address);
+        assert_eq!(Piece::X, piece);
+    }
+
+    #[test]
+    fn dropped_connection_frees_seat() {
+        let address = start();
+        let (x, mut o) = join_both(address);
+
+        drop(x);
+        assert!(matches!(recv(&mut o), Response::Disconnect(Piece::X)));
+
+        let (_, piece) = join(address);
+        assert_eq!(Piece::X, piece);
+    }
+
+    /// The server's end of an in-memory connection, panicking on any request after
+    /// the first.
+    struct Panicky(transport::Memory, bool);
+
+    impl Sink for Panicky {
+        fn send(&mut self, res: &Response) -> io::Result<()> {
+            self.0.send(res)
+        }
+
+        fn close(&mut self) -> io::Result<()> {
+            self.0.close()
+        }
+    }
+
+    impl Transport for Panicky {
+        fn recv(&mut self) -> io::Result<Vec<u8>> {
+            let data = self.0.recv()?;
+            assert!(!std::mem::replace(&mut self.1, true), "Boom");
+            Ok(data)
+        }
+
+        fn sink(&self) -> io::Result<Box<dyn Sink>> {
+            self.0.sink()
+        }
+
+        fn peer_addr(&self) -> io::Result<SocketAddr> {
+            self.0.peer_addr()
+        }
+
+        fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.0.set_read_timeout(timeout)
+        }
+    }
+
+    #[test]
+    fn panicking_handler_frees_seat() {
+        let context = Context::new(Settings::default());
+        let pool = ThreadPool::new(threadpool::Options::default());
+        let join = Request::Join {
+            room: String::new(),
+            name: "tester".to_string(),
+        };
+
+        let x = context.connect().unwrap();
+        x.send(&join).unwrap();
+        assert!(matches!(x.recv().unwrap(), Response::Init { .. }));
+
+        let (server, o) = transport::memory();
+        let peer = context
+            .clients
+            .connect(
+                Ipv4Addr::LOCALHOST.into(),
+                &Settings::default(),
+                Instant::now(),
+            )
+            .unwrap();
+        let rooms = Arc::clone(&context.rooms);
+        let job =
+            move || Server::handle_client(Box::new(Panicky(server, false)), rooms, peer, None);
+        assert!(pool.execute(move || drop(job())).is_ok());
+
+        o.send(&join).unwrap();
+        assert!(matches!(
+            o.recv().unwrap(),
+            Response::Init {
+                piece: Piece::O,
+                ..
+            }
+        ));
+        assert!(matches!(
+            x.recv().unwrap(),
+            Response::Connect {
+                piece: Piece::O,
+                ..
+            }
+        ));
+        o.send(&Request::Ping(0)).unwrap();
+        assert!(matches!(x.recv().unwrap(), Response::Disconnect(Piece::O)));
+
+        let late = context.connect().unwrap();
+        late.send(&join).unwrap();
+        assert!(matches!(
+            late.recv().unwrap(),
+            Response::Init {
+                piece: Piece::O,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn ping() {
+        let (mut x, _o) = join_both(start());
+
+        send(&mut x, &Request::Ping(42));
+        assert!(matches!(recv(&mut x), Response::Pong(42)));
+    }
+
+    #[test]
+    fn idle_player_times_out() {
+        let address = start_with_timeout(Some(Duration::from_millis(200)));
+        let (mut x, mut o) = join_both(address);
+        o.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // X keeps pinging while O stays silent, until O's seat is freed.
+        let mut pongs = 0;
+        loop {
+            thread::sleep(Duration::from_millis(50));
+            send(&mut x, &Request::Ping(0));
+
+            match recv(&mut x) {
+                Response::Pong(0) => pongs += 1,
+                Response::Disconnect(Piece::O) => break,
+                res => panic!("Unexpected {res:?}"),
+            }
+
+            assert!(pongs < 40, "O never timed out");
+        }
+
+        assert!(pongs >= 2);
+        assert!(matches!(recv(&mut o), Response::Disconnect(Piece::O)));
+        assert!(read_str(&mut o).is_err());
+
+        let (_, piece) = join(address);
+        assert_eq!(Piece::O, piece);
+    }
+
+    #[test]
+    fn ipv6() {
+        let listener = Server::listen("[::1]:0".parse().unwrap()).unwrap();
+        let (mut x, mut o) = join_both(serve_on(listener, Settings::default(), None, None));
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+        assert!(matches!(recv(&mut o), Response::Valid { .. }));
+    }
+
+    #[test]
+    fn dual_stack() {
+        let listener = Server::listen("[::]:0".parse().unwrap()).unwrap();
+        let port = serve_on(listener, Settings::default(), None, None).port();
+
+        let (mut x, _) = join(SocketAddr::from(([127, 0, 0, 1], port)));
+        let (_o, _) = join(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port)));
+        assert!(matches!(recv(&mut x), Response::Connect { .. }));
+    }
+
+    #[test]
+    fn rooms_are_separate() {
+        let address = start();
+        let (mut x, _o) = join_both(address);
+        let (_, piece) = join_room(address, "other");
+        assert_eq!(Piece::X, piece);
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+    }
+
+    #[test]
+    fn empty_room_closes() {
+        let address = start();
+        let (mut x, o) = join_both(address);
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+
+        send(&mut x, &Request::Disconnect);
+        assert!(matches!(recv(&mut x), Response::Disconnect(Piece::X)));
+        drop(o);
+
+        // Rejoining until the room is gone, the last player may still be on its way out.
+        for _ in 0..100 {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let room = DEFAULT_ROOM.to_string();
+            let name = "tester".to_string();
+            send(&mut stream, &Request::Join { room, name });
+
+            match recv(&mut stream) {
+                Response::Init { board, .. } if board == Board::new() => return,
+                Response::Init { .. } => thread::sleep(Duration::from_millis(10)),
+                res => panic!("Expected Init, got {res:?}"),
+            }
+        }
+
+        panic!("The room never closed");
+    }
+
+    #[test]
+    fn spectator() {
+        let address = start();
+        let (mut x, _o) = join_both(address);
+
+        let mut spectator = TcpStream::connect(address).unwrap();
+        let room = DEFAULT_ROOM.to_string();
+        send(&mut spectator, &Request::Spectate { room });
+        let Response::Watch { players, .. } = recv(&mut spectator) else {
+            panic!("Expected Watch");
+        };
+        assert_eq!(2, players.len());
+
+        send(&mut spectator, &Request::Play { idx: (0, 0) });
+        assert!(matches!(
+            recv(&mut spectator),
+            Response::Invalid(ErrorCode::NotSeated)
+        ));
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+        assert!(matches!(
+            recv(&mut spectator),
+            Response::Valid { idx: (1, 1), .. }
+        ));
+    }
+
+    #[test]
+    fn must_join_first() {
+        let mut stream = TcpStream::connect(start()).unwrap();
+        send(&mut stream, &Request::Play { idx: (0, 0) });
+        assert!(matches!(
+            recv(&mut stream),
+            Response::Invalid(ErrorCode::NotSeated)
+        ));
+        assert!(read_str(&mut stream).is_err());
+    }
+
+    #[test]
+    fn too_many_rooms() {
+        let address = start_with(Settings {
+            max_rooms: 1,
+            ..Settings::default()
+        });
+        let (_x, _) = join(address);
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let room = "other".to_string();
+        let name = "tester".to_string();
+        send(&mut stream, &Request::Join { room, name });
+        assert!(matches!(
+            recv(&mut stream),
+            Response::Invalid(ErrorCode::TooManyRooms)
+        ));
+    }
+
+    #[test]
+    fn rate_limited() {
+        let address = start_with(Settings {
+            rate_limit: Some(RateLimit {
+                rate: 0.1,
+                burst: 2.0,
+            }),
+            ..Settings::default()
+        });
+        let (mut x, _) = join(address);
+
+        for _ in 0..2 {
+            send(&mut x, &Request::Ping(1));
+            assert!(matches!(recv(&mut x), Response::Pong(1)));
+        }
+
+        send(&mut x, &Request::Ping(1));
+        assert!(matches!(
+            recv(&mut x),
+            Response::Invalid(ErrorCode::RateLimited)
+        ));
+    }
+
+    #[test]
+    fn spectators_rate_limited_by_default() {
+        let address = start();
+        let (_x, _o) = join_both(address);
+
+        let mut spectator = TcpStream::connect(address).unwrap();
+        let room = DEFAULT_ROOM.to_string();
+        send(&mut spectator, &Request::Spectate { room });
+        assert!(matches!(recv(&mut spectator), Response::Watch { .. }));
+
+        // Far more pings than a burst allows, sent before reading any answer.
+        for nonce in 0..100 {
+            send(&mut spectator, &Request::Ping(nonce));
+        }
+
+        let limited = (0..100)
+            .filter(|_| {
+                matches!(
+                    recv(&mut spectator),
+                    Response::Invalid(ErrorCode::RateLimited)
+                )
+            })
+            .count();
+        assert!(limited > 0);
+    }
+
+    #[test]
+    fn ip_rate_limited() {
+        let address = start_with(Settings {
+            ip_rate_limit: Some(RateLimit {
+                rate: 0.1,
+                burst: 2.0,
+            }),
+            ..Settings::default()
+        });
+        let (mut x, mut o) = join_both(address);
+
+        send(&mut x, &Request::Ping(1));
+        assert!(matches!(recv(&mut x), Response::Pong(1)));
+        send(&mut o, &Request::Ping(2));
+        assert!(matches!(recv(&mut o), Response::Pong(2)));
+
+        // Both players connect from the same address, sharing its two tokens.
+        send(&mut o, &Request::Ping(3));
+        assert!(matches!(
+            recv(&mut o),
+            Response::Invalid(ErrorCode::RateLimited)
+        ));
+    }
+
+    #[test]
+    fn connections_per_ip() {
+        let address = start_with(Settings {
+            max_connections_per_ip: Some(1),
+            ..Settings::default()
+        });
+        let (_x, _) = join(address);
+
+        let mut second = TcpStream::connect(address).unwrap();
+        assert!(matches!(
+            recv(&mut second),
+            Response::Invalid(ErrorCode::TooManyConnections)
+        ));
+    }
 
-    pub fn run(self, nthreads: usize) -> Result<(), &'static str> {
-        let Ok(listener) = TcpListener::bind(self.0) else {
-            return Err("Failed to bind to address");
+    #[test]
+    fn banned_after_violations() {
+        let address = start_with(Settings {
+            ban: Some(Ban {
+                strikes: 2.try_into().unwrap(),
+                duration: Duration::from_secs(60),
+            }),
+            ..Settings::default()
+        });
+        let (mut x, mut o) = join_both(address);
+
+        write_str(&mut x, "{ not json").unwrap();
+        assert!(matches!(
+            recv(&mut x),
+            Response::Invalid(ErrorCode::Malformed)
+        ));
+        write_str(&mut x, "still not json").unwrap();
+        assert!(matches!(
+            recv(&mut x),
+            Response::Invalid(ErrorCode::Malformed)
+        ));
+        assert!(matches!(recv(&mut x), Response::Invalid(ErrorCode::Banned)));
+        assert!(matches!(recv(&mut o), Response::Disconnect(Piece::X)));
+
+        let mut again = TcpStream::connect(address).unwrap();
+        assert!(matches!(
+            recv(&mut again),
+            Response::Invalid(ErrorCode::Banned)
+        ));
+    }
+
+    #[test]
+    fn tls() {
+        let identity = Identity::self_signed().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = identity.server_config().unwrap();
+        let address = serve_on(listener, Settings::default(), None, Some(config));
+
+        let trust = Trust::Fingerprint(identity.fingerprint().unwrap());
+        let config = trust.client_config().unwrap();
+        let connect = || {
+            let tcp = TcpStream::connect(address).unwrap();
+            TlsStream::connect(tcp, Arc::clone(&config), "localhost").unwrap()
         };
 
-        let pool = ThreadPool::new(nthreads);
-        let game = Arc::new(Mutex::new(Game::new()));
+        let mut x = connect();
+        let room = DEFAULT_ROOM.to_string();
+        let name = "tester".to_string();
+        send(&mut x, &Request::Join { room, name });
+        assert!(matches!(recv(&mut x), Response::Init { .. }));
+
+        let mut o = connect();
+        let room = DEFAULT_ROOM.to_string();
+        let name = "tester".to_string();
+        send(&mut o, &Request::Join { room, name });
+        assert!(matches!(recv(&mut o), Response::Init { .. }));
+        assert!(matches!(recv(&mut x), Response::Connect { .. }));
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+        assert!(matches!(recv(&mut o), Response::Valid { .. }));
+
+        // Plain connections can't get through.
+        let mut plain = TcpStream::connect(address).unwrap();
+        send(&mut plain, &Request::Ping(1));
+        assert!(read_str(&mut plain).is_err());
+    }
+
+    #[test]
+    fn websocket() {
+        let context = Context {
+            rooms: Arc::new(Rooms::new(Arc::default(), None)),
+            clients: Arc::default(),
+            audit: None,
+            tls: None,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = listen_on(listener, Protocol::Framed, context.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ws = listen_on(listener, Protocol::WebSocket, context);
+
+        // X over plain TCP, O from a browser, in the same room.
+        let (mut x, _) = join(tcp);
+        let stream = TcpStream::connect(ws).unwrap();
+        let (mut o, _) = tungstenite::client(format!("ws://{ws}/"), stream).unwrap();
+        let room = DEFAULT_ROOM.to_string();
+        let name = "browser".to_string();
+        send_ws(&mut o, &Request::Join { room, name });
+        assert!(matches!(
+            recv_ws(&mut o),
+            Response::Init {
+                piece: Piece::O,
+                ..
+            }
+        ));
+        assert!(matches!(recv(&mut x), Response::Connect { .. }));
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+        assert!(matches!(recv_ws(&mut o), Response::Valid { .. }));
 
-        let port = self.0.port();
-        println!("Ready to rumble!!! (port: {port})");
+        send_ws(&mut o, &Request::Play { idx: (0, 0) });
+        assert!(matches!(recv_ws(&mut o), Response::Valid { .. }));
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
 
-        for stream in listener.incoming().flatten() {
-            let game = Arc::clone(&game);
-            pool.execute(move || {
-                if let Err(e) = Self::handle_client(stream, game) {
-                    eprintln!("{e}");
+        // Frames aren't WebSocket messages.
+        let mut framed = TcpStream::connect(ws).unwrap();
+        send(&mut framed, &Request::Ping(1));
+        assert!(read_str(&mut framed).is_err());
+    }
+
+    #[test]
+    fn line() {
+        let context = Context {
+            rooms: Arc::new(Rooms::new(Arc::default(), None)),
+            clients: Arc::default(),
+            audit: None,
+            tls: None,
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let tcp = listen_on(listener, Protocol::Framed, context.clone());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let line = listen_on(listener, Protocol::Line, context);
+
+        let (mut x, _) = join(tcp);
+        let mut o = TcpStream::connect(line).unwrap();
+        let mut lines = io::BufReader::new(o.try_clone().unwrap()).lines();
+        let mut next = || lines.next().unwrap().unwrap();
+        assert!(next().starts_with("Welcome!"));
+        assert!(next().contains("30 minutes"));
+
+        o.write_all(b"dance\r\njoin lobby Marty\r\n").unwrap();
+        assert_eq!("Unknown command `dance`, try `help`", next());
+        assert_eq!("You play O against tester", next());
+        let Response::Connect { name, .. } = recv(&mut x) else {
+            panic!("Expected Connect");
+        };
+        assert_eq!("Marty", name);
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+        // The rest of O's board, then X's move.
+        let board: Vec<_> = (0..13).map(|_| next()).collect();
+        assert_eq!("X to move", board[7]);
+        assert_eq!("X played b2", board[8]);
+        assert_eq!("   | X |   ", board[12]);
+
+        o.write_all(b"chat gg\nresign\n").unwrap();
+        let Response::Chat { piece, text, .. } = recv(&mut x) else {
+            panic!("Expected Chat");
+        };
+        assert_eq!((Piece::O, "gg"), (piece, text.as_str()));
+        assert!(matches!(
+            recv(&mut x),
+            Response::Resigned {
+                piece: Piece::O,
+                turn: Piece::O
+            }
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn default_config() {
+        let path = std::env::temp_dir().join(format!("default-{}.sock", std::process::id()));
+        let config = Config::default();
+        let server = Server::unix(&path)
+            .settings(config.settings())
+            .pool(config.pool());
+        thread::spawn(move || server.run());
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let connect = || loop {
+            assert!(Instant::now() < deadline, "The server never listened");
+            if let Ok(stream) = Stream::unix(&path) {
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+                return stream;
+            }
+
+            thread::sleep(Duration::from_millis(10));
+        };
+        let join = |stream: &mut Stream, room: &str| {
+            let (room, name) = (room.to_string(), "tester".to_string());
+            send(stream, &Request::Join { room, name });
+            recv(stream)
+        };
+
+        // More connections than workers, each answered all the same.
+        let mut x = connect();
+        assert!(matches!(
+            join(&mut x, ""),
+            Response::Init {
+                piece: Piece::X,
+                ..
+            }
+        ));
+        let mut o = connect();
+        assert!(matches!(
+            join(&mut o, ""),
+            Response::Init {
+                piece: Piece::O,
+                ..
+            }
+        ));
+        assert!(matches!(recv(&mut x), Response::Connect { .. }));
+
+        let mut third = connect();
+        let res = join(&mut third, "");
+        assert!(matches!(res, Response::Invalid(ErrorCode::RoomFull)));
+
+        let mut elsewhere = connect();
+        let res = join(&mut elsewhere, "zoo");
+        assert!(matches!(
+            res,
+            Response::Init {
+                piece: Piece::X,
+                ..
+            }
+        ));
+
+        let mut spectator = connect();
+        let room = String::new();
+        send(&mut spectator, &Request::Spectate { room });
+        assert!(matches!(recv(&mut spectator), Response::Watch { .. }));
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn unix() {
+        let path = std::env::temp_dir().join(format!("server-{}.sock", std::process::id()));
+        let bot = BotConfig {
+            room: "zoo".to_string(),
+            name: "Mort".to_string(),
+            skill: 1.0,
+            delay: Duration::ZERO,
+        };
+        let server = Server::unix(&path).bot(bot);
+        thread::spawn(move || server.run());
+
+        // The bot joins in memory and takes X, wait until a spectator on the socket
+        // sees it seated.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            assert!(Instant::now() < deadline, "The bot never took its seat");
+            let Ok(mut stream) = Stream::unix(&path) else {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            };
+
+            let left = deadline
+                .saturating_duration_since(Instant::now())
+                .max(Duration::from_millis(1));
+            stream.set_read_timeout(Some(left)).unwrap();
+            let room = "zoo".to_string();
+            send(&mut stream, &Request::Spectate { room });
+            match recv(&mut stream) {
+                Response::Watch { players, .. } if !players.is_empty() => break,
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+
+        let mut o = Stream::unix(&path).unwrap();
+        let room = "zoo".to_string();
+        let name = "Julien".to_string();
+        send(&mut o, &Request::Join { room, name });
+        assert!(matches!(
+            recv(&mut o),
+            Response::Init {
+                piece: Piece::O,
+                ..
+            }
+        ));
+
+        assert!(matches!(
+            recv(&mut o),
+            Response::Valid {
+                piece: Piece::X,
+                turn: Piece::O,
+                ..
+            }
+        ));
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn flag_falls() {
+        let address = start_with(Settings {
+            time_control: Some(TimeControl {
+                initial: Duration::from_millis(200),
+                increment: Duration::ZERO,
+            }),
+            ..Settings::default()
+        });
+        let (mut x, mut o) = join_both(address);
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        let Response::Valid { clock, .. } = recv(&mut x) else {
+            panic!("Expected Valid");
+        };
+        assert_eq!(Some([200, 200]), clock);
+        assert!(matches!(recv(&mut o), Response::Valid { .. }));
+
+        // Nobody moves, the ticker flags O.
+        for stream in [&mut x, &mut o] {
+            assert!(matches!(
+                recv(stream),
+                Response::Timeout {
+                    piece: Piece::O,
+                    turn: Piece::O
                 }
-            });
+            ));
         }
+    }
 
-        Ok(())
+    #[test]
+    fn in_memory() {
+        let context = Context::new(Settings::default());
+        let join = Request::Join {
+            room: String::new(),
+            name: "tester".to_string(),
+        };
+        let x = context.connect().unwrap();
+        x.send(&join).unwrap();
+        assert!(matches!(
+            x.recv().unwrap(),
+            Response::Init {
+                piece: Piece::X,
+                ..
+            }
+        ));
+        let o = context.connect().unwrap();
+        o.send(&join).unwrap();
+        assert!(matches!(
+            o.recv().unwrap(),
+            Response::Init {
+                piece: Piece::O,
+                ..
+            }
+        ));
+        assert!(matches!(
+            x.recv().unwrap(),
+            Response::Connect {
+                piece: Piece::O,
+                ..
+            }
+        ));
+
+        for (player, idx) in [(&x, (0, 0)), (&o, (1, 0)), (&x, (0, 1)), (&o, (1, 1))] {
+            player.send(&Request::Play { idx }).unwrap();
+            assert!(matches!(x.recv().unwrap(), Response::Valid { .. }));
+            assert!(matches!(o.recv().unwrap(), Response::Valid { .. }));
+        }
+
+        x.send(&Request::Play { idx: (0, 2) }).unwrap();
+        for player in [&x, &o] {
+            assert!(matches!(
+                player.recv().unwrap(),
+                Response::Valid {
+                    state: GameState::Win(Piece::X),
+                    ..
+                }
+            ));
+        }
+
+        drop(o);
+        assert!(matches!(x.recv().unwrap(), Response::Disconnect(Piece::O)));
+    }
+
+    #[test]
+    fn bot_replies() {
+        let context = Context::new(Settings::default());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listen_on(listener, Protocol::Framed, context.clone());
+        let config: BotConfig = toml::from_str("room = \"bots\"").unwrap();
+        bot::spawn(Arc::new(move || context.connect()), config);
+
+        let (mut stream, piece) = join_room(address, "bots");
+        if piece == Piece::X {
+            assert!(matches!(
+                recv(&mut stream),
+                Response::Connect { name, .. } if name == "Bot"
+            ));
+            send(&mut stream, &Request::Play { idx: (1, 1) });
+            assert!(matches!(recv(&mut stream), Response::Valid { .. }));
+        }
+
+        assert!(matches!(
+            recv(&mut stream),
+            Response::Valid { piece: moved, .. } if moved != piece
+        ));
+    }
+
+    #[test]
+    fn audit_trail() {
+        let path = std::env::temp_dir().join(format!("audit-{}.jsonl", std::process::id()));
+        let audit = Arc::new(Recorder::open(&path).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (mut x, mut o) = join_both(serve_on(listener, Settings::default(), Some(audit), None));
+
+        send(&mut x, &Request::Play { idx: (1, 1) });
+        assert!(matches!(recv(&mut x), Response::Valid { .. }));
+        send(&mut x, &Request::Play { idx: (0, 0) });
+        assert!(matches!(recv(&mut x), Response::Invalid(_)));
+        assert!(matches!(recv(&mut o), Response::Valid { .. }));
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        let lines: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(1, lines.len());
+        assert_eq!("b2", lines[0]["cell"]);
+        assert_eq!("X", lines[0]["piece"]);
+        assert_eq!("tester", lines[0]["name"]);
+        assert_eq!(DEFAULT_ROOM, lines[0]["room"]);
     }
 }
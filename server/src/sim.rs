@@ -0,0 +1,644 @@
+//! Deterministic simulation: clients connecting, playing, stalling and vanishing in an
+//! order drawn from a seed, against the same rooms, games and request handling as the
+//! real server. Everything runs on one thread with a virtual clock, so a seed always
+//! replays the same run, and the invariants are checked after every step.
+//!
+//! Faults come from the seed too: requests held on the wire while others overtake them
+//! and time passes, malformed requests and room names too long, addresses banned for
+//! them, connections dropped with requests still in flight, and writes failing,
+//! breaking the connection.
+
+use crate::clock::TimeControl;
+use crate::config::Settings;
+use crate::limit::{Ban, Clients};
+use crate::room::{Rooms, MAX_ROOM_LEN};
+use crate::server::{lock, Conn, Occupant, Seat, Server};
+use crate::transport::Sink;
+use core::game::{board::Board, piece::Piece, state::GameState};
+use core::request::Request;
+use core::response::Response;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::Ipv4Addr;
+use std::num::NonZeroU32;
+use std::ops::{ControlFlow, Range};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// Rooms clients pick from, few enough for them to meet.
+const ROOMS: [&str; 2] = ["", "zoo"];
+
+/// Most clients connected at once.
+const MAX_CLIENTS: usize = 6;
+
+/// Addresses clients connect from, few enough for bans to hit several of them.
+const ADDRESSES: usize = 3;
+
+/// What clients send that can't be a request.
+const GARBAGE: &[u8] = b"{\"Play\":";
+
+/// Xorshift, seeded through SplitMix64 so neighbouring seeds diverge straight away.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self((z ^ (z >> 31)) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len())]
+    }
+}
+
+/// What the server wrote to a client, shared with the sink it writes through.
+#[derive(Default)]
+struct Inbox {
+    responses: VecDeque<Response>,
+    /// Writes fail, as on a connection gone bad.
+    failing: bool,
+    /// A write failed, the client hangs up as soon as it notices.
+    broken: bool,
+    /// The server let the client go, nothing should be written to it anymore.
+    closed: bool,
+    /// What was written after all, a sink left behind in a game.
+    late: Vec<Response>,
+}
+
+struct SimSink(Arc<Mutex<Inbox>>);
+
+impl Sink for SimSink {
+    fn send(&mut self, res: &Response) -> io::Result<()> {
+        let mut inbox = lock(&self.0);
+        if inbox.closed {
+            inbox.late.push(res.clone());
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+
+        if inbox.failing {
+            inbox.broken = true;
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+
+        inbox.responses.push_back(res.clone());
+        Ok(())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        lock(&self.0).broken = true;
+        Ok(())
+    }
+}
+
+/// Where the server put a client, as its handler knows it.
+enum Place {
+    /// Its first request is still on the way.
+    Arriving,
+    Seated(Seat),
+    /// The connection is over.
+    Gone,
+}
+
+struct Client {
+    id: usize,
+    room: String,
+    inbox: Arc<Mutex<Inbox>>,
+    /// Requests on the wire, reaching the server in order.
+    in_flight: VecDeque<Vec<u8>>,
+    conn: Conn,
+    place: Place,
+    /// The board and side to move as the client pieced them together from what it got.
+    view: Option<(Board, Piece)>,
+}
+
+impl Client {
+    fn gone(&self) -> bool {
+        matches!(self.place, Place::Gone)
+    }
+
+    fn seated(&self) -> bool {
+        matches!(self.place, Place::Seated(_))
+    }
+
+    /// Whether the client still hears everything the server says.
+    fn listening(&self) -> bool {
+        !self.gone() && !lock(&self.inbox).failing
+    }
+
+    /// Reads what the server wrote, failing if it doesn't add up.
+    fn read(&mut self) -> Result<(), String> {
+        let responses: Vec<_> = lock(&self.inbox).responses.drain(..).collect();
+        for res in responses {
+            self.apply(&res)
+                .map_err(|e| format!("client {} got {res:?}: {e}", self.id))?;
+        }
+
+        Ok(())
+    }
+
+    fn apply(&mut self, res: &Response) -> Result<(), String> {
+        match *res {
+            Response::Init { board, turn, .. }
+            | Response::Watch { board, turn, .. }
+            | Response::Takeback { board, turn } => self.view = Some((board, turn)),
+
+            Response::Valid {
+                piece,
+                idx,
+                state,
+                turn,
+                ..
+            } => {
+                let (board, next) = self.view.as_mut().ok_or("a move before a board")?;
+                board
+                    .make_move(idx, piece)
+                    .map_err(|e| format!("impossible move, {e:?}"))?;
+                if board.check_end(piece) != state {
+                    return Err("wrong outcome".to_string());
+                }
+
+                if state != GameState::Playing {
+                    board.clear();
+                }
+
+                *next = turn;
+            }
+
+            Response::Timeout { turn, .. } | Response::Resigned { turn, .. } => {
+                let (board, next) = self.view.as_mut().ok_or("a new game before a board")?;
+                board.clear();
+                *next = turn;
+            }
+
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// One simulated server and its clients.
+pub struct Sim {
+    rng: Rng,
+    rooms: Arc<Rooms>,
+    clients: Arc<Clients>,
+    population: Vec<Client>,
+    start: Instant,
+    elapsed: Duration,
+    /// What happened, one line per step.
+    trace: Vec<String>,
+}
+
+impl Sim {
+    pub fn new(seed: u64) -> Self {
+        let settings = Settings {
+            time_control: Some(TimeControl {
+                initial: Duration::from_secs(10),
+                increment: Duration::from_secs(2),
+            }),
+            ban: Some(Ban {
+                strikes: NonZeroU32::new(3).unwrap(),
+                duration: Duration::from_secs(20),
+            }),
+            ..Settings::default()
+        };
+
+        Self {
+            rng: Rng::new(seed),
+            rooms: Arc::new(Rooms::new(Arc::new(RwLock::new(settings)), None)),
+            clients: Arc::default(),
+            population: Vec::new(),
+            start: Instant::now(),
+            elapsed: Duration::ZERO,
+            trace: Vec::new(),
+        }
+    }
+
+    fn now(&self) -> Instant {
+        self.start + self.elapsed
+    }
+
+    fn live(&self) -> Vec<usize> {
+        (0..self.population.len())
+            .filter(|&i| !self.population[i].gone())
+            .collect()
+    }
+
+    /// Runs one step drawn from the seed, then checks the invariants.
+    pub fn step(&mut self) -> Result<(), String> {
+        let live = self.live();
+        let waiting: Vec<_> = live
+            .iter()
+            .copied()
+            .filter(|&i| !self.population[i].in_flight.is_empty())
+            .collect();
+
+        let event = match self.rng.below(20) {
+            0..=1 if live.len() < MAX_CLIENTS => self.connect(),
+            _ if live.is_empty() => self.connect(),
+            2..=7 => {
+                let i = *self.rng.pick(&live);
+                self.send(i)
+            }
+            8..=15 if !waiting.is_empty() => {
+                let i = *self.rng.pick(&waiting);
+                self.deliver(i)
+            }
+            16..=17 => self.wait(),
+            18 => {
+                let i = *self.rng.pick(&live);
+                self.hang_up(i, true);
+                format!("client {i} drops")
+            }
+            19 => {
+                let i = *self.rng.pick(&live);
+                lock(&self.population[i].inbox).failing = true;
+                format!("client {i} gets failing writes")
+            }
+            _ => self.wait(),
+        };
+
+        self.trace.push(event);
+
+        // Clients notice failed writes, and hang up.
+        for i in self.live() {
+            if lock(&self.population[i].inbox).broken {
+                self.hang_up(i, true);
+                self.trace.push(format!("client {i} hangs up"));
+            }
+        }
+
+        for client in &mut self.population {
+            client.read()?;
+        }
+
+        self.check()
+    }
+
+    fn connect(&mut self) -> String {
+        let id = self.population.len();
+        let room = match self.rng.below(10) {
+            0 => "z".repeat(MAX_ROOM_LEN + 1),
+            _ => self.rng.pick(&ROOMS).to_string(),
+        };
+
+        let data = match self.rng.below(10) {
+            0 => GARBAGE.to_vec(),
+            1..=2 => encode(&Request::Spectate { room: room.clone() }),
+            _ => encode(&Request::Join {
+                room: room.clone(),
+                name: format!("client {id}"),
+            }),
+        };
+
+        let ip = Ipv4Addr::new(10, 0, 0, 1 + self.rng.below(ADDRESSES) as u8).into();
+        let settings = self.rooms.settings();
+        let peer = match self.clients.connect(ip, &settings, self.now()) {
+            Ok(peer) => peer,
+            Err(code) => return format!("a client from {ip} is turned away with {code:?}"),
+        };
+
+        let event = format!("client {id} connects from {ip}, sending {}", show(&data));
+        self.population.push(Client {
+            id,
+            room,
+            inbox: Arc::default(),
+            in_flight: VecDeque::from([data]),
+            conn: Conn::new(ip, peer, None),
+            place: Place::Arriving,
+            view: None,
+        });
+
+        event
+    }
+
+    /// Puts a request from client `i` on the wire.
+    fn send(&mut self, i: usize) -> String {
+        // Now and then off the board.
+        let idx = (self.rng.below(4), self.rng.below(3));
+        let req = match self.rng.below(17) {
+            0..=8 => Request::Play { idx },
+            9 => Request::Takeback,
+            10 => Request::AcceptTakeback,
+            11 => Request::DeclineTakeback,
+            12 => Request::Resign,
+            13 => Request::Chat("gg".to_string()),
+            14 => Request::Ping(self.rng.next()),
+            15 => Request::Disconnect,
+            _ => {
+                self.population[i].in_flight.push_back(GARBAGE.to_vec());
+                return format!("client {i} sends {}", show(GARBAGE));
+            }
+        };
+
+        let event = format!("client {i} sends {req:?}");
+        self.population[i].in_flight.push_back(encode(&req));
+        event
+    }
+
+    /// Hands the oldest request on the wire from client `i` to the server, answering it
+    /// as its handler would.
+    fn deliver(&mut self, i: usize) -> String {
+        let now = self.now();
+        let client = &mut self.population[i];
+        let data = client.in_flight.pop_front().unwrap();
+        let event = format!("server gets {} from client {i}", show(&data));
+        let req = serde_json::from_slice(&data);
+
+        let flow = match &client.place {
+            Place::Arriving => {
+                let mut stream = SimSink(Arc::clone(&client.inbox));
+                let sink = Box::new(SimSink(Arc::clone(&client.inbox)));
+                match Server::seat(&self.rooms, req, &mut stream, sink, &client.conn, now) {
+                    Ok(Some(seat)) => {
+                        client.place = Place::Seated(seat);
+                        Ok(ControlFlow::Continue(()))
+                    }
+                    Ok(None) => Ok(ControlFlow::Break(())),
+                    Err(e) => Err(e),
+                }
+            }
+
+            Place::Seated(seat) => match seat.occupant {
+                Occupant::Player(piece) => {
+                    Server::answer(&mut lock(&seat.game), piece, req, &mut client.conn, now)
+                }
+                Occupant::Spectator(id) => {
+                    Server::answer_spectator(&mut lock(&seat.game), id, req, &mut client.conn, now)
+                }
+            },
+
+            Place::Gone => unreachable!("requests from gone clients are dropped"),
+        };
+
+        match flow {
+            Ok(ControlFlow::Continue(())) => {}
+            // A leaving player already gave its seat up, its handler only closes the room.
+            Ok(ControlFlow::Break(())) => self.hang_up(i, false),
+            Err(_) => self.hang_up(i, true),
+        }
+
+        event
+    }
+
+    /// Lets time pass while requests stay on the wire, then lets the clocks tick.
+    fn wait(&mut self) -> String {
+        let millis = self.rng.below(4000) as u64;
+        self.elapsed += Duration::from_millis(millis);
+        let now = self.now();
+        for game in self.rooms.games() {
+            lock(&game).tick(now);
+        }
+
+        format!("{millis} ms pass")
+    }
+
+    /// Ends client `i`'s connection as its handler does, losing whatever it still had on
+    /// the wire. A player is disconnected first if `disconnect`, when reading failed.
+    fn hang_up(&mut self, i: usize, disconnect: bool) {
+        let client = &mut self.population[i];
+        client.in_flight.clear();
+        if let Place::Seated(seat) = std::mem::replace(&mut client.place, Place::Gone) {
+            seat.leave(&self.rooms, disconnect);
+        }
+
+        // Closed only now, as leaving players are told they left like everyone else.
+        lock(&client.inbox).closed = true;
+    }
+
+    /// Whether what clients see agrees with the games, and the games with themselves.
+    fn check(&self) -> Result<(), String> {
+        for client in &self.population {
+            if let Some(res) = lock(&client.inbox).late.first() {
+                return Err(format!("client {} got {res:?} after leaving", client.id));
+            }
+
+            if client.seated() && client.room.len() > MAX_ROOM_LEN {
+                return Err(format!("client {} seated in a room too long", client.id));
+            }
+        }
+
+        for room in ROOMS {
+            let here: Vec<_> = self
+                .population
+                .iter()
+                .filter(|client| client.room == room && !client.gone())
+                .collect();
+
+            let Some(game) = self.rooms.get(room) else {
+                if here.iter().any(|client| client.seated()) {
+                    return Err(format!("room `{room}` closed with clients in it"));
+                }
+
+                continue;
+            };
+
+            let game = lock(&game);
+            let mut seated = Vec::new();
+            let mut spectators = 0;
+            for client in &here {
+                if let Place::Seated(seat) = &client.place {
+                    match seat.occupant {
+                        Occupant::Player(piece) => seated.push(piece),
+                        Occupant::Spectator(_) => spectators += 1,
+                    }
+                }
+            }
+
+            if seated.is_empty() && spectators == 0 {
+                return Err(format!("room `{room}` left open with nobody in it"));
+            }
+
+            for piece in [Piece::X, Piece::O] {
+                let holders = seated.iter().filter(|&&p| p == piece).count();
+                if holders != usize::from(game.name(piece).is_some()) {
+                    return Err(format!("{holders} clients hold {piece} in room `{room}`"));
+                }
+            }
+
+            if spectators != game.spectators() {
+                return Err(format!("{spectators} spectators in room `{room}`"));
+            }
+
+            let position = game.position();
+            let count = |piece| {
+                (0..3)
+                    .flat_map(|row| (0..3).map(move |col| (row, col)))
+                    .filter(|&idx| position.board.get(idx) == Some(Some(piece)))
+                    .count()
+            };
+            if count(Piece::X).abs_diff(count(Piece::O)) > 1 {
+                return Err(format!("unbalanced board in room `{room}`"));
+            }
+
+            let seen = here
+                .iter()
+                .filter(|client| client.listening() && client.seated());
+            for client in seen {
+                if client.view != Some((position.board, position.turn)) {
+                    return Err(format!(
+                        "client {} sees {:?}, room `{room}` has {:?} with {} to move",
+                        client.id, client.view, position.board, position.turn
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn encode(req: &Request) -> Vec<u8> {
+    serde_json::to_vec(req).unwrap()
+}
+
+/// What's on the wire, for traces.
+fn show(data: &[u8]) -> String {
+    String::from_utf8_lossy(data).into_owned()
+}
+
+/// A run that broke an invariant, replayed by running `seed` for `steps` steps.
+#[derive(Debug)]
+pub struct Failure {
+    pub seed: u64,
+    pub steps: usize,
+    pub error: String,
+    pub trace: Vec<String>,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (seed, steps) = (self.seed, self.steps);
+        writeln!(f, "Seed {seed} fails after {steps} steps: {}", self.error)?;
+        for line in &self.trace {
+            writeln!(f, "  {line}")?;
+        }
+
+        write!(f, "Replay it with SIM_SEED={seed}")
+    }
+}
+
+/// Runs `seed` for up to `steps` steps, stopping at the first one breaking an invariant
+/// or `extra`, which tests use to plant bugs.
+pub fn run(
+    seed: u64,
+    steps: usize,
+    extra: &dyn Fn(&Sim) -> Result<(), String>,
+) -> Result<(), Failure> {
+    let mut sim = Sim::new(seed);
+    for step in 1..=steps {
+        if let Err(error) = sim.step().and_then(|()| extra(&sim)) {
+            return Err(Failure {
+                seed,
+                steps: step,
+                error,
+                trace: sim.trace,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs every seed in `seeds`. Since a seed replays the same steps, a failure needs no
+/// more steps than the one it was caught at, so the one reported is the shortest to
+/// replay, the lowest seed among equally short ones.
+pub fn explore(
+    seeds: Range<u64>,
+    steps: usize,
+    extra: &dyn Fn(&Sim) -> Result<(), String>,
+) -> Result<(), Failure> {
+    let failures = seeds.filter_map(|seed| run(seed, steps, extra).err());
+    match failures.min_by_key(|failure| (failure.steps, failure.seed)) {
+        Some(failure) => Err(failure),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    const STEPS: usize = 200;
+
+    fn nothing(_: &Sim) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// The seeds to explore, `SIM_SEED` replaying one and `SIM_SEEDS` setting how many.
+    fn seeds() -> Range<u64> {
+        let var = |name| env::var(name).ok().and_then(|v| v.parse::<u64>().ok());
+        match (var("SIM_SEED"), var("SIM_SEEDS")) {
+            (Some(seed), _) => seed..seed + 1,
+            (None, seeds) => 0..seeds.unwrap_or(1000),
+        }
+    }
+
+    #[test]
+    fn random_schedules() {
+        if let Err(failure) = explore(seeds(), STEPS, &nothing) {
+            panic!("{failure}");
+        }
+    }
+
+    #[test]
+    fn replays_seeds() {
+        let trace = |seed| {
+            let mut sim = Sim::new(seed);
+            for _ in 0..STEPS {
+                sim.step().unwrap();
+            }
+
+            sim.trace
+        };
+
+        assert_eq!(trace(7), trace(7));
+        assert_ne!(trace(7), trace(8));
+    }
+
+    #[test]
+    fn finds_minimal_failing_seed() {
+        // A planted bug: games are never supposed to last five moves.
+        let full = |sim: &Sim| {
+            let games = sim.rooms.games();
+            match games
+                .iter()
+                .any(|game| lock(game).position().board.legal_moves().len() <= 4)
+            {
+                true => Err("five moves".to_string()),
+                false => Ok(()),
+            }
+        };
+
+        let failure = explore(0..200, STEPS, &full).unwrap_err();
+        assert_eq!("five moves", failure.error);
+
+        // It replays, and not a step sooner.
+        let again = run(failure.seed, failure.steps, &full).unwrap_err();
+        assert_eq!(failure.steps, again.steps);
+        assert!(run(failure.seed, failure.steps - 1, &full).is_ok());
+
+        // No seed explored fails sooner.
+        for seed in 0..200 {
+            if let Err(other) = run(seed, failure.steps - 1, &full) {
+                panic!("seed {seed} fails sooner, after {} steps", other.steps);
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
 mod pool;
 mod worker;
 
-pub use pool::ThreadPool;
+pub use pool::{Options, Policy, Stats, ThreadPool};
@@ -1,37 +1,350 @@
-use super::worker::{Job, Worker};
-use std::sync::mpsc::{self, Sender};
-use std::sync::{Arc, Mutex};
+use super::worker::{self, Job, WorkerStats};
+use crate::server::lock;
+use serde::Deserialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{self, Debug};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
+/// What [`ThreadPool::execute`] does when every worker is busy, the pool can't grow and
+/// the queue is full.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// Hands the job back straight away.
+    #[default]
+    Reject,
+    /// Waits for room in the queue.
+    Wait,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Options {
+    /// Workers kept even when there's nothing to do.
+    pub min: usize,
+    /// Workers the pool grows to when jobs keep coming.
+    pub max: usize,
+    /// Jobs that may wait for a worker once the pool can't grow anymore.
+    pub queue: usize,
+    /// How long a worker above `min` waits for a job before exiting.
+    pub keep_alive: Duration,
+    pub policy: Policy,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            min: 2,
+            max: 16,
+            queue: 64,
+            keep_alive: Duration::from_secs(60),
+            policy: Policy::Reject,
+        }
+    }
+}
+
+/// A job the pool turned down, given back to the caller.
+pub struct Rejected<F>(pub F);
+
+impl<F> Debug for Rejected<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Rejected(..)")
+    }
+}
+
+/// How busy a pool is and what each of its workers did, readable while it runs.
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub(super) queued: AtomicUsize,
+    pub(super) busy: AtomicUsize,
+    pub(super) panics: AtomicU64,
+    pub(super) rejected: AtomicU64,
+    pub(super) workers: Mutex<BTreeMap<usize, WorkerStats>>,
+}
+
+impl Stats {
+    /// Jobs waiting for a worker.
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Workers running a job.
+    pub fn busy(&self) -> usize {
+        self.busy.load(Ordering::Relaxed)
+    }
+
+    /// Jobs that panicked, each taking its worker down and having it replaced.
+    pub fn panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Jobs turned down because the pool was full.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Every live worker by id.
+    pub fn workers(&self) -> BTreeMap<usize, WorkerStats> {
+        lock(&self.workers).clone()
+    }
+}
+
+pub(super) struct State {
+    pub queue: VecDeque<Job>,
+    /// Workers waiting for a job.
+    pub idle: usize,
+    pub shutdown: bool,
+    pub next_id: usize,
+    pub threads: BTreeMap<usize, Option<JoinHandle<()>>>,
+}
+
+pub(super) struct Shared {
+    pub options: Options,
+    pub state: Mutex<State>,
+    /// Signalled when a job is queued or the pool shuts down.
+    pub work: Condvar,
+    /// Signalled when a job leaves the queue.
+    pub space: Condvar,
+    pub stats: Arc<Stats>,
+}
+
+impl Shared {
+    fn full(&self, state: &State) -> bool {
+        state.threads.len() >= self.options.max
+            && state.queue.len() >= state.idle.saturating_add(self.options.queue)
+    }
+}
+
+/// Runs jobs on worker threads, between `min` and `max` of them depending on the load.
+/// A job that panics only takes its own worker down, which is replaced straight away.
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<Sender<Job>>,
+    shared: Arc<Shared>,
 }
 
 impl ThreadPool {
-    pub fn new(n: usize) -> Self {
-        let (tx, rx) = mpsc::channel();
-        let rx = Arc::new(Mutex::new(rx));
+    pub fn new(options: Options) -> Self {
+        let options = Options {
+            max: options.max.max(1),
+            min: options.min.min(options.max.max(1)),
+            ..options
+        };
 
-        Self {
-            workers: (0..n).map(|id| Worker::new(id, Arc::clone(&rx))).collect(),
-            sender: Some(tx),
+        let shared = Arc::new(Shared {
+            options,
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                idle: 0,
+                shutdown: false,
+                next_id: 0,
+                threads: BTreeMap::new(),
+            }),
+            work: Condvar::new(),
+            space: Condvar::new(),
+            stats: Arc::default(),
+        });
+
+        let mut state = lock(&shared.state);
+        for _ in 0..options.min {
+            worker::spawn(&shared, &mut state);
         }
+
+        drop(state);
+        Self { shared }
+    }
+
+    pub fn stats(&self) -> Arc<Stats> {
+        Arc::clone(&self.shared.stats)
     }
 
-    pub fn execute<F>(&self, f: F)
+    /// Queues `f`, unless the pool is full and its policy is to reject jobs.
+    pub fn execute<F>(&self, f: F) -> Result<(), Rejected<F>>
     where
         F: FnOnce() + Send + 'static,
     {
-        self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+        let shared = &self.shared;
+        let mut state = lock(&shared.state);
+        while shared.full(&state) {
+            if shared.options.policy == Policy::Reject {
+                shared.stats.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(Rejected(f));
+            }
+
+            state = shared.space.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+
+        state.queue.push_back(Box::new(f));
+        shared.stats.queued.fetch_add(1, Ordering::Relaxed);
+        if state.idle < state.queue.len() && state.threads.len() < shared.options.max {
+            worker::spawn(shared, &mut state);
+        }
+
+        shared.work.notify_one();
+        Ok(())
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
+        let threads: Vec<_> = {
+            let mut state = lock(&self.shared.state);
+            state.shutdown = true;
+            state
+                .threads
+                .values_mut()
+                .filter_map(Option::take)
+                .collect()
+        };
 
-        for worker in &mut self.workers {
-            worker.thread.take().map(|t| t.join());
+        self.shared.work.notify_all();
+        for thread in threads {
+            thread.join().ok();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Instant;
+
+    fn fixed(n: usize) -> Options {
+        options(n, n, usize::MAX, Policy::Wait)
+    }
+
+    fn options(min: usize, max: usize, queue: usize, policy: Policy) -> Options {
+        Options {
+            min,
+            max,
+            queue,
+            keep_alive: Duration::from_millis(50),
+            policy,
+        }
+    }
+
+    /// Polls `f` until it holds, for up to 2 seconds.
+    fn eventually(f: impl Fn() -> bool) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(2) {
+            if f() {
+                return true;
+            }
+
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        false
+    }
+
+    #[test]
+    fn runs_every_job() {
+        let (tx, rx) = mpsc::channel();
+        let pool = ThreadPool::new(fixed(3));
+        for k in 0..20 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(k).unwrap()).unwrap();
+        }
+
+        drop(pool);
+        let mut done: Vec<_> = rx.try_iter().collect();
+        done.sort();
+        assert_eq!((0..20).collect::<Vec<_>>(), done);
+    }
+
+    #[test]
+    fn replaces_panicked_workers() {
+        let pool = ThreadPool::new(fixed(1));
+        let stats = pool.stats();
+        pool.execute(|| panic!("job failed")).unwrap();
+        assert!(eventually(|| stats.panics() == 1));
+
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+        assert!(rx.recv_timeout(Duration::from_secs(2)).is_ok());
+
+        let workers = stats.workers();
+        assert_eq!(1, workers.len());
+        assert_eq!(Some(&1), workers.keys().next());
+    }
+
+    #[test]
+    fn rejects_when_full() {
+        let pool = ThreadPool::new(options(1, 1, 1, Policy::Reject));
+        let (release, wait) = mpsc::channel::<()>();
+        let wait = Arc::new(Mutex::new(wait));
+
+        for busy in 1..=2 {
+            let wait = Arc::clone(&wait);
+            pool.execute(move || lock(&wait).recv().unwrap_or_default())
+                .unwrap();
+            assert!(eventually(|| pool.stats().busy() == 1));
+            assert_eq!(busy - 1, pool.stats().queued());
+        }
+
+        // One job runs, one waits and the queue holds no more.
+        assert!(pool.execute(|| {}).is_err());
+        assert_eq!(1, pool.stats().rejected());
+
+        release.send(()).unwrap();
+        assert!(eventually(|| pool.stats().queued() == 0));
+        assert!(pool.execute(|| {}).is_ok());
+        drop(release);
+    }
+
+    #[test]
+    fn waits_when_full() {
+        let pool = Arc::new(ThreadPool::new(options(1, 1, 0, Policy::Wait)));
+        let (release, wait) = mpsc::channel::<()>();
+        pool.execute(move || wait.recv().unwrap_or_default())
+            .unwrap();
+        assert!(eventually(|| pool.stats().busy() == 1));
+
+        let (tx, rx) = mpsc::channel();
+        let waiting = Arc::clone(&pool);
+        thread::spawn(move || waiting.execute(move || tx.send(()).unwrap()).unwrap());
+
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        release.send(()).unwrap();
+        assert!(rx.recv_timeout(Duration::from_secs(2)).is_ok());
+    }
+
+    #[test]
+    fn grows_and_shrinks() {
+        let pool = ThreadPool::new(options(1, 4, 0, Policy::Reject));
+        let stats = pool.stats();
+        assert_eq!(1, stats.workers().len());
+
+        let (release, wait) = mpsc::channel::<()>();
+        let wait = Arc::new(Mutex::new(wait));
+        for busy in 1..=4 {
+            let wait = Arc::clone(&wait);
+            pool.execute(move || lock(&wait).recv().unwrap_or_default())
+                .unwrap();
+            assert!(eventually(|| stats.busy() == busy));
+        }
+
+        assert_eq!(4, stats.workers().len());
+        assert!(pool.execute(|| {}).is_err());
+
+        drop(release);
+        assert!(eventually(|| stats.workers().len() == 1));
+    }
+
+    #[test]
+    fn worker_stats() {
+        let pool = ThreadPool::new(fixed(1));
+        let stats = pool.stats();
+        for _ in 0..3 {
+            pool.execute(|| thread::sleep(Duration::from_millis(5)))
+                .unwrap();
+        }
+
+        assert!(eventually(|| stats.workers()[&0].jobs == 3));
+        let worker = &stats.workers()[&0];
+        assert!(!worker.busy);
+        assert!(worker.busy_time >= Duration::from_millis(15));
+    }
+}
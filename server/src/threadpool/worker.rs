@@ -1,26 +1,132 @@
-use std::sync::{mpsc::Receiver, Arc, Mutex};
-use std::thread::{self, JoinHandle};
+use super::pool::{Shared, State};
+use crate::server::lock;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub type Job = Box<dyn FnOnce() + Send + 'static>;
 
-pub struct Worker {
-    pub thread: Option<JoinHandle<()>>,
-    pub id: usize,
+/// What one worker did so far.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkerStats {
+    pub jobs: u64,
+    pub busy: bool,
+    pub busy_time: Duration,
+}
+
+/// Starts a worker, `state` being the pool's locked state.
+pub(super) fn spawn(shared: &Arc<Shared>, state: &mut State) {
+    let id = state.next_id;
+    state.next_id += 1;
+
+    let worker = Worker {
+        id,
+        shared: Arc::clone(shared),
+    };
+
+    let spawned = thread::Builder::new()
+        .name(format!("worker-{id}"))
+        .spawn(move || worker.run());
+
+    match spawned {
+        Ok(thread) => {
+            state.threads.insert(id, Some(thread));
+            lock(&shared.stats.workers).insert(id, WorkerStats::default());
+        }
+
+        Err(e) => error!(error = e.to_string(); "Failed to start worker {id}"),
+    }
+}
+
+struct Worker {
+    id: usize,
+    shared: Arc<Shared>,
 }
 
 impl Worker {
-    pub fn new(id: usize, receiver: Arc<Mutex<Receiver<Job>>>) -> Self {
-        let thread = thread::spawn(move || loop {
-            let Ok(job) = receiver.lock().unwrap().recv() else {
-                break;
-            };
+    /// The next job, or `None` once the worker should exit.
+    fn next(&self) -> Option<Job> {
+        let shared = &self.shared;
+        let mut state = lock(&shared.state);
+
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                shared.space.notify_one();
+                return Some(job);
+            }
+
+            if state.shutdown {
+                return None;
+            }
+
+            // A waiting worker makes room for one more job.
+            state.idle += 1;
+            shared.space.notify_one();
+            let (next, waited) = shared
+                .work
+                .wait_timeout(state, shared.options.keep_alive)
+                .unwrap_or_else(PoisonError::into_inner);
+            state = next;
+            state.idle -= 1;
+
+            let spare = state.threads.len() > shared.options.min;
+            if waited.timed_out() && state.queue.is_empty() && spare {
+                self.retire(&mut state);
+                return None;
+            }
+        }
+    }
+
+    fn run(self) {
+        let stats = &self.shared.stats;
 
+        while let Some(job) = self.next() {
+            stats.queued.fetch_sub(1, Ordering::Relaxed);
+            stats.busy.fetch_add(1, Ordering::Relaxed);
+            self.update(|worker| worker.busy = true);
+
+            let start = Instant::now();
             job();
-        });
 
-        Self {
-            thread: Some(thread),
-            id,
+            stats.busy.fetch_sub(1, Ordering::Relaxed);
+            self.update(|worker| {
+                worker.jobs += 1;
+                worker.busy = false;
+                worker.busy_time += start.elapsed();
+            });
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut WorkerStats)) {
+        if let Some(worker) = lock(&self.shared.stats.workers).get_mut(&self.id) {
+            f(worker);
+        }
+    }
+
+    /// Forgets the worker, which is about to exit.
+    fn retire(&self, state: &mut State) {
+        state.threads.remove(&self.id);
+        lock(&self.shared.stats.workers).remove(&self.id);
+    }
+}
+
+impl Drop for Worker {
+    /// Replaces the worker if a job panicked on it.
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+
+        let shared = &self.shared;
+        shared.stats.busy.fetch_sub(1, Ordering::Relaxed);
+        shared.stats.panics.fetch_add(1, Ordering::Relaxed);
+        error!("Worker {} panicked, replacing it", self.id);
+
+        let mut state = lock(&shared.state);
+        self.retire(&mut state);
+        if !state.shutdown {
+            spawn(shared, &mut state);
         }
     }
 }
@@ -0,0 +1,447 @@
+//! What carries requests and responses between the server and its clients, so games and
+//! handlers don't care whether a client speaks length-prefixed frames over TCP or TLS,
+//! WebSocket messages, lines of text, or lives in the server's own process.
+
+use crate::metrics;
+use crate::{line, websocket};
+use core::request::Request;
+use core::response::Response;
+use core::stream::Stream;
+use core::{read_bytes, write_str};
+use std::cell::Cell;
+use std::io;
+use std::net::{Ipv4Addr, Shutdown, SocketAddr, TcpListener};
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+#[cfg(unix)]
+use std::{fs, path::Path};
+
+/// Where clients connect.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Listens on the Unix socket at `path`, replacing the one a previous run left.
+    #[cfg(unix)]
+    pub fn unix(path: &Path) -> io::Result<Self> {
+        use std::os::unix::fs::FileTypeExt;
+        if fs::metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+
+        UnixListener::bind(path).map(Listener::Unix)
+    }
+
+    /// Every client that connects, for as long as the listener works.
+    pub fn incoming(&self) -> Box<dyn Iterator<Item = Stream> + '_> {
+        match self {
+            Listener::Tcp(listener) => Box::new(listener.incoming().flatten().map(Stream::Tcp)),
+            #[cfg(unix)]
+            Listener::Unix(listener) => Box::new(listener.incoming().flatten().map(Stream::Unix)),
+        }
+    }
+}
+
+/// How the clients of a listener frame their messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// JSON frames prefixed with their length, as [`write_str`] writes them.
+    Framed,
+    /// JSON in WebSocket text messages.
+    WebSocket,
+    /// Commands typed by people, answered in plain text.
+    Line,
+}
+
+impl Protocol {
+    /// Completes the protocol's handshake on `stream`, if it has one.
+    pub fn accept(self, stream: Stream) -> io::Result<Box<dyn Transport>> {
+        match self {
+            Protocol::Framed => Ok(Box::new(stream)),
+            Protocol::WebSocket => Ok(Box::new(websocket::Connection::accept(stream)?)),
+            Protocol::Line => Ok(Box::new(line::Connection::new(stream)?)),
+        }
+    }
+
+    /// Whether a client can understand an answer before any handshake.
+    pub fn handshake(self) -> bool {
+        self == Protocol::WebSocket
+    }
+}
+
+/// Where the responses meant for one client go.
+pub trait Sink: Send {
+    fn send(&mut self, res: &Response) -> io::Result<()>;
+
+    /// Hangs up on the client, its handler then sees the connection end.
+    fn close(&mut self) -> io::Result<()>;
+}
+
+/// One client's connection.
+pub trait Transport: Sink {
+    /// The next request, as sent. Frames too large to read fail with `InvalidData`.
+    fn recv(&mut self) -> io::Result<Vec<u8>>;
+
+    /// Another handle sending to the same client, for its game to keep.
+    fn sink(&self) -> io::Result<Box<dyn Sink>>;
+
+    fn peer_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Gives up on `recv` after `timeout` without hearing from the client.
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl Sink for Stream {
+    fn send(&mut self, res: &Response) -> io::Result<()> {
+        let json = metrics::encode(res)?;
+        write_str(self, &json)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.shutdown(Shutdown::Both)
+    }
+}
+
+impl Transport for Stream {
+    fn recv(&mut self) -> io::Result<Vec<u8>> {
+        read_bytes(self)
+    }
+
+    fn sink(&self) -> io::Result<Box<dyn Sink>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Stream::peer_addr(self)
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        Stream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Responses that may wait to be written to one client before it counts as gone.
+const OUTBOX_LEN: usize = 1024;
+
+/// What waits to be written to a client.
+enum Outbound {
+    Response(Response),
+    Close,
+}
+
+/// A sink writing on a thread of its own, so that whoever sends, often with a game
+/// locked, never waits on the client. A client whose writes fail, or that doesn't read
+/// fast enough to keep its outbox from filling up, is hung up on, and its handler then
+/// frees its seat.
+pub struct Outbox {
+    outbound: SyncSender<Outbound>,
+    /// Hangs up straight away when the outbox is full.
+    closer: Box<dyn Sink>,
+    stalled: Arc<AtomicBool>,
+}
+
+impl Outbox {
+    /// Writes through `sink`, hanging up through `closer`, another handle to the same
+    /// client.
+    pub fn spawn(mut sink: Box<dyn Sink>, closer: Box<dyn Sink>) -> io::Result<Self> {
+        let (outbound, queue) = mpsc::sync_channel(OUTBOX_LEN);
+        let stalled = Arc::new(AtomicBool::new(false));
+        let writer = stalled.clone();
+        thread::Builder::new().spawn(move || {
+            // Once the outbox is dropped, what's left in it is still written.
+            for out in queue {
+                if let Outbound::Response(res) = out {
+                    match sink.send(&res) {
+                        Ok(()) => continue,
+                        Err(e)
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) =>
+                        {
+                            writer.store(true, Ordering::Relaxed);
+                        }
+                        Err(_) => {}
+                    }
+                }
+
+                sink.close().ok();
+                break;
+            }
+        })?;
+
+        Ok(Self {
+            outbound,
+            closer,
+            stalled,
+        })
+    }
+
+    /// Set once the client stopped reading: its outbox filled up or a write to it timed
+    /// out. Still readable after the outbox is handed over to a game.
+    pub fn stalled(&self) -> Arc<AtomicBool> {
+        self.stalled.clone()
+    }
+
+    fn push(&mut self, out: Outbound) -> io::Result<()> {
+        match self.outbound.try_send(out) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(_)) => {
+                self.stalled.store(true, Ordering::Relaxed);
+                self.closer.close().ok();
+                Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    "The client doesn't read what it's sent",
+                ))
+            }
+            Err(TrySendError::Disconnected(_)) => Err(io::ErrorKind::BrokenPipe.into()),
+        }
+    }
+}
+
+impl Sink for Outbox {
+    fn send(&mut self, res: &Response) -> io::Result<()> {
+        self.push(Outbound::Response(res.clone()))
+    }
+
+    /// Hangs up once what's already in the outbox is written.
+    fn close(&mut self) -> io::Result<()> {
+        self.push(Outbound::Close)
+    }
+}
+
+/// What travels from a client to the server's end of an in-memory connection, `None`
+/// hanging up.
+type Inbound = Option<Vec<u8>>;
+
+/// The server's end of an in-memory connection, for clients living in the server's own
+/// process like bots, or in tests.
+pub struct Memory {
+    requests: Receiver<Inbound>,
+    sink: MemorySink,
+    timeout: Cell<Option<Duration>>,
+}
+
+/// The client's end of an in-memory connection.
+pub struct MemoryClient {
+    requests: Sender<Inbound>,
+    responses: Receiver<Response>,
+    timeout: Option<Duration>,
+}
+
+/// Both ends of a new in-memory connection, with no socket in between.
+pub fn memory() -> (Memory, MemoryClient) {
+    let (requests_tx, requests_rx) = mpsc::channel();
+    let (responses_tx, responses_rx) = mpsc::channel();
+    let server = Memory {
+        requests: requests_rx,
+        sink: MemorySink {
+            responses: responses_tx,
+            hangup: requests_tx.clone(),
+        },
+        timeout: Cell::new(None),
+    };
+
+    let client = MemoryClient {
+        requests: requests_tx,
+        responses: responses_rx,
+        timeout: None,
+    };
+
+    (server, client)
+}
+
+/// How a read on a channel failed, as a socket would have.
+fn recv_error(e: RecvTimeoutError) -> io::Error {
+    match e {
+        RecvTimeoutError::Timeout => io::ErrorKind::TimedOut.into(),
+        RecvTimeoutError::Disconnected => io::ErrorKind::UnexpectedEof.into(),
+    }
+}
+
+fn recv<T>(rx: &Receiver<T>, timeout: Option<Duration>) -> io::Result<T> {
+    match timeout {
+        Some(timeout) => rx.recv_timeout(timeout).map_err(recv_error),
+        None => rx
+            .recv()
+            .map_err(|_| recv_error(RecvTimeoutError::Disconnected)),
+    }
+}
+
+#[derive(Clone)]
+struct MemorySink {
+    responses: Sender<Response>,
+    hangup: Sender<Inbound>,
+}
+
+impl Sink for MemorySink {
+    fn send(&mut self, res: &Response) -> io::Result<()> {
+        self.responses
+            .send(res.clone())
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.hangup.send(None).ok();
+        Ok(())
+    }
+}
+
+impl Sink for Memory {
+    fn send(&mut self, res: &Response) -> io::Result<()> {
+        self.sink.send(res)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.sink.close()
+    }
+}
+
+impl Transport for Memory {
+    fn recv(&mut self) -> io::Result<Vec<u8>> {
+        recv(&self.requests, self.timeout.get())?.ok_or(io::ErrorKind::UnexpectedEof.into())
+    }
+
+    fn sink(&self) -> io::Result<Box<dyn Sink>> {
+        Ok(Box::new(self.sink.clone()))
+    }
+
+    /// In-process clients count as `127.0.0.1:0`, like those on Unix sockets.
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        Ok((Ipv4Addr::LOCALHOST, 0).into())
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.timeout.set(timeout);
+        Ok(())
+    }
+}
+
+impl MemoryClient {
+    pub fn send(&self, req: &Request) -> io::Result<()> {
+        let data = serde_json::to_vec(req)?;
+        self.requests
+            .send(Some(data))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    /// The next response, failing with `TimedOut` after the read timeout and with
+    /// `UnexpectedEof` once the server's end is gone.
+    pub fn recv(&self) -> io::Result<Response> {
+        recv(&self.responses, self.timeout)
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+}
+
+impl Drop for MemoryClient {
+    fn drop(&mut self) {
+        self.requests.send(None).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a response each time it's let through, telling what it wrote and when it
+    /// got closed. Writes time out once nobody can let them through anymore.
+    struct Gated {
+        gate: Receiver<()>,
+        written: Sender<Option<Response>>,
+    }
+
+    impl Sink for Gated {
+        fn send(&mut self, res: &Response) -> io::Result<()> {
+            self.gate
+                .recv()
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?;
+            self.written.send(Some(res.clone())).ok();
+            Ok(())
+        }
+
+        fn close(&mut self) -> io::Result<()> {
+            self.written.send(None).ok();
+            Ok(())
+        }
+    }
+
+    /// What a [`Gated`] sink wrote, `None` for closing.
+    type Writes = Receiver<Option<Response>>;
+
+    /// An outbox writing through a [`Gated`] sink, its gate, what it writes and what
+    /// its closer closes.
+    fn outbox() -> (Outbox, Sender<()>, Writes, Writes) {
+        let (gate, gated) = mpsc::channel();
+        let (written, writes) = mpsc::channel();
+        let (closed, closes) = mpsc::channel();
+        let sink = Gated {
+            gate: gated,
+            written,
+        };
+        let closer = Gated {
+            gate: mpsc::channel().1,
+            written: closed,
+        };
+
+        let outbox = Outbox::spawn(Box::new(sink), Box::new(closer)).unwrap();
+        (outbox, gate, writes, closes)
+    }
+
+    #[test]
+    fn writes_in_order_then_closes() {
+        let (mut outbox, gate, writes, closes) = outbox();
+        for _ in 0..2 {
+            gate.send(()).unwrap();
+        }
+
+        outbox.send(&Response::Pong(1)).unwrap();
+        outbox.send(&Response::Pong(2)).unwrap();
+        outbox.close().unwrap();
+        drop(outbox);
+
+        let writes: Vec<_> = writes
+            .iter()
+            .map(|res| res.map(|res| format!("{res:?}")))
+            .collect();
+        assert_eq!(
+            vec![Some("Pong(1)".into()), Some("Pong(2)".into()), None],
+            writes
+        );
+        assert!(closes.try_recv().is_err());
+    }
+
+    #[test]
+    fn hangs_up_on_stuck_clients() {
+        let (mut outbox, _gate, _writes, closes) = outbox();
+
+        // Nothing's ever written, yet sending doesn't wait until the outbox is full.
+        let sent = (0..2 * OUTBOX_LEN)
+            .take_while(|&n| outbox.send(&Response::Pong(n as u64)).is_ok())
+            .count();
+        assert!((OUTBOX_LEN..=OUTBOX_LEN + 1).contains(&sent), "{sent}");
+        assert!(closes.recv().unwrap().is_none());
+        assert!(outbox.stalled().load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn stalls_on_timed_out_writes() {
+        let (mut outbox, gate, writes, _closes) = outbox();
+        outbox.send(&Response::Pong(1)).unwrap();
+        drop(gate);
+
+        // A dropped gate times the write out, which the writer hangs up on.
+        assert!(writes.recv().unwrap().is_none());
+        assert!(outbox.stalled().load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,137 @@
+//! WebSocket clients, like browsers, sending the same JSON requests and getting the same
+//! responses as other clients, one per text message.
+
+use crate::metrics;
+use crate::server::lock;
+use crate::transport::{Sink, Transport};
+use core::response::Response;
+use core::stream::Stream;
+use core::MAX_FRAME_LEN;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tungstenite::protocol::{Role, WebSocketConfig};
+use tungstenite::{Error, Message, WebSocket};
+
+type Writer = Arc<Mutex<WebSocket<Stream>>>;
+
+fn io_error(e: Error) -> io::Error {
+    match e {
+        Error::Io(e) => e,
+        Error::ConnectionClosed | Error::AlreadyClosed => io::ErrorKind::UnexpectedEof.into(),
+        Error::Capacity(_) | Error::Protocol(_) | Error::Utf8(_) => {
+            io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+        }
+        e => io::Error::other(e.to_string()),
+    }
+}
+
+/// The stream messages are read from. What the reading side writes, like answers to
+/// pings, goes through the writing side so it can't end up in the middle of a response.
+struct ReadHalf {
+    stream: Stream,
+    writer: Writer,
+}
+
+impl Read for ReadHalf {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for ReadHalf {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(&self.writer).get_mut().write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(&self.writer).get_mut().flush()
+    }
+}
+
+/// A client connected over WebSocket. Reading and writing are split, so the game can
+/// send to the client while its handler waits for the next request.
+pub struct Connection {
+    reader: WebSocket<ReadHalf>,
+    writer: Writer,
+}
+
+impl Connection {
+    /// Completes the WebSocket handshake on `stream`.
+    pub fn accept(stream: Stream) -> io::Result<Self> {
+        let config = WebSocketConfig::default()
+            .max_message_size(Some(MAX_FRAME_LEN))
+            .max_frame_size(Some(MAX_FRAME_LEN));
+
+        let writer = WebSocket::from_raw_socket(stream.try_clone()?, Role::Server, Some(config));
+        let writer = Arc::new(Mutex::new(writer));
+        let half = ReadHalf {
+            stream,
+            writer: Arc::clone(&writer),
+        };
+
+        let reader = tungstenite::accept_with_config(half, Some(config))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        Ok(Self { reader, writer })
+    }
+}
+
+/// Sends `res` as a text message.
+fn send(writer: &Writer, res: &Response) -> io::Result<()> {
+    let json = metrics::encode(res)?;
+    lock(writer).send(Message::text(json)).map_err(io_error)
+}
+
+fn close(writer: &Writer) -> io::Result<()> {
+    lock(writer).get_ref().shutdown(Shutdown::Both)
+}
+
+struct WebSocketSink(Writer);
+
+impl Sink for WebSocketSink {
+    fn send(&mut self, res: &Response) -> io::Result<()> {
+        send(&self.0, res)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        close(&self.0)
+    }
+}
+
+impl Sink for Connection {
+    fn send(&mut self, res: &Response) -> io::Result<()> {
+        send(&self.writer, res)
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        close(&self.writer)
+    }
+}
+
+impl Transport for Connection {
+    fn recv(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            match self.reader.read().map_err(io_error)? {
+                Message::Text(text) => return Ok(text.as_bytes().to_vec()),
+                Message::Binary(data) => return Ok(data.to_vec()),
+                Message::Close(_) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                Message::Ping(_) | Message::Pong(_) | Message::Frame(_) => {}
+            }
+        }
+    }
+
+    fn sink(&self) -> io::Result<Box<dyn Sink>> {
+        Ok(Box::new(WebSocketSink(Arc::clone(&self.writer))))
+    }
+
+    fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.reader.get_ref().stream.peer_addr()
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.reader.get_ref().stream.set_read_timeout(timeout)
+    }
+}